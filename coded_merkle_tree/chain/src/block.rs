@@ -95,7 +95,8 @@ impl Block {
 		//Compute coded Merkle tree and hashes of the last layer from the transactions	
 		let (trans_size, root_hashes, tree) = block.coded_merkle_roots(header_size, RATE, codes.to_vec(), correct);
 		let mut new_header = header;// block.block_header.clone();
-        //new_header.num_symbols 
+		// decoders read transactions up to payload_len, not into the filler
+		new_header.payload_len = transactions.iter().map(|t| t.bytes().len()).sum::<usize>() as u32;
         
 		// Merkle root from transactions
 		// base unit is transaction
@@ -20,7 +20,7 @@ pub struct BlockHeader {
 	pub bits: Compact,
 	pub nonce: u32,
 	pub coded_merkle_roots_hashes: Vec<H256>,//hashes of the symbols on the top layer of coded Merkle tree
-    //pub delimitor: Vec<u32>, // number of bytes in transactions 
+	pub payload_len: u32, // bytes of transactions, the rest of the block is filler
 }
 
 impl BlockHeader {
@@ -241,7 +241,7 @@ impl fmt::Debug for BlockHeader {
 			.field("bits", &self.bits)
 			.field("nonce", &self.nonce)
 			.field("coded_merkle_roots_hashes", &self.coded_merkle_roots_hashes.len())
-            //.field("delimitor", &self.delimitor)
+			.field("payload_len", &self.payload_len)
 			.finish()
 	}
 }
@@ -273,7 +273,7 @@ mod tests {
 			bits: 5.into(),
 			nonce: 6,
 			coded_merkle_roots_hashes: vec![H256::default(); 8],
-            len: 0,
+			payload_len: 0,
 		};
 
 		let mut stream = Stream::default();
@@ -295,6 +295,7 @@ mod tests {
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+			0, 0, 0, 0,
 		].into();
 
 		assert_eq!(stream.out(), expected);
@@ -318,6 +319,7 @@ mod tests {
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+			0, 0, 0, 0,
 		];
 
 		let mut reader = Reader::new(&buffer);
@@ -330,7 +332,7 @@ mod tests {
 			bits: 5.into(),
 			nonce: 6,
 			coded_merkle_roots_hashes: vec![H256::default(); 8],
-            len: 0,
+			payload_len: 0,
 		};
 
 		assert_eq!(expected, reader.read().unwrap());
@@ -1,4 +1,4 @@
-use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE, HEADER_SIZE, TRANSACTION_SIZE};
+use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE, HEADER_SIZE};
 use std::cmp;
 use std::ops::BitXor;
 use {Symbols, SymbolBase, SymbolUp};
@@ -13,7 +13,7 @@ use std::thread;
 use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
 use bytes::Bytes;
 use {BlockHeader, Transaction};
-use ser::{deserialize, serialize};
+use ser::{deserialize, deserialize_iterator, serialize};
 
 // Symbols on the base layer can have different size as the upper layer
 // The value of symbol is empty before it is decoded
@@ -155,6 +155,30 @@ fn symbol_to_hash(symbols: &Vec<Symbol>) -> Vec<H256> {
 	previous_hashes
 }
 
+// transactions are serialized back to back in the systematic base symbols,
+// the filler past payload_len is not part of the block
+fn base_layer_transactions(base_decoder: &Decoder, payload_len: usize) -> Vec<Transaction> {
+    let mut bytes: Vec<u8> = vec![];
+    for symbol in base_decoder.symbol_values[0..base_decoder.k as usize].iter() {
+        if let Symbol::Base(s) = symbol {
+            bytes.extend_from_slice(&s[0..BASE_SYMBOL_SIZE]);
+        }
+    }
+    let payload_len = cmp::min(payload_len, bytes.len());
+    let mut transactions: Vec<Transaction> = vec![];
+    for transaction in deserialize_iterator(&bytes[..payload_len]) {
+        match transaction {
+            Ok(t) => transactions.push(t),
+            Err(e) => {
+                warn!("Malformed transaction in decoded block: {:?}", e);
+                break;
+            }
+        }
+    }
+    debug!("Coded Merkle tree successfully decoded {} transactions", transactions.len());
+    transactions
+}
+
 //return if a symbol is equal to zero or not (every byte equals to 0u8)
 fn symbol_equal_to_zero(symbol: Symbol) -> bool {
 	let mut flag = true;
@@ -429,29 +453,7 @@ impl TreeDecoder {
 				    hash_proof = self.hashes[(i-1) as usize].clone();
 				    continue;	
 				} else {
-                    let base_decoder = &self.decoders[i];
-                    let symbols = base_decoder.symbol_values[0..base_decoder.k as usize].to_vec();
-
-                    let mut bytes: Vec<u8> = vec![];                   
-                    for symbol in symbols.clone() {
-                        if let Symbol::Base(s) = symbol {
-                            bytes.extend_from_slice(&s[0..BASE_SYMBOL_SIZE]);
-                        }
-                    }
-
-                    let mut transactions_byte: Vec<Bytes> = vec![];                   
-                    let mut transactions: Vec<Transaction> = vec![];
-                    let num_trans = bytes.len() / TRANSACTION_SIZE as usize; //TODO magic
-                    for d in 0..(num_trans-1) {
-                        let l = d*TRANSACTION_SIZE as usize;
-                        let u = (d+1)*TRANSACTION_SIZE as usize;
-                        let b: Bytes = bytes[l..u].into();
-                        let t: Transaction = deserialize(&b as &[u8]).unwrap();
-                        transactions_byte.push(b);
-                        transactions.push(t);
-                    }
-                    println!("Coded Merkle tree successfully decoded {}.", transactions_byte.len());
-                    return Ok(transactions);
+                    return Ok(base_layer_transactions(&self.decoders[i], header.payload_len as usize));
 				}							
 			}
 
@@ -486,30 +488,7 @@ impl TreeDecoder {
 				                    decoded = finished;
 				                    break;
 				                } else { //base layer decoded
-                                    let base_decoder = &self.decoders[i];
-                                    let symbols = base_decoder.symbol_values[0..base_decoder.k as usize].to_vec();
-
-                                    let mut bytes: Vec<u8> = vec![];                   
-                                    for symbol in symbols.clone() {
-                                        if let Symbol::Base(s) = symbol {
-                                            bytes.extend_from_slice(&s[0..BASE_SYMBOL_SIZE]);
-                                        }
-                                    }
-
-                                    let mut transactions_byte: Vec<Bytes> = vec![];                   
-                                    let mut transactions: Vec<Transaction> = vec![];
-                                    let num_trans = bytes.len() / TRANSACTION_SIZE as usize; //TODO magic
-                                    for d in 0..(num_trans-1) {
-                                        let l = d*TRANSACTION_SIZE as usize;
-                                        let u = (d+1)*TRANSACTION_SIZE as usize;
-                                        let b: Bytes = bytes[l..u].into();
-                                        let t: Transaction = deserialize(&b as &[u8]).unwrap();
-                                        transactions_byte.push(b);
-                                        transactions.push(t);
-                                    }
-
-                                    println!("Coded Merkle tree successfully decoded {}.", transactions_byte.len());
-                                    return Ok(transactions);                   
+                                    return Ok(base_layer_transactions(&self.decoders[i], header.payload_len as usize));
 				                } 				                
 				            } else { //decoding for layer i needs to continue 
 				            	continue;
@@ -543,30 +522,7 @@ impl TreeDecoder {
 				if i > 0 {
 					continue;
 				} else {
-                    let base_decoder = &self.decoders[i];
-                    let symbols = base_decoder.symbol_values[0..base_decoder.k as usize].to_vec();
-
-                    let mut bytes: Vec<u8> = vec![];                   
-                    for symbol in symbols.clone() {
-                        if let Symbol::Base(s) = symbol {
-                            bytes.extend_from_slice(&s[0..BASE_SYMBOL_SIZE]);
-                        }
-                    }
-
-                    let mut transactions_byte: Vec<Bytes> = vec![];                   
-                    let mut transactions: Vec<Transaction> = vec![];
-                    let num_trans = bytes.len() / TRANSACTION_SIZE as usize; //TODO magic
-                    for d in 0..(num_trans-1) {
-                        let l = d*TRANSACTION_SIZE as usize;
-                        let u = (d+1)*TRANSACTION_SIZE as usize;
-                        let b: Bytes = bytes[l..u].into();
-                        let t: Transaction = deserialize(&b as &[u8]).unwrap();
-                        transactions_byte.push(b);
-                        transactions.push(t);
-                    }
-
-                    println!("Coded Merkle tree successfully decoded {}.", transactions_byte.len());
-                    return Ok(transactions);                   
+                    return Ok(base_layer_transactions(&self.decoders[i], header.payload_len as usize));
 				}
 			} 
 		}
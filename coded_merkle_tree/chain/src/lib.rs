@@ -7,6 +7,8 @@ extern crate serialization as ser;
 extern crate rand;
 
 extern crate serde;
+#[macro_use]
+extern crate log;
 
 #[macro_use]
 extern crate serialization_derive;
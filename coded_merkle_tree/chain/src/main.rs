@@ -143,7 +143,7 @@ fn main() {
 			bits: 5.into(),
 			nonce: 6u32,
 			coded_merkle_roots_hashes: vec![H256::default(); 8],
-            delimitor: vec![],
+			payload_len: 0,
 		};
 
 	let header_1 = header.clone(); //header for first test
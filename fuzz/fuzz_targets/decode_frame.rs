@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+use system_rust::network::message::{MessageLimits, decode_message};
+use system_rust::network::peer::{ReadContext, ReadResult};
+
+// feed raw socket bytes through the length-prefixed framing and the decoder
+fuzz_target!(|data: &[u8]| {
+    let limits = MessageLimits::default();
+    let mut reader = ReadContext::new(Cursor::new(data.to_vec()), limits.max_frame());
+    loop {
+        match reader.read() {
+            Ok(ReadResult::Continue) => continue,
+            Ok(ReadResult::Message(m)) => {
+                let _ = decode_message(&m, &limits);
+            },
+            Ok(ReadResult::EOF) | Err(_) => break,
+        }
+    }
+});
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use system_rust::network::message::{MessageLimits, decode_message};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_message(data, &MessageLimits::default());
+});
@@ -73,17 +73,19 @@ func hashToG1(curve CurveSystem, message []byte) Point {
 	return curve.GetG1().Mul(new(big.Int).SetBytes(buf))
 }
 
+// writes a fresh key to filename, or to stdout for "-"
 func Gen(curve CurveSystem, filename string) {
 	
 	//g2 := curve.GetG2()
 	sk, _, pk, _ := KeyGen(curve)
-	fmt.Println("sk,", sk)
-	fmt.Println("pk,", pk.ToAffineCoords())
 
 	pkc := pk.ToAffineCoords()
 	data := fmt.Sprintf("{\n\"sk\": \"%v\",\n\"pkx1\": \"%v\",\n\"pkx2\": \"%v\",\n\"pky1\": \"%v\",\n\"pky2\": \"%v\"\n}", sk, pkc[0], pkc[1], pkc[2], pkc[3]) 
 
-	
+	if filename == "-" {
+		_, _ = io.WriteString(os.Stdout, data)
+		return
+	}
 
 	file, _ := os.Create(filename)
 
@@ -92,11 +94,16 @@ func Gen(curve CurveSystem, filename string) {
     _, _ = io.WriteString(file, data)
 }
 
+// reads the key from keyfilename, or from stdin for "-"
 func SignBLS(curve CurveSystem, keyfilename string, msg string) {
-	keyfile, _ := os.Open(keyfilename)
-	defer keyfile.Close()
-
-	key, _ := ioutil.ReadAll(keyfile)
+	var key []byte
+	if keyfilename == "-" {
+		key, _ = ioutil.ReadAll(os.Stdin)
+	} else {
+		keyfile, _ := os.Open(keyfilename)
+		defer keyfile.Close()
+		key, _ = ioutil.ReadAll(keyfile)
+	}
 	msg_bytes, _ := hex.DecodeString(msg)
 	var account Account
 	json.Unmarshal(key, &account)
@@ -122,18 +129,61 @@ func StrToInt(str string) *big.Int {
 	return x
 }
 
+// checks a signature against a public key, hash is the keccak of the signed
+// message, the scalar hashToG1 multiplies G1 by
+func VerifyBLS(curve CurveSystem, hash string, sigx string, sigy string, pk []string) bool {
+	hash_bytes, err := hex.DecodeString(hash)
+	if err != nil || len(hash_bytes) != 32 {
+		return false
+	}
+	h := curve.GetG1().Mul(new(big.Int).SetBytes(hash_bytes))
+
+	sig, ok := curve.MakeG1Point([]*big.Int{StrToInt(sigx), StrToInt(sigy)}, true)
+	if !ok {
+		return false
+	}
+	coords := make([]*big.Int, 4)
+	for i := range pk {
+		coords[i] = StrToInt(pk[i])
+	}
+	g2pk, ok := curve.MakeG2Point(coords, true)
+	if !ok {
+		return false
+	}
+	p1, ok1 := curve.Pair(sig, curve.GetG2())
+	p2, ok2 := curve.Pair(h, g2pk)
+	return ok1 && ok2 && p1.Equals(p2)
+}
+
 func main() {
 	curve := CurveSystem(Altbn128)
 	//idPtr := flag.Int("nid", 1, "node id")
 	msgPtr := flag.String("msg", "", "msg to sign")
-	keyPtr := flag.String("key", "", "key file")
+	keyPtr := flag.String("key", "", "key file, - for stdin (stdout with -gen)")
+	genPtr := flag.Bool("gen", false, "generate a key instead of signing")
+	verifyPtr := flag.Bool("verify", false, "verify a signature instead of signing")
+	hashPtr := flag.String("hash", "", "keccak of the signed msg, with -verify")
+	sigxPtr := flag.String("sigx", "", "signature x, with -verify")
+	sigyPtr := flag.String("sigy", "", "signature y, with -verify")
+	pkx1Ptr := flag.String("pkx1", "", "public key x1, with -verify")
+	pkx2Ptr := flag.String("pkx2", "", "public key x2, with -verify")
+	pky1Ptr := flag.String("pky1", "", "public key y1, with -verify")
+	pky2Ptr := flag.String("pky2", "", "public key y2, with -verify")
 	
 
 
     flag.Parse()
     //filename := fmt.Sprintf("keyfile/node%d", *idPtr)
 
-    //Gen(curve, filename)
+    if *genPtr {
+    	Gen(curve, *keyPtr)
+    	return
+    }
+    if *verifyPtr {
+    	pk := []string{*pkx1Ptr, *pkx2Ptr, *pky1Ptr, *pky2Ptr}
+    	fmt.Println(VerifyBLS(curve, *hashPtr, *sigxPtr, *sigyPtr, pk))
+    	return
+    }
   	SignBLS(curve, *keyPtr, *msgPtr)
 
 
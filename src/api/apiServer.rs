@@ -1,10 +1,9 @@
 extern crate tiny_http;
 
 use super::{TxGenSignal};
-use super::mempool::mempool::{Mempool};
-use super::blockchain::blockchain::BlockChain;
+use super::blockchain::sidechain::SideChain;
 use super::db::blockDb::BlockDb;
-use super::contract::interface::{Message, Handle, Answer};
+use super::contract::interface::{Message, Handle, Retry, request_with_retry};
 use super::contract::interface::Response as ContractResponse;
 use crossbeam::channel::{self, Sender};
 use std::thread;
@@ -13,10 +12,13 @@ use url::Url;
 use std::net::{SocketAddr};
 use std::sync::{Arc, Mutex};
 use std::collections::{HashMap};
+use std::io::Read;
 use serde::{Serialize};
 use crate::network::message::{PeerHandle, ServerSignal};
 use super::network::message::Message as PerformerMessage;
 use super::experiment::snapshot::{PERFORMANCE_COUNTER};
+use super::cmtda::{Transaction, BLOCK_SIZE};
+use super::cmtda::H256 as CMTH256;
 use mio_extras::channel::Sender as MioSender;
 use web3::types::U256;
 
@@ -27,12 +29,12 @@ pub struct ApiServer {
 }
 
 pub struct RequestContext {
-    tx_control: Sender<TxGenSignal>,
-    mempool: Arc<Mutex<Mempool>>,
-    chain: Arc<Mutex<BlockChain>>,
+    tx_controls: HashMap<u64, Sender<TxGenSignal>>, // by sid
+    chains: HashMap<u64, SideChain>,
     block_db: Arc<Mutex<BlockDb>>,
     contract_channel: Sender<Handle>,
     server_control: MioSender<ServerSignal>,
+    addr: SocketAddr, // p2p
 }
 
 #[derive(Serialize)]
@@ -43,38 +45,91 @@ pub struct ApiResponse {
 
 macro_rules! respond_result {
     ( $req:expr, $success:expr, $message:expr ) => {{
+        respond_result!($req, $success, $message, 200)
+    }};
+    ( $req:expr, $success:expr, $message:expr, $status:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
         let api_result = ApiResponse {
             success: $success,
             message: $message.to_string(),
         };
         let response = Response::from_string(serde_json::to_string_pretty(&api_result).unwrap())
-            .with_header(content_type);
+            .with_header(content_type)
+            .with_status_code($status);
         $req.respond(response).unwrap();
     }};
 }
 
+// a submitted tx is hex of at most BLOCK_SIZE bytes, with room for a
+// trailing newline
+const MAX_TX_BODY: usize = 2 * BLOCK_SIZE as usize + 2;
+
+// sid query parameter, side chain 0 if absent
+macro_rules! query_sid {
+    ( $req:expr, $url:expr ) => {{
+        match $url.query_pairs().find(|(k, _)| k == "sid") {
+            Some((_, v)) => match v.parse::<u64>() {
+                Ok(sid) => sid,
+                Err(_) => {
+                    respond_result!($req, false, "sid needs to be numeric");
+                    return;
+                },
+            },
+            None => 0,
+        }
+    }};
+}
+
+// per side chain entry of a side chain served by this node
+macro_rules! get_served {
+    ( $req:expr, $map:expr, $sid:expr ) => {{
+        match $map.get(&$sid) {
+            Some(entry) => entry,
+            None => {
+                respond_result!($req, false, format!("side chain {} is not served", $sid));
+                return;
+            },
+        }
+    }};
+}
+// read of the contract actor, tried again while the rpc endpoint is flaky
+macro_rules! query_contract {
+    ( $req:expr, $rc:expr, $message:expr, $variant:ident ) => {{
+        match request_with_retry(&$rc.contract_channel, $message, &Retry::default()) {
+            Ok(ContractResponse::$variant(value)) => value,
+            Ok(_) => {
+                respond_result!($req, false, "contract query fails: invalid response type");
+                return;
+            },
+            Err(e) => {
+                respond_result!($req, false, format!("contract query fails {}", e));
+                return;
+            },
+        }
+    }};
+}
+
 
 
 impl ApiServer {
     pub fn start(socket: SocketAddr, 
-                 tx_control: Sender<TxGenSignal>, 
-                 mempool: Arc<Mutex<Mempool>>,
+                 tx_controls: HashMap<u64, Sender<TxGenSignal>>, 
+                 chains: HashMap<u64, SideChain>,
                  contract_channel: Sender<Handle>,
-                 chain: Arc<Mutex<BlockChain>>,
                  block_db: Arc<Mutex<BlockDb>>,
                  server_control: MioSender<ServerSignal>,
+                 p2p_addr: SocketAddr,
     ) {
         let server = Server::http(&socket).unwrap();
         let _handler = thread::spawn(move || {
-            for request in server.incoming_requests() {
+            for mut request in server.incoming_requests() {
                 let rc = RequestContext {
-                    tx_control: tx_control.clone(),
-                    mempool: mempool.clone(),
-                    chain: chain.clone(),
+                    tx_controls: tx_controls.clone(),
+                    chains: chains.clone(),
                     block_db: block_db.clone(),
                     contract_channel: contract_channel.clone(),
                     server_control: server_control.clone(),
+                    addr: p2p_addr,
                 };
                 // new thread per request
                 let _ = thread::spawn(move || {
@@ -114,11 +169,15 @@ impl ApiServer {
                                     return;
                                 },
                             };
-                            rc.tx_control.send(TxGenSignal::Start(s as u64));
+                            let sid = query_sid!(request, url);
+                            let tx_control = get_served!(request, rc.tx_controls, sid);
+                            tx_control.send(TxGenSignal::Start(s as u64));
                             respond_result!(request, true, "ok");
                         },
                         "/transaction-generator/stop" => {
-                            rc.tx_control.send(TxGenSignal::Stop);
+                            let sid = query_sid!(request, url);
+                            let tx_control = get_served!(request, rc.tx_controls, sid);
+                            tx_control.send(TxGenSignal::Stop);
                         },
                         "/transaction-generator/step" => {
                             let mut pairs: HashMap<_, _> = url.query_pairs().into_owned().collect();
@@ -136,22 +195,36 @@ impl ApiServer {
                                     return;
                                 },
                             };
-                            rc.tx_control.send(TxGenSignal::Step(step));
+                            let sid = query_sid!(request, url);
+                            let tx_control = get_served!(request, rc.tx_controls, sid);
+                            tx_control.send(TxGenSignal::Step(step));
                             respond_result!(request, true, "ok");
                         },
                         "/transaction-generator/simulate" => {
-                            rc.tx_control.send(TxGenSignal::Simulate);
+                            let sid = query_sid!(request, url);
+                            let tx_control = get_served!(request, rc.tx_controls, sid);
+                            tx_control.send(TxGenSignal::Simulate);
                             respond_result!(request, true, "ok");
                         },
                         "/blockchain/get-curr-state" => {
+                            let sid = query_sid!(request, url);
+                            let side_chain = get_served!(request, rc.chains, sid);
                             println!("before /blockchain/get-curr-state lock" );
-                            let chain = rc.chain.lock().expect("api get-curr-state");
+                            let chain = side_chain.chain.lock().expect("api get-curr-state");
                             println!("after /blockchain/get-curr-state lock" );
                             let state = chain.get_latest_state().expect("/blockchain/get-curr-state empty");
                             drop(chain);
                             respond_result!(request, true, format!("{:?}, {}", state.block_id, state.curr_hash.to_string()));
                             //respond_result!(request, true, format!("{:?}", state));
                         },
+                        "/blockchain/get-confirmed-state" => {
+                            let sid = query_sid!(request, url);
+                            let side_chain = get_served!(request, rc.chains, sid);
+                            let chain = side_chain.chain.lock().expect("api get-confirmed-state");
+                            let state = chain.get_final_state().expect("/blockchain/get-confirmed-state empty");
+                            drop(chain);
+                            respond_result!(request, true, format!("{:?}, {}", state.block_id, state.curr_hash.to_string()));
+                        },
                         "/block-db/get-stored-blocks" => {
                             let block_db = rc.block_db.lock().expect("api gets block db");
                             let num_blocks = block_db.get_num_blocks();
@@ -195,13 +268,135 @@ impl ApiServer {
                                     return;
                                 },
                             };
-                            let mut mempool = rc.mempool.lock().expect("api change mempool size");
+                            let sid = query_sid!(request, url);
+                            let side_chain = get_served!(request, rc.chains, sid);
+                            let mut mempool = side_chain.mempool.lock().expect("api change mempool size");
                             mempool.change_mempool_size(size);
                             drop(mempool);
                             respond_result!(request, true, format!("mempool size changed to {}", size));
                         },
+                        "/mempool/change-capacity" => {
+                            let mut pairs: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let bytes = match pairs.get("bytes") {
+                                Some(s) => s,
+                                None => {
+                                    respond_result!(request, false, "missing bytes");
+                                    return;
+                                },
+                            };
+                            let bytes = match bytes.parse::<usize>() {
+                                Ok(s) => s,
+                                Err(_) => {
+                                    respond_result!(request, false, "bytes need to be numeric");
+                                    return;
+                                },
+                            };
+                            let sid = query_sid!(request, url);
+                            let side_chain = get_served!(request, rc.chains, sid);
+                            let mut mempool = side_chain.mempool.lock().expect("api change mempool capacity");
+                            mempool.change_max_bytes(bytes);
+                            drop(mempool);
+                            respond_result!(request, true, format!("mempool capacity changed to {} bytes", bytes));
+                        },
+                        "/transaction/submit" => {
+                            let mut pairs: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            // the hex tx comes in the tx parameter or as the POST body
+                            let tx_hex = match pairs.remove("tx") {
+                                Some(s) => s,
+                                None => {
+                                    if request.body_length().map_or(false, |len| len > MAX_TX_BODY) {
+                                        respond_result!(request, false, "tx does not fit in a block", 413);
+                                        return;
+                                    }
+                                    // the length may be missing or lie, never read past the limit
+                                    let mut body = String::new();
+                                    let read = request.as_reader().take(MAX_TX_BODY as u64 + 1).read_to_string(&mut body);
+                                    if let Err(e) = read {
+                                        respond_result!(request, false, format!("unreadable body {:?}", e));
+                                        return;
+                                    }
+                                    if body.len() > MAX_TX_BODY {
+                                        respond_result!(request, false, "tx does not fit in a block", 413);
+                                        return;
+                                    }
+                                    if body.trim().is_empty() {
+                                        respond_result!(request, false, "missing tx");
+                                        return;
+                                    }
+                                    body.trim().to_string()
+                                },
+                            };
+                            let tx_bytes = match hex::decode(&tx_hex) {
+                                Ok(b) => b,
+                                Err(_) => {
+                                    respond_result!(request, false, "tx needs to be hex");
+                                    return;
+                                },
+                            };
+                            if tx_bytes.len() > BLOCK_SIZE as usize {
+                                respond_result!(request, false, format!("tx of {} bytes does not fit in a block", tx_bytes.len()));
+                                return;
+                            }
+                            let transaction: Transaction = match ser::deserialize(&tx_bytes as &[u8]) {
+                                Ok(t) => t,
+                                Err(e) => {
+                                    respond_result!(request, false, format!("malformed tx {:?}", e));
+                                    return;
+                                },
+                            };
+                            let sid = query_sid!(request, url);
+                            let side_chain = get_served!(request, rc.chains, sid);
+                            let hash = transaction.hash();
+                            match side_chain.next_proposer() {
+                                Some(proposer) if proposer != rc.addr => {
+                                    let message = PerformerMessage::SendTransaction(sid, tx_bytes);
+                                    rc.server_control.send(ServerSignal::ServerUnicast((proposer, message)));
+                                    let mut mempool = side_chain.mempool.lock().expect("api submit transaction");
+                                    mempool.record_forwarded(&hash, proposer);
+                                    drop(mempool);
+                                },
+                                _ => {
+                                    let mut mempool = side_chain.mempool.lock().expect("api submit transaction");
+                                    let result = mempool.insert(transaction);
+                                    drop(mempool);
+                                    if let Err(e) = result {
+                                        respond_result!(request, false, format!("tx {} rejected: {:?}", hash, e));
+                                        return;
+                                    }
+                                },
+                            }
+                            respond_result!(request, true, hash.to_string());
+                        },
+                        "/transaction/status" => {
+                            let mut pairs: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let hash = match pairs.get("hash") {
+                                Some(s) => s,
+                                None => {
+                                    respond_result!(request, false, "missing hash");
+                                    return;
+                                },
+                            };
+                            let hash = match hash.parse::<CMTH256>() {
+                                Ok(h) => h,
+                                Err(_) => {
+                                    respond_result!(request, false, "hash needs to be 32 bytes of hex");
+                                    return;
+                                },
+                            };
+                            let sid = query_sid!(request, url);
+                            let side_chain = get_served!(request, rc.chains, sid);
+                            let mempool = side_chain.mempool.lock().expect("api transaction status");
+                            let status = mempool.get_status(&hash);
+                            drop(mempool);
+                            match status {
+                                Some(status) => respond_result!(request, true, format!("{:?}", status)),
+                                None => respond_result!(request, false, "unknown tx"),
+                            }
+                        },
                         "/mempool/num-transaction" => {
-                            let mut mempool = rc.mempool.lock().expect("api change mempool size");
+                            let sid = query_sid!(request, url);
+                            let side_chain = get_served!(request, rc.chains, sid);
+                            let mut mempool = side_chain.mempool.lock().expect("api change mempool size");
                             let num = mempool.get_num_transaction();
                             drop(mempool);
                             respond_result!(request, true, &num.to_string());
@@ -215,148 +410,55 @@ impl ApiServer {
                                     return;
                                 },
                             };
-                            let a = hex::decode(hash).unwrap();
-                            let tx_hash: &[u8] = a.as_ref();
-                            let tx_hash = web3::types::H256::from_slice(tx_hash);
-
-                            let (answer_tx, answer_rx) = channel::bounded(1);
-                            let handle = Handle {
-                                message: Message::GetTxReceipt(tx_hash),
-                                answer_channel: Some(answer_tx),
-                            };
-                            rc.contract_channel.send(handle);
-                            let receipt = match answer_rx.recv() {
-                                Ok(answer) => {
-                                    match answer {
-                                        Answer::Success(response) => {
-                                            match response {
-                                                ContractResponse::TxReceipt(receipt) => receipt,
-                                                _ => {
-                                                    panic!("answer to GetScaleNodes: invalid response type");
-                                                },
-                                            }
-                                        },
-                                        Answer::Fail(reason) => {
-                                            respond_result!(request, false, format!("contract query fails {}", reason));
-                                            return;
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    respond_result!(request, false, format!("contract channel broken"));
+                            let a = match hex::decode(hash) {
+                                Ok(a) if a.len() == 32 => a,
+                                _ => {
+                                    respond_result!(request, false, "hash needs to be 32 bytes of hex");
                                     return;
                                 },
                             };
+                            let tx_hash: &[u8] = a.as_ref();
+                            let tx_hash = web3::types::H256::from_slice(tx_hash);
+
+                            let receipt = query_contract!(request, rc, Message::GetTxReceipt(tx_hash), TxReceipt);
                             respond_result!(request, true, format!("{:?}", receipt));
                         },
                         "/contract/reset-chain" => {
-                            info!("reset-chain");
+                            let sid = query_sid!(request, url);
+                            info!("reset-chain {}", sid);
                             let handle = Handle {
-                                message: Message::ResetChain(0),
+                                message: Message::ResetChain(sid as usize),
                                 answer_channel: None,
                             };
                             rc.contract_channel.send(handle);
                         }
                         "/contract/count-scale-nodes" => {
                             // USE CALLBACK
-                            let (answer_tx, answer_rx) = channel::bounded(1);
-                            let handle = Handle {
-                                message: Message::CountScaleNodes,
-                                answer_channel: Some(answer_tx),
-                            };
-                            rc.contract_channel.send(handle);
-                            let num_node = match answer_rx.recv() {
-                                Ok(answer) => {
-                                    match answer {
-                                        Answer::Success(response) => {
-                                            match response {
-                                                ContractResponse::CountScaleNode(num_scale_node) => num_scale_node,
-                                                _ => {
-                                                    panic!("answer to NumScaleNode: invalid response type");
-                                                },
-                                            }
-                                        },
-                                        Answer::Fail(reason) => {
-                                            respond_result!(request, false, format!("contract query fails {}", reason));
-                                            return;
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    respond_result!(request, false, format!("contract channel broken"));
-                                    return;
-                                },
-                            };
+                            let num_node = query_contract!(request, rc, Message::CountScaleNodes, CountScaleNode);
                             respond_result!(request, true, format!("{}", num_node));
                         },
                         "/contract/get-curr-state" => {
-                            let (answer_tx, answer_rx) = channel::bounded(1);
-                            let handle = Handle {
-                                message: Message::GetCurrState(0),
-                                answer_channel: Some(answer_tx),
-                            };
-                            rc.contract_channel.send(handle);
-                            let curr_state = match answer_rx.recv() {
-                                Ok(answer) => {
-                                    match answer {
-                                        Answer::Success(response) => {
-                                            match response {
-                                                ContractResponse::GetCurrState(curr_state) => curr_state,
-                                                _ => {
-                                                    panic!("answer to GetCurrState: invalid response type");
-                                                },
-                                            }
-                                        },
-                                        Answer::Fail(reason) => {
-                                            respond_result!(request, false, format!("contract query fails {}", reason));
-                                            return;
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    respond_result!(request, false, format!("contract channel broken"));
-                                    return;
-                                },
-                            };
+                            let sid = query_sid!(request, url);
+                            let curr_state = query_contract!(request, rc, Message::GetCurrState(sid as usize), GetCurrState);
                             respond_result!(request, true, format!("{:?}, {:?}", curr_state.block_id, curr_state.curr_hash));
                         },
                         "/contract/get-scale-nodes" => {
-                            let (answer_tx, answer_rx) = channel::bounded(1);
-                            let handle = Handle {
-                                message: Message::GetScaleNodes,
-                                answer_channel: Some(answer_tx),
-                            };
-                            rc.contract_channel.send(handle);
-                            let scale_nodes = match answer_rx.recv() {
-                                Ok(answer) => {
-                                    match answer {
-                                        Answer::Success(response) => {
-                                            match response {
-                                                ContractResponse::ScaleNodesList(scale_nodes) => scale_nodes,
-                                                _ => {
-                                                    panic!("answer to GetScaleNodes: invalid response type");
-                                                },
-                                            }
-                                        },
-                                        Answer::Fail(reason) => {
-                                            respond_result!(request, false, format!("contract query fails {}", reason));
-                                            return;
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    respond_result!(request, false, format!("contract channel broken"));
-                                    return;
-                                },
-                            };
+                            let scale_nodes = query_contract!(request, rc, Message::GetScaleNodes, ScaleNodesList);
                             respond_result!(request, true, format!("{:?}", scale_nodes));
                         },
                         "/contract/add-scale-node" => {
                             let mut pairs: HashMap<_, _> = url.query_pairs().into_owned().collect();
-                            let id = match pairs.get("id") {
+                            let account = match pairs.get("account") {
                                 Some(s) => s,
                                 None => {
-                                    respond_result!(request, false, "missing id");
+                                    respond_result!(request, false, "missing account");
+                                    return;
+                                },
+                            };
+                            let keyfile = match pairs.get("keyfile") {
+                                Some(s) => s,
+                                None => {
+                                    respond_result!(request, false, "missing keyfile");
                                     return;
                                 },
                             };
@@ -368,13 +470,12 @@ impl ApiServer {
                                 },
                             };
                             let (answer_tx, answer_rx) = channel::bounded(1);
-                            //let id = id.parse().unwrap();
                             let handle = Handle {
-                                message: Message::AddScaleNode(id.clone(), ip.clone()),
+                                message: Message::AddScaleNode(account.clone(), keyfile.clone(), ip.clone()),
                                 answer_channel: Some(answer_tx),
                             };
                             rc.contract_channel.send(handle);
-                            let reply = Response::from_string(format!("Add scaleNode {}", id));
+                            let reply = Response::from_string(format!("Add scaleNode {}", account));
                             request.respond(reply);
                         },
                         "/contract/submit-vote" => {
@@ -402,65 +503,17 @@ impl ApiServer {
                             rc.contract_channel.send(handle);
                         },
                         "/contract/sync-chain" => {
-                            let (answer_tx, answer_rx) = channel::bounded(1);
-                            let handle = Handle {
-                                message: Message::SyncChain,
-                                answer_channel: Some(answer_tx),
-                            };
-                            rc.contract_channel.send(handle);
-                            let chain_len = match answer_rx.recv() {
-                                Ok(answer) => {
-                                    match answer {
-                                        Answer::Success(response) => {
-                                            match response {
-                                                ContractResponse::SyncChain(chain_len) => chain_len,
-                                                _ => {
-                                                    panic!("answer to GetScaleNodes: invalid response type");
-                                                },
-                                            }
-                                        },
-                                        Answer::Fail(reason) => {
-                                            respond_result!(request, false, format!("contract query fails {}", reason));
-                                            return;
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    respond_result!(request, false, format!("contract channel broken"));
-                                    return;
-                                },
-                            };
+                            let chain_len = query_contract!(request, rc, Message::SyncChain, SyncChain);
                             respond_result!(request, true, format!("{:?}", chain_len));
                         },
                         "/contract/add-side-node" => {
-                            let (answer_tx, answer_rx) = channel::bounded(1);
-                            let handle = Handle {
-                                message: Message::AddSideNode(0),
-                                answer_channel: Some(answer_tx),
-                            };
-                            rc.contract_channel.send(handle);
-                            let chain_len = match answer_rx.recv() {
-                                Ok(answer) => {
-                                    match answer {
-                                        Answer::Success(response) => {
-                                            match response {
-                                                ContractResponse::SyncChain(chain_len) => chain_len,
-                                                _ => {
-                                                    panic!("answer to GetScaleNodes: invalid response type");
-                                                },
-                                            }
-                                        },
-                                        Answer::Fail(reason) => {
-                                            respond_result!(request, false, format!("contract query fails {}", reason));
-                                            return;
-                                        },
-                                    }
-                                },
-                                Err(e) => {
-                                    respond_result!(request, false, format!("contract channel broken"));
-                                    return;
-                                },
-                            };
+                            let sid = query_sid!(request, url);
+                            // a write, sent once
+                            let message = Message::AddSideNode(sid as usize);
+                            match super::contract::interface::request(&rc.contract_channel, message, Retry::default().timeout) {
+                                Ok(_) => respond_result!(request, true, "ok"),
+                                Err(e) => respond_result!(request, false, format!("contract write fails {}", e)),
+                            }
                         },
                         _ => {
                             println!("all other option {:?}", url.path());
@@ -8,5 +8,6 @@ use super::db;
 use super::blockchain;
 use super::contract;
 use super::network;
+use super::cmtda;
 
 
@@ -8,47 +8,92 @@ use super::experiment::snapshot::PERFORMANCE_COUNTER;
 
 pub struct BlockChain {
     blockchain: Vec<ContractState>,
+    // main chain block number and hash committing each state, None where
+    // unknown, e.g. genesis
+    main_blocks: Vec<Option<(u64, H256)>>,
+    confirmations: u64, // main chain blocks on top before a state is final
+    main_height: u64, // highest main chain block seen
 }
 
 impl BlockChain {
-    pub fn new() -> BlockChain {
+    pub fn new(confirmations: u64) -> BlockChain {
         let genesis = ContractState::default();
         //PERFORMANCE_COUNTER.record_chain_update();
         BlockChain {
             blockchain: vec![genesis],
+            main_blocks: vec![None],
+            confirmations: confirmations,
+            main_height: 0,
         } 
     }
 
     // input must be consistent with previous block
     pub fn insert(&mut self, contract_state: &ContractState) {
         self.blockchain.push(contract_state.clone());
+        self.main_blocks.push(None);
     }
 
     // TODO redundent to insert, remove insert later
     pub fn append(&mut self, eth_state: &ContractState) {
         self.blockchain.push(eth_state.clone());
+        self.main_blocks.push(None);
     }
 
-    pub fn update(&mut self, eth_state: &ContractState) {
-        let curr_state = self.blockchain.last();
-        let curr_state = curr_state.expect("blockchain:update is empty");
-        if eth_state.block_id == curr_state.block_id + 1 {
-            self.blockchain.push(eth_state.clone());
-        } else if eth_state.block_id > curr_state.block_id + 1 {
-            // local chain is missing blocks
-        } else if eth_state.block_id == curr_state.block_id {
-            println!("local chain already synced");
-        } else {
-            panic!("local chain screw up, it is greater than eth chain");
+    pub fn append_committed(&mut self, eth_state: &ContractState, main_block: (u64, H256)) {
+        self.blockchain.push(eth_state.clone());
+        self.main_blocks.push(Some(main_block));
+    }
+
+    // drop the states from block_id on, genesis stays. Returns their block ids
+    pub fn rollback(&mut self, block_id: u64) -> Vec<u64> {
+        let keep = self.blockchain.
+            iter().
+            position(|s| s.block_id >= block_id).
+            unwrap_or(self.blockchain.len()).
+            max(1);
+        self.main_blocks.truncate(keep);
+        self.blockchain.
+            drain(keep..).
+            map(|s| s.block_id).
+            collect()
+    }
+
+    // drop the states committed from the main chain block on, after the main
+    // chain replaced it. Returns the reverted block ids
+    pub fn rollback_main(&mut self, main_number: u64) -> Vec<u64> {
+        let first = self.main_blocks.
+            iter().
+            zip(self.blockchain.iter()).
+            find(|(m, _)| m.map_or(false, |(number, _)| number >= main_number)).
+            map(|(_, s)| s.block_id);
+        match first {
+            Some(block_id) => self.rollback(block_id),
+            None => vec![],
         }
     }
 
-    // block_id itself is changed
-    pub fn revise(&mut self, block_id: usize, states: Vec<ContractState>) {
+    // the latest state no reorg of the main chain at main_height can revert,
+    // states of unknown main chain blocks count as final
+    pub fn get_confirmed_state(&self, main_height: u64) -> Option<ContractState> {
+        self.blockchain.
+            iter().
+            zip(self.main_blocks.iter()).
+            take_while(|(_, m)| m.map_or(true, |(number, _)| number + self.confirmations <= main_height + 1)).
+            last().
+            map(|(s, _)| s.clone())
+    }
+
+    pub fn observe_main_height(&mut self, main_height: u64) {
+        self.main_height = self.main_height.max(main_height);
+    }
 
+    // confirmed at the highest main chain block seen so far
+    pub fn get_final_state(&self) -> Option<ContractState> {
+        self.get_confirmed_state(self.main_height)
     }
 
     pub fn replace(&mut self, chain: Vec<ContractState>) {
+        self.main_blocks = vec![None; chain.len()];
         self.blockchain = chain;
         //PERFORMANCE_COUNTER.store_chain_depth(self.blockchain.len());
     }
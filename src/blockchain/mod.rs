@@ -1,10 +1,13 @@
 use super::primitive::{self, hash, block};
 use super::contract;
 use super::experiment;
+use super::mempool;
+use super::network;
 
 
 pub mod blockchain;
 pub mod fork;
+pub mod sidechain;
 
 pub const GENESIS: hash::H256 = hash::H256([0 as u8;32]);
 
@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::collections::HashMap;
+use web3::types::Address;
+use crossbeam::channel::Sender;
+use super::blockchain::{BlockChain};
+use super::mempool::mempool::{Mempool};
+use super::mempool::scheduler;
+use super::mempool::clock::{SlotClock};
+use super::network::message::Samples;
+
+// per side chain state of a node, a node serves every side chain it is
+// configured with and looks them up by sid
+#[derive(Clone)]
+pub struct SideChain {
+    pub sid: u64,
+    pub chain: Arc<Mutex<BlockChain>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    pub sidenodes: Vec<SocketAddr>, // proposer ring in slot order
+    pub accounts: HashMap<SocketAddr, Address>, // registered account of each side node
+    pub scheduler_handler: Sender<scheduler::Signal>,
+    pub manager_source: Sender<(u64, Option<Samples>)>,
+    pub clock: Arc<dyn SlotClock>, // slots of the proposer ring
+}
+
+impl SideChain {
+    pub fn num_side(&self) -> u64 {
+        self.sidenodes.len() as u64
+    }
+
+    // only side nodes in the ring run a scheduler
+    pub fn is_proposer(&self, addr: &SocketAddr) -> bool {
+        self.sidenodes.contains(addr)
+    }
+
+    // proposals and token hand-offs are signed by the account the side
+    // node registered with
+    pub fn is_account_of(&self, addr: &SocketAddr, account: &Address) -> bool {
+        self.accounts.get(addr) == Some(account)
+    }
+
+    // side node owning the next slot, where new transactions are sent
+    pub fn next_proposer(&self) -> Option<SocketAddr> {
+        if self.sidenodes.is_empty() {
+            return None;
+        }
+        let next_slot = self.clock.curr_slot() + 1;
+        Some(self.sidenodes[(next_slot % self.num_side()) as usize])
+    }
+}
@@ -5,8 +5,13 @@ use super::network::message::Message as ServerMessage;
 use super::mempool::mempool::{Mempool};
 use super::blockchain::blockchain::{BlockChain};
 use super::db::blockDb::{BlockDb};
-use super::interface::{Handle, Message, Response, Answer};
+use super::interface::{Handle, Message, Response, Answer, ScaleNodeInfo, Error};
 use super::utils::*;
+use super::txManager::{TxManager, TxConfig, TxRequest};
+use super::gas::{GasConfig};
+use super::events::{EventSource, EventConfig};
+use super::keystore;
+use super::voteBatcher::{VoteBatcher, BatchConfig, VoteCall, VOTE_GAS};
 
 use web3::contract::Contract as EthContract;
 use web3::contract::Options as EthOption;
@@ -19,12 +24,11 @@ use crypto::sha2::Sha256;
 use std::sync::{Arc, Mutex};
 use std::{time};
 use std::fs::{self, File, OpenOptions};
-use std::io::{Write, BufReader, BufRead, Error};
+use std::io::{Write, BufReader, BufRead};
 
 use crossbeam::channel::{self, Sender, Receiver};
 use mio_extras::channel as Mio_channel;
 use serde::{Serialize, Deserialize};
-use ethereum_tx_sign::RawTransaction;
 
 use mio_extras::channel::Sender as MioSender;
 use crate::experiment::snapshot::PERFORMANCE_COUNTER;
@@ -33,8 +37,8 @@ use crate::experiment::snapshot::PERFORMANCE_COUNTER;
 use log::{info, warn, error};
 use std::time::{SystemTime, UNIX_EPOCH, Duration};
 
-const ETH_CHAIN_ID: u32 = 42;
-
+// token ring slots read per side chain, deleted side nodes leave holes
+const MAX_SIDE_NODES: usize = 64;
 
 pub struct Contract {
     contract: EthContract<web3::transports::Http>,
@@ -48,7 +52,9 @@ pub struct Contract {
     ip_addr: String,
     rpc_url: String,
     contract_address: Address,
-    receipt_tx: Sender<(web3::types::H256, usize)>,
+    writes: Sender<TxRequest>,
+    votes: Option<Sender<VoteCall>>, // batched votes, each on its own if None
+    event_config: EventConfig,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -70,6 +76,10 @@ impl Contract {
         abi_path: String,
         rpc_url: &str,
         contract_address: &Address,
+        gas_config: GasConfig,
+        tx_config: TxConfig,
+        event_config: EventConfig,
+        batch_config: BatchConfig,
     ) -> Contract {
         let (eloop, http) = web3::transports::Http::new(rpc_url).unwrap();
         eloop.into_remote();
@@ -80,7 +90,18 @@ impl Contract {
             contract_address.clone(), 
             &json_bytes.clone()
             ).unwrap();
-        let (tx, rx) = channel::unbounded();
+        let writes = TxManager::new(
+            rpc_url,
+            account.clone(),
+            contract_address.clone(),
+            gas_config,
+            tx_config,
+        ).start();
+        let votes = if batch_config.max_votes > 1 {
+            Some(VoteBatcher::new(writes.clone(), batch_config).start())
+        } else {
+            None
+        };
         let contract = Contract{
             contract,
             key,
@@ -93,7 +114,9 @@ impl Contract {
             ip_addr,
             rpc_url: rpc_url.to_string(),
             contract_address: contract_address.clone(),
-            receipt_tx: tx,
+            writes,
+            votes,
+            event_config,
         };
         return contract;
     }
@@ -102,6 +125,7 @@ impl Contract {
         account: &Account,
         rpc_url: &str,
         contract_address: &Address,
+        gas_config: GasConfig,
     ) -> Contract {
         let (eloop, http) = web3::transports::Http::new(rpc_url).unwrap();
         eloop.into_remote();
@@ -116,16 +140,15 @@ impl Contract {
         let (server_control_sender, server_control_receiver) = Mio_channel::channel();
         let (contract_handle_sender, contract_handle_receiver) = channel::unbounded();
         let ip_addr = "127.0.0.1".to_owned();
-        let key_path = "./keyfile/node0";
-        let key_file = File::open(key_path).unwrap();
-        let key_str: BLSKeyStr = match serde_json::from_reader(key_file) {
-            Ok(k) => k,
-            Err(e) => {
-                panic!("unable to deser keyfile");
-            }
-        };
-        let key: BLSKey = BLSKey::new(key_str);
-        let (tx, rx) = channel::unbounded();
+        // the cli registers nodes and reads state, it never signs votes
+        let key = BLSKey::default();
+        let writes = TxManager::new(
+            rpc_url,
+            account.clone(),
+            contract_address.clone(),
+            gas_config,
+            TxConfig::default(),
+        ).start();
         let contract = Contract{
             contract,
             key,
@@ -138,46 +161,14 @@ impl Contract {
             ip_addr,
             rpc_url: rpc_url.to_owned(),
             contract_address: contract_address.clone(),
-            receipt_tx: tx,
+            writes,
+            votes: None,
+            event_config: EventConfig::default(),
         };
         return contract;
     }
 
     pub fn start(mut self) {
-        let (tx, rx) = channel::unbounded();
-        self.receipt_tx = tx;
-        let rpc_url = self.rpc_url.clone();
-        // measurement only
-        let _ = std::thread::spawn(move || {
-            let (eloop, http) = web3::transports::Http::new(&rpc_url).unwrap();
-            eloop.into_remote();
-            let web3 = web3::api::Web3::new(http);
-            loop {
-                match rx.recv() {
-                    Ok((tx_hash, block_id)) => {
-                        let start = SystemTime::now();
-                        //PERFORMANCE_COUNTER.record_submit_block_update(block_id as u64);
-                        match web3.eth()
-                            .transaction_receipt(tx_hash)
-                            .wait() 
-                        {
-                            Ok(t) => {
-                                PERFORMANCE_COUNTER.record_submit_block_stop(block_id);
-                                PERFORMANCE_COUNTER.record_gas_update(gas);
-                           
-                                info!("get receipt {} takes {:?}", block_id, start.elapsed());
-                            },
-                            Err(_) => (),
-                        }
-                    },
-                    Err(e) => {
-                        info!("receipt recerver error {:?}", e);
-                    }
-                }
-            }
-        });
-
-
         let _ = std::thread::spawn(move || {
             loop {
                 match self.contract_handle.recv() {
@@ -185,10 +176,10 @@ impl Contract {
                         Ok(handle) => {
                             match handle.message {
                                 Message::SendBlock(block) => {
-                                    self.send_block(block);
+                                    self.send_block(block, handle.answer_channel);
                                 },
                                 Message::SubmitVote(header, sid, bid, sigx, sigy, bitset) => {
-                                    self.submit_vote(header, sid, bid, sigx, sigy, bitset);
+                                    self.submit_vote(header, sid, bid, sigx, sigy, bitset, handle.answer_channel);
                                    // let header = _generate_random_header();
                                    // let (sigx, sigy) = _sign_bls(header.clone(), "node1".to_string());
                                    // let (sigx2, sigy2) = _sign_bls(header.clone(), "node2".to_string());
@@ -197,13 +188,21 @@ impl Contract {
                                  //   let (sigx, sigy) = _aggregate_sig(sigx, sigy, sigx3, sigy3);
                                   //  self.submit_vote(header, U256::from_dec_str(sigx.as_ref()).unwrap(), U256::from_dec_str(sigy.as_ref()).unwrap(), U256::from(26))
                                 },
-                                Message::AddScaleNode(id, ip) => {
-                                    let file = File::open(format!("accounts/account{}", id)).unwrap();
-                                    let key_file = File::open(format!("keyfile/node{}", id)).unwrap();
-                                    let account: Account = serde_json::from_reader(file).expect("deser account");
-                                    let key_str: BLSKeyStr = serde_json::from_reader(key_file).expect("deser key file");
-                                    let key = BLSKey::new(key_str);
-                                    self.add_scale_node(account.address, ip, key.pkx1, key.pkx2, key.pky1, key.pky2);
+                                Message::AddScaleNode(account, keyfile, ip) => {
+                                    // public parts only, no passphrase needed
+                                    let registered = keystore::read_address(&account).and_then(|address| {
+                                        keystore::read_bls_public(&keyfile).map(|pk| (address, pk))
+                                    });
+                                    match registered {
+                                        Ok((address, (pkx1, pkx2, pky1, pky2))) => {
+                                            self.add_scale_node(address, ip, pkx1, pkx2, pky1, pky2, handle.answer_channel);
+                                        },
+                                        Err(e) => {
+                                            if let Some(ch) = handle.answer_channel {
+                                                ch.send(Answer::Fail(Error::Rejected(e)));
+                                            }
+                                        },
+                                    }
                                 },
                                 Message::CountScaleNodes => {
                                     self.count_scale_nodes(handle);
@@ -215,7 +214,7 @@ impl Contract {
                                     self.get_scale_nodes(handle);
                                 },
                                 Message::GetTxReceipt(tx_hash) => {
-                                    self.get_tx_receipt(tx_hash);
+                                    self.get_tx_receipt(handle, tx_hash);
                                 },
                                 Message::GetAll((_init_hash, start, end)) => {
                                     self.get_all(handle, start, end);
@@ -224,13 +223,24 @@ impl Contract {
                                     self.sync_etherchain(handle);
                                 },
                                 Message::EstimateGas(block) => {
-                                    self.estimate_gas(block);
+                                    if let Err(e) = self.estimate_gas(block) {
+                                        warn!("gas not estimated: {}", e);
+                                    }
                                 }
                                 Message::ResetChain(sid) => {
-                                    self.reset_chain(sid);
+                                    self.reset_chain(sid, handle.answer_channel);
                                 },
                                 Message::AddSideNode(sid) => {
                                     //self.add_side_node(sid);
+                                    if let Some(ch) = handle.answer_channel {
+                                        ch.send(Answer::Fail(Error::Rejected("add side node is not supported".to_string())));
+                                    }
+                                },
+                                Message::GetBlockNumber => {
+                                    self.get_block_number(handle);
+                                },
+                                Message::Subscribe(sid, block_id) => {
+                                    self.subscribe(handle, sid, block_id);
                                 },
                                 //...
                                 _ => {
@@ -239,7 +249,9 @@ impl Contract {
                             };
                         },
                         Err(e) => {
-                            panic!("contract query channel");
+                            // every handle is gone, nobody asks any more
+                            info!("contract query channel closed, contract stops");
+                            return;
                         }, 
                     //});
                 }
@@ -248,10 +260,36 @@ impl Contract {
     }
 
     pub fn get_curr_state(&self, handle: Handle, sid: usize) {
-        let curr_state = self._get_curr_state(sid);
-        let response = Response::GetCurrState(curr_state);
-        let answer = Answer::Success(response);
-        handle.answer_channel.unwrap().send(answer);
+        let answer = match self._get_curr_state(sid) {
+            Ok(curr_state) => Answer::Success(Response::GetCurrState(curr_state)),
+            Err(e) => Answer::Fail(e),
+        };
+        if let Some(ch) = handle.answer_channel {
+            ch.send(answer);
+        }
+    }
+
+    pub fn get_block_number(&self, handle: Handle) {
+        let answer = match self._get_block_number() {
+            Ok(number) => Answer::Success(Response::BlockNumber(number)),
+            Err(e) => Answer::Fail(e),
+        };
+        if let Some(ch) = handle.answer_channel {
+            ch.send(answer);
+        }
+    }
+
+    pub fn subscribe(&self, handle: Handle, sid: usize, block_id: u64) {
+        let events = EventSource::new(
+            self.contract.clone(),
+            self.web3.clone(),
+            sid as u64,
+            block_id,
+            self.event_config.clone(),
+        ).start();
+        if let Some(ch) = handle.answer_channel {
+            ch.send(Answer::Success(Response::Events(events)));
+        }
     }
 
     pub fn get_prev_blocks(&self, start: usize, end: usize) -> Vec<EthBlkTransaction> {
@@ -259,18 +297,16 @@ impl Contract {
     }
      
     pub fn get_scale_nodes(&self, handle: Handle) {
-        let n = self._count_scale_nodes();
-        let mut nodes = Vec::new();
-        for i in {0..n} {
-            let address = self._get_scale_node(i);
-            nodes.push(address);
-        }
-        info!("scale nodes list = {:?}", nodes);
-        let response = Response::ScaleNodesList(nodes);
-        let answer = Answer::Success(response);
+        let answer = match self._get_scale_nodes() {
+            Ok(nodes) => {
+                info!("scale nodes list = {:?}", nodes);
+                Answer::Success(Response::ScaleNodesList(nodes))
+            },
+            Err(e) => Answer::Fail(e),
+        };
         match handle.answer_channel.as_ref() {
-            Some(ch) => (*ch).send(answer).unwrap(),
-            None => panic!("contract get scale nodes list without answer channel"),
+            Some(ch) => { (*ch).send(answer); },
+            None => warn!("contract get scale nodes list without answer channel"),
         }
 
     }
@@ -280,204 +316,106 @@ impl Contract {
     }
 
     pub fn count_scale_nodes(&self, handle: Handle){
-        let num_scale_node = self._count_scale_nodes();
-        info!("count_scale_nodes = {:?}", num_scale_node);
-        let response = Response::CountScaleNode(num_scale_node);
-        let answer = Answer::Success(response);
+        let answer = match self._count_scale_nodes() {
+            Ok(num_scale_node) => {
+                info!("count_scale_nodes = {:?}", num_scale_node);
+                Answer::Success(Response::CountScaleNode(num_scale_node))
+            },
+            Err(e) => Answer::Fail(e),
+        };
         match handle.answer_channel.as_ref() {
-            Some(ch) => (*ch).send(answer).unwrap(),
-            None => panic!("contract count scale node without answer channel"),
+            Some(ch) => { (*ch).send(answer); },
+            None => warn!("contract count scale node without answer channel"),
         }
     }
 
-    pub fn add_scale_node(&self, address: Address, ip_addr: String, x1: U256, x2: U256, y1: U256, y2: U256) {
-        let nonce = self._transaction_count();
-        let function_abi = _encode_addScaleNode(address, ip_addr.clone() , x1, x2, y1, y2);
-        let gas = self._estimate_gas(function_abi.clone());
-
-        let tx = RawTransaction {
-            nonce: _convert_u256(nonce),
-            to: Some(ethereum_types::H160::from(self.contract_address.0)),
-            value: ethereum_types::U256::zero(),
-            gas_price: ethereum_types::U256::from(1000000000),
-            gas: _convert_u256(gas),
-            data: function_abi
-        };
-
-        let key = _get_key_as_H256(self.my_account.private_key.clone());
-        let signed_tx = tx.sign(&key, &ETH_CHAIN_ID);
-        match self._send_transaction(signed_tx) {
-            Ok(tx_hash) => {
-                if self.get_tx_receipt(tx_hash) {
-                    println!("tx_hash = {:?}", tx_hash);
-                }
-            },
-            Err(e) => {
-                info!("{:?} Error send_transaction {:?}", ip_addr, e);
-            }
-        }
-        
+    pub fn add_scale_node(&self, address: Address, ip_addr: String, x1: U256, x2: U256, y1: U256, y2: U256, answer_channel: Option<Sender<Answer>>) {
+        let function_abi = _encode_addScaleNode(address, ip_addr, x1, x2, y1, y2);
+        self.write("addScaleNode", function_abi, None, None, answer_channel);
     }
 
-    pub fn add_side_node(&self, sid: U256, address: Address, ip_addr: String) {
-        let nonce = self._transaction_count();
+    pub fn add_side_node(&self, sid: U256, address: Address, ip_addr: String, answer_channel: Option<Sender<Answer>>) {
         let function_abi = _encode_addSideNode(sid, address, ip_addr);
-        let gas = self._estimate_gas(function_abi.clone());
-
-        println!("{:?}", gas);
-
-        let tx = RawTransaction {
-            nonce: _convert_u256(nonce),
-            to: Some(ethereum_types::H160::from(self.contract_address.0)),
-            value: ethereum_types::U256::zero(),
-            gas_price: ethereum_types::U256::from(1000000000),
-            gas: _convert_u256(gas),
-            data: function_abi
-        };
-
-        let key = _get_key_as_H256(self.my_account.private_key.clone());
-        let signed_tx = tx.sign(&key, &ETH_CHAIN_ID);
-
-        match self._send_transaction(signed_tx) {
-            Ok(tx_hash) => {
-                if self.get_tx_receipt(tx_hash) {
-                    println!("tx_hash = {:?}", tx_hash);
-                }
-            },
-            Err(e) => {
-                info!("Error send_transaction {:?}", e);
-            }
-        }
-        
+        self.write("addSideNode", function_abi, None, None, answer_channel);
     }
 
-    pub fn delete_side_node(&self, sid: U256, tid: U256) {
-        let nonce = self._transaction_count();
+    pub fn delete_side_node(&self, sid: U256, tid: U256, answer_channel: Option<Sender<Answer>>) {
         let function_abi = _encode_deleteSideNode(sid, tid);
-        //let gas = self._estimate_gas(function_abi.clone());
-
-        //println!("{:?}", gas);
-
-        let tx = RawTransaction {
-            nonce: _convert_u256(nonce),
-            to: Some(ethereum_types::H160::from(self.contract_address.0)),
-            value: ethereum_types::U256::zero(),
-            gas_price: ethereum_types::U256::from(1000000000),
-            gas: ethereum_types::U256::from(750000),
-            data: function_abi
-        };
-
-        let key = _get_key_as_H256(self.my_account.private_key.clone());
-        let signed_tx = tx.sign(&key, &ETH_CHAIN_ID);
-        match self._send_transaction(signed_tx) {
-            Ok(tx_hash) => {
-                if self.get_tx_receipt(tx_hash) {
-                    println!("tx_hash = {:?}", tx_hash);
-                }
-            },
-            Err(e) => {
-                info!("Error send_transaction {:?}", e);
-            }
-        }
+        self.write("deleteSideNode", function_abi, Some(U256::from(750000)), None, answer_channel);
     }
 
-    pub fn submit_vote(&self, str_block: String, sid: U256, bid: U256, sigx: U256, sigy: U256, bitset: U256) {
-        let nonce = self._transaction_count();
-        let private_key = _get_key_as_vec(self.my_account.private_key.clone());
-        let function_abi = _encode_submitVote(str_block, sid, bid, sigx, sigy, bitset);
-       // let gas = self._estimate_gas(function_abi.clone());
-        //  println!("{:?}", gas);
-        let tx = RawTransaction {
-            nonce: _convert_u256(nonce),
-            to: Some(ethereum_types::H160::from(self.contract_address.0)),
-            value: ethereum_types::U256::zero(),
-            gas_price: ethereum_types::U256::from(1000000000)*20, //20 Gwei
-            gas: ethereum_types::U256::from(950000),
-            data: function_abi
-        };
-
-
-        let key = _get_key_as_H256(self.my_account.private_key.clone());
-        //info!("key {:?}", key);
-        let signed_tx = tx.sign(&key, &ETH_CHAIN_ID);
-        let block_id = bid.as_u64();
-        //info!("ETH_CHAIN_ID {} signed tx {:?}", ETH_CHAIN_ID, signed_tx);
-
-        //let tx_hash = self._send_transaction_ori(signed_tx);
-        //if self.get_tx_receipt(tx_hash) {
-            //println!("success tx_hash = {:?}", tx_hash);
-        //}
-        //
-        //PERFORMANCE_COUNTER.record_sign_block_stop(block_id as usize);
-        
-        let start = SystemTime::now();
-        match self._send_transaction(signed_tx) {
-            Ok(tx_hash) => {
-                //if self.get_tx_receipt(tx_hash) {
-                self.receipt_tx.send((tx_hash, bid.as_usize()));
-                //    println!("block id {}. tx_hash = {:?}", bid.as_usize(), tx_hash);
-                //}
-            },
-            Err(e) => {
-                info!("{:?} Error send_transaction {:?}", self.my_account.address , e);
+    pub fn submit_vote(&self, str_block: String, sid: U256, bid: U256, sigx: U256, sigy: U256, bitset: U256, answer_channel: Option<Sender<Answer>>) {
+        PERFORMANCE_COUNTER.record_chain_update();
+        if let Some(votes) = &self.votes {
+            let vote = VoteCall {
+                params: (str_block, sid, bid, sigx, sigy, bitset),
+                answer_channel: answer_channel,
+            };
+            if votes.send(vote).is_err() {
+                warn!("vote batcher stopped, vote of block {} dropped", bid);
             }
+            return;
         }
-        PERFORMANCE_COUNTER.record_chain_update();
+        let function_abi = _encode_submitVote(str_block, sid, bid, sigx, sigy, bitset);
+        self.write("submitVote", function_abi, Some(U256::from(VOTE_GAS)), Some(bid.as_usize()), answer_channel);
     }
 
-    pub fn send_block(&self, block: Block)  {
+    pub fn send_block(&self, block: Block, answer_channel: Option<Sender<Answer>>)  {
         let str_block= _block_to_str(block.clone());
-        let nonce = self._transaction_count();
-        let blk_id = self._get_blk_id(0);
+        let blk_id = match self._get_blk_id(0) {
+            Ok(blk_id) => blk_id,
+            Err(e) => {
+                warn!("send block fail: {}", e);
+                if let Some(ch) = answer_channel {
+                    ch.send(Answer::Fail(e));
+                }
+                return;
+            },
+        };
         let private_key = _get_key_as_vec(self.my_account.private_key.clone());
         let signature = _sign_block(str_block.as_str(), &private_key);
         let function_abi = _encode_sendBlock(str_block, signature, blk_id + 1);
 
-
-        let gas = self._estimate_gas(function_abi.clone());
-        let tx = RawTransaction {
-            nonce: _convert_u256(nonce),
-            to: Some(ethereum_types::H160::from(self.contract_address.0)),
-            value: ethereum_types::U256::zero(),
-            gas_price: ethereum_types::U256::from(1000000000) *20,
-            gas: _convert_u256(gas),
-            data: function_abi
+        // peers sync the block only once the contract state has it
+        let (answer_tx, answer_rx) = channel::bounded(1);
+        self.write("sendBlock", function_abi, None, None, Some(answer_tx));
+        let answer = match answer_rx.recv() {
+            Ok(answer) => answer,
+            Err(_) => Answer::Fail(Error::Stopped),
         };
-
-
-        let key = _get_key_as_H256(self.my_account.private_key.clone());
-        let signed_tx = tx.sign(&key, &ETH_CHAIN_ID);
-        match self._send_transaction(signed_tx) {
-            Ok(tx_hash) => {
-                if self.get_tx_receipt(tx_hash) {
-                    let curr_state = self._get_curr_state(0);
-                    // update local blockchain
-                    // broadcast to peers
+        match &answer {
+            // the block is in, a failed read only delays peers to their next sync
+            Answer::Success(_) => match self._get_curr_state(0) {
+                Ok(curr_state) => {
                     info!("broadcast to peer");
                     self.send_p2p(curr_state, block);
-                } else {
-                    warn!("get_tx_receipt fail");
-                }
+                },
+                Err(e) => warn!("block sent but not broadcast: {}", e),
             },
-            Err(e) => {
-                info!("Error send_transaction {:?}", e);
-            }
+            Answer::Fail(reason) => warn!("send block fail: {}", reason),
+        }
+        if let Some(ch) = answer_channel {
+            ch.send(answer);
         }
-
-        
     }
 
-    pub fn estimate_gas(&self, block: Block) -> U256 {
-        let mut file = OpenOptions::new().append(true).open("gas_history.csv").unwrap();
+    pub fn estimate_gas(&self, block: Block) -> Result<U256, Error> {
         let str_block= _block_to_str(block.clone());
-        let nonce = self._transaction_count();
-        let blk_id = self._get_blk_id(0);
+        let blk_id = self._get_blk_id(0)?;
         let private_key = _get_key_as_vec(self.my_account.private_key.clone());
         let signature = _sign_block(str_block.as_str(), &private_key);
         let function_abi = _encode_sendBlock(str_block, signature, blk_id + 1);
-        let gas = self._estimate_gas(function_abi.clone());
-        file.write_all(format!("{}\n ", gas).as_bytes());
-        return gas;
+        let gas = self._estimate_gas(function_abi.clone())?;
+        // the history is only for experiments, the estimate stands without it
+        let history = OpenOptions::new().
+            append(true).
+            create(true).
+            open("gas_history.csv").
+            and_then(|mut file| file.write_all(format!("{}\n ", gas).as_bytes()));
+        if let Err(e) = history {
+            warn!("unable to record gas estimate: {}", e);
+        }
+        return Ok(gas);
     }
 
     fn send_p2p(&self, curr_state: ContractState, block: Block) {
@@ -491,31 +429,37 @@ impl Contract {
         self.server_control_sender.send(p2p_message); 
     }
 
-    pub fn get_tx_receipt(&self, tx_hash: web3::types::H256) -> bool {
-        let now = time::Instant::now();
-        let mut receipt = self._transaction_receipt(tx_hash.clone());
-        while receipt.is_none() {
-            receipt = self._transaction_receipt(tx_hash.clone());
-            match receipt {
-                Some(ref r) => {
-                    match r.gas_used {
-                        Some(g) => {
-                            let gas = g.as_usize();
-                            PERFORMANCE_COUNTER.record_gas_update(gas);
-                            break;
-                        },
-                        None => {},
-                    }
-                },
-                None => {},
-            }
-            
-            if now.elapsed().as_secs() > 60 {
-                warn!("Transaction TimeOut");
-                return false;
-            }
+    pub fn get_tx_receipt(&self, handle: Handle, tx_hash: web3::types::H256) {
+        let answer = match self._transaction_receipt(tx_hash) {
+            Ok(Some(receipt)) => Answer::Success(Response::TxReceipt(receipt)),
+            Ok(None) => Answer::Fail(Error::Rejected(format!("no receipt for {:?}", tx_hash))),
+            Err(e) => Answer::Fail(e),
+        };
+        if let Some(ch) = handle.answer_channel {
+            ch.send(answer);
+        }
+    }
+
+    // queue a state changing call to the tx manager, which answers once the
+    // tx is final
+    fn write(
+        &self,
+        label: &'static str,
+        data: Vec<u8>,
+        gas: Option<U256>,
+        vote_of: Option<usize>,
+        answer_channel: Option<Sender<Answer>>,
+    ) {
+        let request = TxRequest {
+            label: label,
+            data: data,
+            gas: gas,
+            vote_of: vote_of,
+            answer_channel: answer_channel,
+        };
+        if self.writes.send(request).is_err() {
+            warn!("tx manager stopped, {} dropped", label);
         }
-        return true;
     }
 
     // pull function to get updated, return number of state change, 0 for no change 
@@ -535,22 +479,24 @@ impl Contract {
             }).collect();
 
         let response = Response::SyncChain(chain_len);
-        let answer = Answer::Success(response);
-        handle.answer_channel.unwrap().send(answer);
+        if let Some(ch) = handle.answer_channel {
+            ch.send(Answer::Success(response));
+        }
     }
 
     // [start, end)
     pub fn get_all(&self, handle: Handle, start: usize, end: usize) {
         let transactions = self._get_all([0 as u8; 32], 0, std::usize::MAX);
-        let req_transactions: Vec<EthBlkTransaction> = if end != 0 {
-            transactions[start..end].to_vec()  
-        } else {
-            transactions[start..].to_vec()
+        let end = if end != 0 { end } else { transactions.len() };
+        let answer = match transactions.get(start..end) {
+            Some(req_transactions) => Answer::Success(Response::GetAll(req_transactions.to_vec())),
+            None => Answer::Fail(Error::Rejected(
+                format!("range {}..{} out of {} transactions", start, end, transactions.len())
+            )),
         };
-
-        let response = Response::GetAll(req_transactions);
-        let answer = Answer::Success(response);
-        handle.answer_channel.unwrap().send(answer);
+        if let Some(ch) = handle.answer_channel {
+            ch.send(answer);
+        }
     }
 
     pub fn _get_all(&self, init_hash: [u8;32], start: usize, end: usize) -> (Vec<EthBlkTransaction>) {
@@ -628,124 +574,95 @@ impl Contract {
         return transactions;
     }
 
-    fn reset_side_node(&self, sid: usize)  {
-        let nonce = self._transaction_count();
+    fn reset_side_node(&self, sid: usize, answer_channel: Option<Sender<Answer>>)  {
         let function_abi = _encode_resetSideChain(U256::from(sid));
-        let gas = self._estimate_gas(function_abi.clone());
-
-        let tx = RawTransaction {
-            nonce: _convert_u256(nonce),
-            to: Some(ethereum_types::H160::from(self.contract_address.0)),
-            value: ethereum_types::U256::zero(),
-            gas_price: ethereum_types::U256::from(1000000000),
-            gas: _convert_u256(gas),
-            data: function_abi
-        };
-        let now = time::Instant::now();
-        let key = _get_key_as_H256(self.my_account.private_key.clone());
-        let signed_tx = tx.sign(&key, &ETH_CHAIN_ID);
-
-        match self._send_transaction(signed_tx) {
-            Ok(tx_hash) => {
-                if self.get_tx_receipt(tx_hash) {
-                    println!("tx_hash = {:?}", tx_hash);
-                }
-            },
-            Err(e) => {
-                info!("Error send_transaction {:?}", e);
-            }
-        }
+        self.write("resetSideChain", function_abi, None, None, answer_channel);
     }
 
-    pub fn reset_chain(&self, sid: usize)  {
-        let nonce = self._transaction_count();
+    pub fn reset_chain(&self, sid: usize, answer_channel: Option<Sender<Answer>>)  {
         let function_abi = _encode_resetSideChain(U256::from(sid));
-        let gas = self._estimate_gas(function_abi.clone());
-
-        //println!("{:?}", gas);
-
-        let tx = RawTransaction {
-            nonce: _convert_u256(nonce),
-            to: Some(ethereum_types::H160::from(self.contract_address.0)),
-            value: ethereum_types::U256::zero(),
-            gas_price: ethereum_types::U256::from(1000000000),
-            gas: _convert_u256(gas),
-            data: function_abi
-        };
-        let now = time::Instant::now();
-        let key = _get_key_as_H256(self.my_account.private_key.clone());
-        let signed_tx = tx.sign(&key, &ETH_CHAIN_ID);
-        match self._send_transaction(signed_tx) {
-            Ok(tx_hash) => {
-                if self.get_tx_receipt(tx_hash) {
-                    println!("tx_hash = {:?}", tx_hash);
-                }
-            },
-            Err(e) => {
-                info!("Error send_transaction {:?}", e);
-            }
-        }
-
-
-        
+        self.write("resetSideChain", function_abi, None, None, answer_channel);
     }
 
-    fn _get_blk_id(&self, sid: usize) -> U256 {
+    fn _get_blk_id(&self, sid: usize) -> Result<U256, Error> {
         self.contract
             .query("getBlockID", (web3::types::U256::from(sid),), None, EthOption::default(), None)
             .wait()
-            .unwrap()
+            .map_err(Error::from)
     }
 
-    fn _get_side_node_id(&self, sid: usize, addr: Address) -> U256 {
+    fn _get_side_node_id(&self, sid: usize, addr: Address) -> Result<U256, Error> {
         self.contract
             .query("getSideNodeID", (web3::types::U256::from(sid), addr), None, EthOption::default(), None)
             .wait()
-            .unwrap()
-    }
-
-    fn _transaction_count(&self) -> U256 {
-        self.web3.eth()
-            .transaction_count(self.my_account.address, None)
-            .wait()
-            .unwrap()
+            .map_err(Error::from)
     }
 
-    fn _get_curr_hash(&self, sid: usize) -> web3::types::H256 {
-        match self.contract
+    fn _get_curr_hash(&self, sid: usize) -> Result<web3::types::H256, Error> {
+        self.contract
             .query("getCurrentHash", (web3::types::U256::from(sid),), None, EthOption::default(), None)
             .wait()
-        {
-            Ok(h) => h,
-            Err(e) => {
-                println!("_get_curr_hash recv ERROR");
-                web3::types::H256::default()
-            }
-        }
+            .map_err(Error::from)
     }
 
-    pub fn _get_curr_state(&self, sid: usize) -> ContractState {
-        let hash = self._get_curr_hash(sid);
-        let blk_id = self._get_blk_id(sid);
-        ContractState {
+    pub fn _get_curr_state(&self, sid: usize) -> Result<ContractState, Error> {
+        let hash = self._get_curr_hash(sid)?;
+        let blk_id = self._get_blk_id(sid)?;
+        Ok(ContractState {
             curr_hash: hash.into(),
             block_id: blk_id.as_u64(),
-        }
+        })
     }
 
-    pub fn _count_scale_nodes(&self) -> usize {
+    pub fn _count_scale_nodes(&self) -> Result<usize, Error> {
         let cnt: U256 = self.contract
             .query("scaleNodesCount", (), None, EthOption::default(), None)
-            .wait()
-            .unwrap();
-        cnt.as_usize()
+            .wait()?;
+        Ok(cnt.as_usize())
     }
 
-    pub fn _get_scale_node(&self, index: usize) -> Address {
+    pub fn _get_scale_node(&self, index: usize) -> Result<Address, Error> {
         self.contract
             .query("getScaleNode", (web3::types::U256::from(index), ), None, EthOption::default(), None)
             .wait()
-            .unwrap()
+            .map_err(Error::from)
+    }
+
+    pub fn _get_scale_node_info(&self, index: usize) -> Result<ScaleNodeInfo, Error> {
+        let (address, ip_addr, pkx1, pkx2, pky1, pky2): (Address, String, U256, U256, U256, U256) = self.contract
+            .query("scale_nodes", (web3::types::U256::from(index), ), None, EthOption::default(), None)
+            .wait()?;
+        Ok(ScaleNodeInfo {
+            index,
+            address,
+            ip_addr,
+            pub_key: (pkx1, pkx2, pky1, pky2),
+        })
+    }
+
+    // the node 0 is a placeholder of the contract, registered scale nodes
+    // start from 1
+    pub fn _get_scale_nodes(&self) -> Result<Vec<ScaleNodeInfo>, Error> {
+        let n = self._count_scale_nodes()?;
+        let mut nodes = Vec::new();
+        for i in 1..n {
+            nodes.push(self._get_scale_node_info(i)?);
+        }
+        Ok(nodes)
+    }
+
+    // registered side nodes of a ring, (account, p2p address) by ring id
+    pub fn _get_side_nodes(&self, sid: usize) -> Result<Vec<(Address, String)>, Error> {
+        let mut nodes = Vec::new();
+        for tid in 0..MAX_SIDE_NODES {
+            let (address, ip_addr): (Address, String) = self.contract
+                .query("token_rings", (web3::types::U256::from(sid), web3::types::U256::from(tid)), None, EthOption::default(), None)
+                .wait()?;
+            if address != Address::zero() {
+                nodes.push((address, ip_addr));
+            }
+        }
+        Ok(nodes)
     }
 
     pub fn _get_scale_id(&self, addr: Address) -> Option<U256> {
@@ -757,12 +674,20 @@ impl Contract {
         }
     }
 
-    pub fn _get_scale_pub_key(&self, addr: Address) -> (U256, U256, U256, U256) {
+    pub fn _get_scale_pub_key(&self, addr: Address) -> Result<(U256, U256, U256, U256), Error> {
         self.contract
             .query("getScalePubKey", (addr), None, EthOption::default(), None)
             .wait()
-            .unwrap()
+            .map_err(Error::from)
+
+    }
 
+    fn _get_block_number(&self) -> Result<u64, Error> {
+        self.web3.eth()
+            .block_number()
+            .wait()
+            .map(|number| number.as_u64())
+            .map_err(Error::from)
     }
 
     fn _send_transaction_ori(&self, signed_tx: Vec<u8>) -> web3::types::H256 {
@@ -778,18 +703,15 @@ impl Contract {
             .wait() 
     }
 
-    fn _transaction_receipt(&self, tx_hash: web3::types::H256) -> Option<TransactionReceipt> {
-        match self.web3.eth()
+    // None while the tx is not mined
+    fn _transaction_receipt(&self, tx_hash: web3::types::H256) -> Result<Option<TransactionReceipt>, Error> {
+        self.web3.eth()
             .transaction_receipt(tx_hash)
             .wait()
-            {
-                Ok(t) => t,
-                Err(_) => None,
-            }
-            
+            .map_err(Error::from)
     }
 
-    fn _estimate_gas(&self, data: Vec<u8>) -> U256 {
+    fn _estimate_gas(&self, data: Vec<u8>) -> Result<U256, Error> {
         let call_request = CallRequest {
             from: Some(H160::from(self.my_account.address.0)),
             to: H160::from(self.contract_address.0),
@@ -799,12 +721,10 @@ impl Contract {
             value: Some(U256::zero())
         };
 
-        let gas =
-            self.web3.eth()
-                .estimate_gas(call_request, None)
-                .wait()
-                .unwrap();
-        gas
+        self.web3.eth()
+            .estimate_gas(call_request, None)
+            .wait()
+            .map_err(Error::from)
     }
 
 
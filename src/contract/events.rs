@@ -0,0 +1,421 @@
+use super::interface::ContractEvent;
+use super::utils::{hash_header, BLOCK_COMMITTED};
+
+use web3::contract::Contract as EthContract;
+use web3::contract::Options as EthOption;
+use web3::types::{BlockId, BlockNumber, FilterBuilder, Log, H256, U256};
+use web3::futures::Future;
+use web3::ethabi::{self, ParamType, Token};
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use crossbeam::channel::{self, Sender, Receiver};
+use log::{info, warn};
+
+const SCALE_NODE_ADDED: &str = "ScaleNodeAdded(uint256,address,string)";
+const SIDE_NODE_ADDED: &str = "SideNodeAdded(uint256,address,string)";
+const SIDE_NODE_DELETED: &str = "SideNodeDeleted(uint256,uint256)";
+const SIDE_CHAIN_RESET: &str = "SideChainReset(uint256)";
+
+#[derive(Debug, Clone)]
+pub struct EventConfig {
+    pub poll: Duration,
+    pub max_range: u64,             // main chain blocks per log query, nodes cap it
+    pub cursor_dir: Option<String>, // cursors kept in memory only if None
+    pub confirmations: u64,         // main chain blocks on top before a commit is final
+}
+
+impl Default for EventConfig {
+    fn default() -> Self {
+        EventConfig {
+            poll: Duration::from_secs(1),
+            max_range: 1000,
+            cursor_dir: None,
+            confirmations: 12,
+        }
+    }
+}
+
+// next main chain block to filter logs from, persisted so that a restart
+// does not scan the main chain again from its start. A fresh cursor starts
+// at the head, blocks committed before are found by their block id
+struct Cursor {
+    next_block: Option<u64>,
+    path: Option<PathBuf>,
+}
+
+impl Cursor {
+    fn load(path: Option<PathBuf>) -> Cursor {
+        let next_block = path.
+            as_ref().
+            and_then(|p| fs::read_to_string(p).ok()).
+            and_then(|s| s.trim().parse::<u64>().ok());
+        Cursor {
+            next_block: next_block,
+            path: path,
+        }
+    }
+
+    fn advance(&mut self, next_block: u64) {
+        self.next_block = Some(next_block);
+        if let Some(path) = &self.path {
+            if let Err(e) = fs::write(path, next_block.to_string()) {
+                warn!("unable to persist event cursor {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+// events of a side chain, from contract logs where the node serves them and
+// from polling the block id otherwise. Every committed block id is emitted
+// once and in order, also those whose log was missed
+pub struct EventSource {
+    contract: EthContract<web3::transports::Http>,
+    web3: web3::api::Web3<web3::transports::Http>,
+    sid: u64,
+    last_block_id: u64, // latest block id emitted
+    cursor: Cursor,
+    config: EventConfig,
+    use_logs: bool, // false once the node does not support log filters
+    // block id, main chain block number and hash of commits not final yet
+    recent: VecDeque<(u64, u64, H256)>,
+}
+
+impl EventSource {
+    pub fn new(
+        contract: EthContract<web3::transports::Http>,
+        web3: web3::api::Web3<web3::transports::Http>,
+        sid: u64,
+        last_block_id: u64,
+        config: EventConfig,
+    ) -> EventSource {
+        let path = config.
+            cursor_dir.
+            as_ref().
+            map(|dir| PathBuf::from(format!("{}/events-{}", dir, sid)));
+        EventSource {
+            contract: contract,
+            web3: web3,
+            sid: sid,
+            last_block_id: last_block_id,
+            cursor: Cursor::load(path),
+            config: config,
+            use_logs: true,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn start(mut self) -> Receiver<ContractEvent> {
+        let (event_tx, event_rx) = channel::unbounded();
+        let _ = thread::Builder::new()
+            .name("contract-events".to_string())
+            .spawn(move || {
+                // until the subscriber is gone
+                while self.poll(&event_tx).is_ok() {
+                    thread::sleep(self.config.poll);
+                }
+            });
+        event_rx
+    }
+
+    fn poll(&mut self, events: &Sender<ContractEvent>) -> Result<(), ()> {
+        let head = match self.web3.eth().block_number().wait() {
+            Ok(n) => n.as_u64(),
+            Err(e) => {
+                warn!("unable to read main chain height: {:?}", e);
+                return Ok(());
+            },
+        };
+        if self.cursor.next_block.is_none() {
+            self.cursor.advance(head);
+        }
+        self.check_reorg(head, events)?;
+        while self.use_logs && self.cursor.next_block.unwrap() <= head {
+            let from = self.cursor.next_block.unwrap();
+            let to = head.min(from + self.config.max_range - 1);
+            match self.logs(from, to) {
+                Ok(logs) => {
+                    for log in logs.iter() {
+                        if let Some(event) = self.decode(log) {
+                            self.emit(event, events)?;
+                        }
+                    }
+                    self.cursor.advance(to + 1);
+                },
+                Err(e) if is_unsupported(&e) => {
+                    warn!("node does not filter logs, polling contract state only: {:?}", e);
+                    self.use_logs = false;
+                },
+                Err(e) => {
+                    // timeouts and dropped connections, the range is tried
+                    // again next poll
+                    warn!("log filtering of blocks {} to {} failed: {:?}", from, to, e);
+                    break;
+                },
+            }
+        }
+
+        // read at the same height as the logs, so that a block found here
+        // had its log in the range already. Also covers contracts deployed
+        // without events
+        let at = Some(BlockNumber::Number(head.into()));
+        let block_id: U256 = match self.contract
+            .query("getBlockID", (U256::from(self.sid),), None, EthOption::default(), at)
+            .wait()
+        {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("unable to read block id of side chain {}: {:?}", self.sid, e);
+                return Ok(());
+            },
+        };
+        let block_id = block_id.as_u64();
+        if block_id < self.last_block_id {
+            info!("side chain {} went back to block {}", self.sid, block_id);
+            self.last_block_id = block_id;
+            return self.send(ContractEvent::ChainReset { sid: self.sid }, events);
+        }
+        if block_id > self.last_block_id {
+            let curr_hash: Option<H256> = self.contract
+                .query("getCurrentHash", (U256::from(self.sid),), None, EthOption::default(), at)
+                .wait()
+                .ok();
+            // committed at head at the latest
+            let event = ContractEvent::BlockCommitted {
+                sid: self.sid,
+                block_id: block_id,
+                header_hash: None,
+                curr_hash: curr_hash,
+                signers: None,
+                main_block: self.main_hash(head).map(|hash| (head, hash)),
+            };
+            self.emit(event, events)?;
+        }
+        Ok(())
+    }
+
+    fn logs(&self, from: u64, to: u64) -> Result<Vec<Log>, web3::error::Error> {
+        let topics = vec![
+            event_topic(BLOCK_COMMITTED),
+            event_topic(SCALE_NODE_ADDED),
+            event_topic(SIDE_NODE_ADDED),
+            event_topic(SIDE_NODE_DELETED),
+            event_topic(SIDE_CHAIN_RESET),
+        ];
+        let filter = FilterBuilder::default()
+            .address(vec![self.contract.address()])
+            .from_block(BlockNumber::Number(from.into()))
+            .to_block(BlockNumber::Number(to.into()))
+            .topics(Some(topics), None, None, None)
+            .build();
+        self.web3.eth()
+            .logs(filter)
+            .wait()
+    }
+
+    // side node events of other side chains are left out
+    fn decode(&self, log: &Log) -> Option<ContractEvent> {
+        let topic = log.topics.get(0)?;
+        let data = &log.data.0;
+        let event = if *topic == event_topic(BLOCK_COMMITTED) {
+            let params = [
+                ParamType::Uint(256), ParamType::Uint(256),
+                ParamType::FixedBytes(32), ParamType::FixedBytes(32),
+                ParamType::Uint(256),
+            ];
+            let tokens = ethabi::decode(&params, data).ok()?;
+            ContractEvent::BlockCommitted {
+                sid: uint(&tokens[0])?.as_u64(),
+                block_id: uint(&tokens[1])?.as_u64(),
+                header_hash: Some(bytes32(&tokens[2])?),
+                curr_hash: Some(bytes32(&tokens[3])?),
+                signers: Some(uint(&tokens[4])?),
+                main_block: match (log.block_number, log.block_hash) {
+                    (Some(number), Some(hash)) => Some((number.as_u64(), hash)),
+                    _ => None,
+                },
+            }
+        } else if *topic == event_topic(SCALE_NODE_ADDED) {
+            let params = [ParamType::Uint(256), ParamType::Address, ParamType::String];
+            let mut tokens = ethabi::decode(&params, data).ok()?;
+            ContractEvent::ScaleNodeAdded {
+                scale_id: uint(&tokens[0])?.as_usize(),
+                address: tokens[1].clone().to_address()?,
+                ip_addr: tokens.remove(2).to_string()?,
+            }
+        } else if *topic == event_topic(SIDE_NODE_ADDED) {
+            let params = [ParamType::Uint(256), ParamType::Address, ParamType::String];
+            let mut tokens = ethabi::decode(&params, data).ok()?;
+            ContractEvent::SideNodeAdded {
+                sid: uint(&tokens[0])?.as_u64(),
+                address: tokens[1].clone().to_address()?,
+                ip_addr: tokens.remove(2).to_string()?,
+            }
+        } else if *topic == event_topic(SIDE_NODE_DELETED) {
+            let tokens = ethabi::decode(&[ParamType::Uint(256), ParamType::Uint(256)], data).ok()?;
+            ContractEvent::SideNodeDeleted {
+                sid: uint(&tokens[0])?.as_u64(),
+                tid: uint(&tokens[1])?.as_u64(),
+            }
+        } else if *topic == event_topic(SIDE_CHAIN_RESET) {
+            let tokens = ethabi::decode(&[ParamType::Uint(256)], data).ok()?;
+            ContractEvent::ChainReset {
+                sid: uint(&tokens[0])?.as_u64(),
+            }
+        } else {
+            return None;
+        };
+        match &event {
+            ContractEvent::BlockCommitted { sid, .. } |
+            ContractEvent::SideNodeAdded { sid, .. } |
+            ContractEvent::SideNodeDeleted { sid, .. } |
+            ContractEvent::ChainReset { sid } |
+            ContractEvent::Reorg { sid, .. } if *sid != self.sid => None,
+            _ => Some(event),
+        }
+    }
+
+    // blocks up to the emitted one go first if still missing, as committed
+    // in the same main chain block at the latest
+    fn emit(&mut self, event: ContractEvent, events: &Sender<ContractEvent>) -> Result<(), ()> {
+        match &event {
+            ContractEvent::BlockCommitted { block_id, main_block, .. } => {
+                if *block_id <= self.last_block_id {
+                    return Ok(());
+                }
+                for missing in (self.last_block_id + 1)..*block_id {
+                    let gap = ContractEvent::BlockCommitted {
+                        sid: self.sid,
+                        block_id: missing,
+                        header_hash: None,
+                        curr_hash: None,
+                        signers: None,
+                        main_block: *main_block,
+                    };
+                    self.send(gap, events)?;
+                }
+                self.last_block_id = *block_id;
+                if let Some((number, hash)) = main_block {
+                    self.recent.push_back((*block_id, *number, *hash));
+                }
+            },
+            ContractEvent::ChainReset { .. } => {
+                self.last_block_id = 0;
+                self.recent.clear();
+            },
+            _ => (),
+        }
+        self.send(event, events)
+    }
+
+    // a commit whose main chain block is no longer canonical is reverted with
+    // every commit after it, logs and block id are then read again from there
+    fn check_reorg(&mut self, head: u64, events: &Sender<ContractEvent>) -> Result<(), ()> {
+        let confirmations = self.config.confirmations;
+        while let Some((_, number, _)) = self.recent.front() {
+            if number + confirmations <= head + 1 {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+        let mut canonical: Option<(u64, Option<H256>)> = None;
+        let mut fork: Option<usize> = None;
+        for (i, (_, number, hash)) in self.recent.iter().enumerate() {
+            let main_hash = match canonical {
+                Some((n, h)) if n == *number => h,
+                _ => self.main_hash(*number),
+            };
+            canonical = Some((*number, main_hash));
+            // unable to tell on a read error, checked again next poll
+            if main_hash.map_or(false, |h| h != *hash) {
+                fork = Some(i);
+                break;
+            }
+        }
+        let fork = match fork {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let (block_id, main_number, _) = self.recent[fork];
+        warn!("main chain replaced block {}, side chain {} reverts to block {}", main_number, self.sid, block_id - 1);
+        self.recent.truncate(fork);
+        self.last_block_id = block_id - 1;
+        if self.cursor.next_block.map_or(false, |n| n > main_number) {
+            self.cursor.advance(main_number);
+        }
+        self.send(ContractEvent::Reorg { sid: self.sid, main_number: main_number }, events)
+    }
+
+    fn main_hash(&self, number: u64) -> Option<H256> {
+        self.web3.eth()
+            .block(BlockId::Number(BlockNumber::Number(number.into())))
+            .wait()
+            .ok()
+            .flatten()
+            .and_then(|block| block.hash)
+    }
+
+    fn send(&self, event: ContractEvent, events: &Sender<ContractEvent>) -> Result<(), ()> {
+        events.send(event).map_err(|_| ())
+    }
+}
+
+// keccak of the event signature, the first topic of its logs
+// the node lacks eth_getLogs, as opposed to failing a call of it
+fn is_unsupported(e: &web3::error::Error) -> bool {
+    match e {
+        web3::error::Error::Rpc(rpc) => {
+            let message = rpc.message.to_lowercase();
+            rpc.code == web3::rpc::ErrorCode::MethodNotFound ||
+                message.contains("method not found") ||
+                message.contains("not supported") ||
+                message.contains("unsupported")
+        },
+        _ => false,
+    }
+}
+
+fn event_topic(signature: &str) -> H256 {
+    let mut hash = [0u8; 32];
+    hash_header(signature.as_bytes(), &mut hash);
+    H256::from(hash)
+}
+
+fn uint(token: &Token) -> Option<U256> {
+    match token {
+        Token::Uint(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn bytes32(token: &Token) -> Option<H256> {
+    match token {
+        Token::FixedBytes(b) if b.len() == 32 => Some(H256::from_slice(b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc_error(code: web3::rpc::ErrorCode, message: &str) -> web3::error::Error {
+        web3::error::Error::Rpc(web3::rpc::Error {
+            code: code,
+            message: message.to_string(),
+            data: None,
+        })
+    }
+
+    #[test]
+    fn only_missing_log_filters_are_unsupported() {
+        assert!(is_unsupported(&rpc_error(web3::rpc::ErrorCode::MethodNotFound, "Method not found")));
+        assert!(is_unsupported(&rpc_error(web3::rpc::ErrorCode::ServerError(-32000), "eth_getLogs is not supported")));
+        assert!(!is_unsupported(&rpc_error(web3::rpc::ErrorCode::ServerError(-32000), "query timeout exceeded")));
+        assert!(!is_unsupported(&web3::error::Error::Transport("connection reset".to_string())));
+    }
+}
@@ -0,0 +1,311 @@
+use super::utils::*;
+use super::keystore::address_of;
+
+use web3::types::{Address, U256};
+use ethereum_tx_sign::RawTransaction;
+use crypto::sha3::Sha3;
+use crypto::digest::Digest;
+use rlp::RlpStream;
+use std::str::FromStr;
+
+const GWEI: u64 = 1000000000;
+const EIP1559_TX_TYPE: u8 = 2;
+
+// how writes pay for gas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeMode {
+    // type 0 tx, EIP-155 signed
+    Legacy { gas_price: U256 },
+    // type 2 tx, base fee burnt and the tip to the miner
+    Eip1559 { max_fee: U256, max_priority_fee: U256 },
+}
+
+impl FeeMode {
+    // the most a unit of gas may cost
+    pub fn max_price(&self) -> U256 {
+        match self {
+            FeeMode::Legacy { gas_price } => *gas_price,
+            FeeMode::Eip1559 { max_fee, .. } => *max_fee,
+        }
+    }
+
+    // prices of a replacement tx, None once beyond max_price. Nodes take a
+    // type 2 replacement only if both the fee cap and the tip are raised
+    pub fn bump(&self, percent: u64, max_price: U256) -> Option<FeeMode> {
+        let raise = |v: U256| v * U256::from(100 + percent) / U256::from(100);
+        let bumped = match self {
+            FeeMode::Legacy { gas_price } => FeeMode::Legacy { gas_price: raise(*gas_price) },
+            FeeMode::Eip1559 { max_fee, max_priority_fee } => FeeMode::Eip1559 {
+                max_fee: raise(*max_fee),
+                max_priority_fee: raise(*max_priority_fee),
+            },
+        };
+        if bumped.max_price() > max_price {
+            None
+        } else {
+            Some(bumped)
+        }
+    }
+}
+
+// gas limit of writes without one of their own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GasLimit {
+    Estimate { margin_percent: u64 }, // eth_estimateGas plus a margin
+    Fixed(U256),
+}
+
+impl GasLimit {
+    pub fn from_estimate(&self, estimate: U256) -> U256 {
+        match self {
+            GasLimit::Estimate { margin_percent } => estimate * U256::from(100 + margin_percent) / U256::from(100),
+            GasLimit::Fixed(gas) => *gas,
+        }
+    }
+}
+
+// --gas_limit, "estimate", "estimate:<margin percent>" or a fixed limit
+impl FromStr for GasLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GasLimit, String> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next().unwrap(), parts.next()) {
+            ("estimate", None) => Ok(GasLimit::Estimate { margin_percent: 20 }),
+            ("estimate", Some(margin)) => margin.
+                parse::<u64>().
+                map(|m| GasLimit::Estimate { margin_percent: m }).
+                map_err(|_| format!("invalid gas margin {}", margin)),
+            (gas, None) => U256::from_dec_str(gas).
+                map(GasLimit::Fixed).
+                map_err(|_| format!("unknown gas limit {}, expect estimate, estimate:<margin> or a number", s)),
+            _ => Err(format!("unknown gas limit {}, expect estimate, estimate:<margin> or a number", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GasConfig {
+    pub chain_id: u32,
+    pub fees: FeeMode,
+    pub limit: GasLimit,
+    pub max_gas_price: U256, // cap of bumped prices
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        ChainProfile::Devnet.gas_config()
+    }
+}
+
+// --chain_profile presets, each part can be overridden on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainProfile {
+    Kovan,
+    Sepolia,
+    Holesky,
+    Devnet, // geth --dev, ganache and alike
+}
+
+impl ChainProfile {
+    pub fn gas_config(&self) -> GasConfig {
+        let gwei = |n: u64| U256::from(GWEI) * U256::from(n);
+        match self {
+            ChainProfile::Kovan => GasConfig {
+                chain_id: 42,
+                fees: FeeMode::Legacy { gas_price: gwei(20) },
+                limit: GasLimit::Estimate { margin_percent: 20 },
+                max_gas_price: gwei(500),
+            },
+            ChainProfile::Sepolia => GasConfig {
+                chain_id: 11155111,
+                fees: FeeMode::Eip1559 { max_fee: gwei(50), max_priority_fee: gwei(2) },
+                limit: GasLimit::Estimate { margin_percent: 20 },
+                max_gas_price: gwei(500),
+            },
+            ChainProfile::Holesky => GasConfig {
+                chain_id: 17000,
+                fees: FeeMode::Eip1559 { max_fee: gwei(50), max_priority_fee: gwei(2) },
+                limit: GasLimit::Estimate { margin_percent: 20 },
+                max_gas_price: gwei(500),
+            },
+            // blocks are mined on demand, so nothing gets stuck behind fees
+            ChainProfile::Devnet => GasConfig {
+                chain_id: 1337,
+                fees: FeeMode::Legacy { gas_price: gwei(1) },
+                limit: GasLimit::Estimate { margin_percent: 20 },
+                max_gas_price: gwei(10),
+            },
+        }
+    }
+}
+
+impl FromStr for ChainProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ChainProfile, String> {
+        match s {
+            "kovan" => Ok(ChainProfile::Kovan),
+            "sepolia" => Ok(ChainProfile::Sepolia),
+            "holesky" => Ok(ChainProfile::Holesky),
+            "devnet" => Ok(ChainProfile::Devnet),
+            _ => Err(format!("unknown chain profile {}, expect kovan, sepolia, holesky or devnet", s)),
+        }
+    }
+}
+
+// raw signed tx of a contract call, for eth_sendRawTransaction
+pub fn sign_tx(
+    fees: &FeeMode,
+    chain_id: u32,
+    nonce: U256,
+    to: Address,
+    gas: U256,
+    data: &[u8],
+    private_key: &str,
+) -> Result<Vec<u8>, String> {
+    let secret = hex::decode(private_key.trim_start_matches("0x")).
+        map_err(|_| "invalid hex in private key".to_string())?;
+    address_of(&secret)?;
+    match fees {
+        FeeMode::Legacy { gas_price } => {
+            let tx = RawTransaction {
+                nonce: _convert_u256(nonce),
+                to: Some(ethereum_types::H160::from(to.0)),
+                value: ethereum_types::U256::zero(),
+                gas_price: _convert_u256(*gas_price),
+                gas: _convert_u256(gas),
+                data: data.to_vec(),
+            };
+            let key = ethereum_types::H256(_to_array(&secret));
+            Ok(tx.sign(&key, &chain_id))
+        },
+        FeeMode::Eip1559 { max_fee, max_priority_fee } => {
+            // 0x02 || rlp([chain_id, nonce, tip, fee cap, gas, to, value, data, access_list])
+            let append_fields = |stream: &mut RlpStream| {
+                stream.append(&(chain_id as u64));
+                stream.append(&_u256_to_bytes(nonce));
+                stream.append(&_u256_to_bytes(*max_priority_fee));
+                stream.append(&_u256_to_bytes(*max_fee));
+                stream.append(&_u256_to_bytes(gas));
+                stream.append(&to.as_bytes().to_vec());
+                stream.append(&Vec::<u8>::new()); // value
+                stream.append(&data.to_vec());
+                stream.begin_list(0); // access list
+            };
+            let mut unsigned = RlpStream::new_list(9);
+            append_fields(&mut unsigned);
+            let mut payload = vec![EIP1559_TX_TYPE];
+            payload.extend_from_slice(&unsigned.out());
+
+            let mut hasher = Sha3::keccak256();
+            hasher.input(&payload);
+            let mut digest = [0u8; 32];
+            hasher.result(&mut digest);
+            let signature = sign_digest(&digest, &secret).
+                ok_or("invalid private key".to_string())?;
+
+            let mut signed = RlpStream::new_list(12);
+            append_fields(&mut signed);
+            signed.append(&(signature[64] as u64)); // y parity
+            signed.append(&_trim_zeros(&signature[0..32]));
+            signed.append(&_trim_zeros(&signature[32..64]));
+            let mut raw = vec![EIP1559_TX_TYPE];
+            raw.extend_from_slice(&signed.out());
+            Ok(raw)
+        },
+    }
+}
+
+// rlp integers are big endian without leading zeros
+fn _u256_to_bytes(value: U256) -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    _trim_zeros(&bytes)
+}
+
+fn _trim_zeros(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    bytes[start..].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlp::Rlp;
+
+    // the key and recipient of the EIP-155 example
+    const KEY: &str = "4646464646464646464646464646464646464646464646464646464646464646";
+
+    fn to() -> Address {
+        Address::from_slice(&[0x35; 20])
+    }
+
+    fn fees() -> FeeMode {
+        FeeMode::Eip1559 {
+            max_fee: U256::from(100 * GWEI),
+            max_priority_fee: U256::from(2 * GWEI),
+        }
+    }
+
+    fn keccak(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha3::keccak256();
+        hasher.input(data);
+        let mut digest = [0u8; 32];
+        hasher.result(&mut digest);
+        digest
+    }
+
+    // expected bytes from an independent encoder and rfc6979 signer, which
+    // reproduces the signed tx of the EIP-155 example with the same key.
+    // Nonce 0 encodes as the empty string, y parity is 1 here and 0 below
+    #[test]
+    fn eip1559_known_answer() {
+        let raw = sign_tx(&fees(), 1, U256::zero(), to(), U256::from(100000), &[0xde, 0xad, 0xbe, 0xef], KEY).unwrap();
+        assert_eq!(
+            hex::encode(&raw),
+            "02f8700180847735940085174876e800830186a0943535353535353535353535353535353535353535\
+             8084deadbeefc001a0bf95eed391802bae477673786ff5094496f442960ce7ee1d4096b22254bcb54a\
+             a048f6781c1ffe75681d43c94a9d6458941ccc183097bdf5d0aa461385536dbb41"
+        );
+        assert_eq!(hex::encode(keccak(&raw)), "00463eaea59007e134d605e2b3ff51d6c4064e6acf663b593a2ab4778b6efdb7");
+
+        let raw = sign_tx(&fees(), 1, U256::one(), to(), U256::from(100000), &[0xde, 0xad, 0xbe, 0xef], KEY).unwrap();
+        assert_eq!(
+            hex::encode(&raw),
+            "02f8700101847735940085174876e800830186a0943535353535353535353535353535353535353535\
+             8084deadbeefc080a08defbd4052b40c886491331709698a0248ab2524e3944a7ece7842c0496e1a93\
+             a0086f3cdeb1f98d702252b5d0fe59765e104ab724a072e908167ab7a94375c520"
+        );
+        assert_eq!(hex::encode(keccak(&raw)), "fa73d5ed45d8b86ac3035380132068006cf42d5b4d05adf1eb972419d2e83458");
+    }
+
+    // the signature over 0x02 || rlp(first 9 fields) recovers the sender
+    #[test]
+    fn eip1559_recovers_sender() {
+        let key = hex::decode(KEY).unwrap();
+        let sender = address_of(&key).unwrap();
+        for nonce in 0..4u64 {
+            let raw = sign_tx(&fees(), 5, U256::from(nonce), to(), U256::from(21000), &[], KEY).unwrap();
+            assert_eq!(raw[0], EIP1559_TX_TYPE);
+            let fields = Rlp::new(&raw[1..]);
+            assert_eq!(fields.item_count().unwrap(), 12);
+            let mut unsigned = RlpStream::new_list(9);
+            for i in 0..9 {
+                unsigned.append_raw(fields.at(i).unwrap().as_raw(), 1);
+            }
+            let mut payload = vec![EIP1559_TX_TYPE];
+            payload.extend_from_slice(&unsigned.out());
+
+            let y_parity: u8 = fields.val_at(9).unwrap();
+            let r: Vec<u8> = fields.val_at(10).unwrap();
+            let s: Vec<u8> = fields.val_at(11).unwrap();
+            let mut signature = vec![0u8; 65];
+            signature[32 - r.len()..32].copy_from_slice(&r);
+            signature[64 - s.len()..64].copy_from_slice(&s);
+            signature[64] = y_parity;
+            assert!(y_parity <= 1);
+            assert_eq!(recover_address(&keccak(&payload), &signature), Some(sender));
+        }
+    }
+}
@@ -1,6 +1,42 @@
-use crossbeam::channel::{Sender};
+use crossbeam::channel::{self, Sender, Receiver, RecvTimeoutError};
 use super::primitive::block::{EthBlkTransaction, ContractState, Block};
 use web3::types::{Address, H256, TransactionReceipt, U256};
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+use log::warn;
+
+// a scale node as registered in the contract
+#[derive(Clone, Debug)]
+pub struct ScaleNodeInfo {
+    pub index: usize, // scale id
+    pub address: Address,
+    pub ip_addr: String, // p2p address given at registration
+    pub pub_key: (U256, U256, U256, U256), // bls pkx1, pkx2, pky1, pky2
+}
+
+// state changes of the contract, in the order the main chain made them
+#[derive(Clone, Debug)]
+pub enum ContractEvent {
+    // header_hash and curr_hash are None when found by polling the block id,
+    // signers a bitset of scale ids. main_block is the main chain block
+    // number and hash of the commit, or of a later block if found by polling
+    BlockCommitted {
+        sid: u64,
+        block_id: u64,
+        header_hash: Option<H256>,
+        curr_hash: Option<H256>,
+        signers: Option<U256>,
+        main_block: Option<(u64, H256)>,
+    },
+    ScaleNodeAdded { scale_id: usize, address: Address, ip_addr: String },
+    SideNodeAdded { sid: u64, address: Address, ip_addr: String },
+    SideNodeDeleted { sid: u64, tid: u64 },
+    ChainReset { sid: u64 },
+    // the main chain replaced its blocks from main_number on, block ids
+    // committed there are emitted again as the new blocks commit them
+    Reorg { sid: u64, main_number: u64 },
+}
 
 #[derive(Clone)]
 pub struct Handle {
@@ -13,22 +49,24 @@ pub enum Response {
     GetCurrState(ContractState),
     CountScaleNode(usize), 
     AddScaleNode,
-    ScaleNodesList(Vec<Address>),
+    ScaleNodesList(Vec<ScaleNodeInfo>),
     TxReceipt(TransactionReceipt),
     GetAll(Vec<EthBlkTransaction>),
     SyncChain(usize),
+    BlockNumber(u64), // main chain height
+    Events(Receiver<ContractEvent>),
 }
 #[derive(Clone)]
 pub enum Answer {
     Success(Response),
-    Fail(String),
+    Fail(Error),
 }
 #[derive(Clone)]
 pub enum Message {
     SendBlock(Block),
     GetCurrState(usize),
     CountScaleNodes,
-    AddScaleNode(String, String),
+    AddScaleNode(String, String, String), // account and bls keystores, p2p address
     GetScaleNodes,
     GetTxReceipt(H256),
     GetAll(([u8;32], usize, usize)), //inithash, start, end
@@ -37,9 +75,250 @@ pub enum Message {
     SubmitVote(String, U256, U256, U256, U256, U256),
     ResetChain(usize),
     AddSideNode(usize),
+    GetBlockNumber,
+    Subscribe(usize, u64), // sid, block id the subscriber has
 }
 
+// why a contract request failed, answered instead of panicking the actor
+#[derive(Clone, Debug, PartialEq)]
 pub enum Error {
-    TimeOut,
-    ConnectionFail,
+    TimeOut,                // no answer in time
+    ConnectionFail(String), // rpc endpoint unreachable or failing
+    Reverted(String),       // call or tx reverted by the contract
+    OutOfGas(String),       // tx ran out of its gas limit
+    NonceConflict(String),  // nonce taken, or replacement underpriced
+    Decode(String),         // unexpected rpc or abi data
+    Rejected(String),       // refused otherwise, by the node or before sending
+    Stopped,                // contract actor or tx manager is gone
+}
+
+impl Error {
+    // failures another try may not run into
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::TimeOut | Error::ConnectionFail(_) | Error::NonceConflict(_) => true,
+            _ => false,
+        }
+    }
+
+    // rpc error messages of geth, parity and alike
+    fn from_rpc_message(message: String) -> Error {
+        let lower = message.to_lowercase();
+        if lower.contains("nonce too low") || lower.contains("replacement transaction underpriced") {
+            Error::NonceConflict(message)
+        } else if lower.contains("out of gas") || lower.contains("gas required exceeds") ||
+            lower.contains("intrinsic gas too low") {
+            Error::OutOfGas(message)
+        } else if lower.contains("revert") {
+            Error::Reverted(message)
+        } else {
+            Error::Rejected(message)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TimeOut => write!(f, "timed out"),
+            Error::ConnectionFail(e) => write!(f, "rpc unreachable: {}", e),
+            Error::Reverted(e) => write!(f, "reverted: {}", e),
+            Error::OutOfGas(e) => write!(f, "out of gas: {}", e),
+            Error::NonceConflict(e) => write!(f, "nonce conflict: {}", e),
+            Error::Decode(e) => write!(f, "decode failure: {}", e),
+            Error::Rejected(e) => write!(f, "rejected: {}", e),
+            Error::Stopped => write!(f, "contract handler stopped"),
+        }
+    }
+}
+
+impl From<web3::error::Error> for Error {
+    fn from(e: web3::error::Error) -> Error {
+        match e {
+            web3::error::Error::Rpc(rpc) => Error::from_rpc_message(rpc.message),
+            web3::error::Error::Decoder(e) | web3::error::Error::InvalidResponse(e) => Error::Decode(e),
+            e => Error::ConnectionFail(format!("{:?}", e)),
+        }
+    }
+}
+
+impl From<web3::contract::Error> for Error {
+    fn from(e: web3::contract::Error) -> Error {
+        match e {
+            web3::contract::Error::Api(e) => Error::from(e),
+            e => Error::Decode(format!("{:?}", e)),
+        }
+    }
+}
+
+// how contract reads are tried
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    pub attempts: usize,
+    pub timeout: Duration, // for each answer
+    pub backoff: Duration, // before the second try, doubled after each
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            attempts: 3,
+            timeout: Duration::from_secs(30),
+            backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+// send a message to the contract actor and wait for its answer
+pub fn request(contract_handler: &Sender<Handle>, message: Message, timeout: Duration) -> Result<Response, Error> {
+    let (answer_tx, answer_rx) = channel::bounded(1);
+    let handle = Handle {
+        message: message,
+        answer_channel: Some(answer_tx),
+    };
+    if contract_handler.send(handle).is_err() {
+        return Err(Error::Stopped);
+    }
+    match answer_rx.recv_timeout(timeout) {
+        Ok(Answer::Success(response)) => Ok(response),
+        Ok(Answer::Fail(e)) => Err(e),
+        Err(RecvTimeoutError::Timeout) => Err(Error::TimeOut),
+        Err(RecvTimeoutError::Disconnected) => Err(Error::Stopped),
+    }
+}
+
+// the same, tried again while the failure is transient. For reads only, a
+// write timing out may still land
+pub fn request_with_retry(contract_handler: &Sender<Handle>, message: Message, retry: &Retry) -> Result<Response, Error> {
+    let mut backoff = retry.backoff;
+    let mut attempt = 1;
+    loop {
+        match request(contract_handler, message.clone(), retry.timeout) {
+            Err(e) if e.is_transient() && attempt < retry.attempts => {
+                warn!("contract request failed, {}, try again in {:?}", e, backoff);
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    fn rpc_error(message: &str) -> web3::error::Error {
+        web3::error::Error::Rpc(web3::rpc::Error {
+            code: web3::rpc::ErrorCode::ServerError(-32000),
+            message: message.to_string(),
+            data: None,
+        })
+    }
+
+    // a contract actor answering each request with the next scripted answer,
+    // counting the requests it got
+    fn scripted(answers: Vec<Answer>) -> (Sender<Handle>, Arc<Mutex<usize>>) {
+        let (tx, rx) = channel::unbounded::<Handle>();
+        let count = Arc::new(Mutex::new(0));
+        let seen = count.clone();
+        thread::spawn(move || {
+            let mut answers = answers.into_iter();
+            for handle in rx.iter() {
+                *seen.lock().unwrap() += 1;
+                if let (Some(ch), Some(answer)) = (handle.answer_channel, answers.next()) {
+                    ch.send(answer).unwrap();
+                }
+            }
+        });
+        (tx, count)
+    }
+
+    fn quick_retry() -> Retry {
+        Retry {
+            attempts: 3,
+            timeout: Duration::from_secs(1),
+            backoff: Duration::from_millis(20),
+        }
+    }
+
+    #[test]
+    fn classify_rpc_errors() {
+        assert_eq!(Error::from(rpc_error("nonce too low")), Error::NonceConflict("nonce too low".to_string()));
+        assert_eq!(
+            Error::from(rpc_error("replacement transaction underpriced")),
+            Error::NonceConflict("replacement transaction underpriced".to_string())
+        );
+        assert_eq!(
+            Error::from(rpc_error("gas required exceeds allowance (8000000)")),
+            Error::OutOfGas("gas required exceeds allowance (8000000)".to_string())
+        );
+        assert_eq!(Error::from(rpc_error("Out of gas")), Error::OutOfGas("Out of gas".to_string()));
+        assert_eq!(
+            Error::from(rpc_error("execution reverted: wrong block id")),
+            Error::Reverted("execution reverted: wrong block id".to_string())
+        );
+        assert_eq!(Error::from(rpc_error("unknown account")), Error::Rejected("unknown account".to_string()));
+    }
+
+    #[test]
+    fn classify_transport_errors() {
+        let e = Error::from(web3::error::Error::Transport("connection refused".to_string()));
+        assert!(matches!(e, Error::ConnectionFail(_)));
+        assert!(e.is_transient());
+        let e = Error::from(web3::error::Error::Decoder("invalid hex".to_string()));
+        assert_eq!(e, Error::Decode("invalid hex".to_string()));
+        assert!(!e.is_transient());
+        assert!(!Error::Reverted(String::new()).is_transient());
+    }
+
+    #[test]
+    fn retry_transient_with_backoff() {
+        let (contract, count) = scripted(vec![
+            Answer::Fail(Error::ConnectionFail("down".to_string())),
+            Answer::Fail(Error::NonceConflict("nonce too low".to_string())),
+            Answer::Success(Response::BlockNumber(7)),
+        ]);
+        let start = Instant::now();
+        match request_with_retry(&contract, Message::GetBlockNumber, &quick_retry()) {
+            Ok(Response::BlockNumber(7)) => (),
+            _ => panic!("expected the third answer"),
+        }
+        // 20ms before the second try, 40ms before the third
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        assert_eq!(*count.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn give_up_after_attempts() {
+        let down = || Answer::Fail(Error::ConnectionFail("down".to_string()));
+        let (contract, count) = scripted(vec![down(), down(), down(), down()]);
+        let result = request_with_retry(&contract, Message::GetBlockNumber, &quick_retry());
+        assert_eq!(result.err(), Some(Error::ConnectionFail("down".to_string())));
+        assert_eq!(*count.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn no_retry_when_not_transient() {
+        let (contract, count) = scripted(vec![
+            Answer::Fail(Error::Reverted("wrong block id".to_string())),
+            Answer::Success(Response::BlockNumber(7)),
+        ]);
+        let start = Instant::now();
+        let result = request_with_retry(&contract, Message::GetBlockNumber, &quick_retry());
+        assert_eq!(result.err(), Some(Error::Reverted("wrong block id".to_string())));
+        assert_eq!(*count.lock().unwrap(), 1);
+        assert!(start.elapsed() < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn stopped_actor() {
+        let (contract, rx) = channel::unbounded::<Handle>();
+        drop(rx);
+        let result = request(&contract, Message::GetBlockNumber, Duration::from_millis(10));
+        assert_eq!(result.err(), Some(Error::Stopped));
+    }
 }
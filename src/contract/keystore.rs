@@ -0,0 +1,575 @@
+use super::contract::Account;
+use super::utils::{BLSKeyStr};
+
+use web3::types::{Address, U256};
+use crypto::aes::{self, KeySize};
+use crypto::scrypt::{scrypt, ScryptParams};
+use crypto::pbkdf2::pbkdf2;
+use crypto::hmac::Hmac;
+use crypto::sha2::Sha256;
+use crypto::sha3::Sha3;
+use crypto::digest::Digest;
+use crypto::symmetriccipher::SynchronousStreamCipher;
+use crypto::util::fixed_time_eq;
+use secp256k1::{Secp256k1, SecretKey, PublicKey};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::str::FromStr;
+use log::warn;
+
+// env var holding the passphrase when no --passphrase_file is given
+pub const PASSPHRASE_ENV: &str = "KEYSTORE_PASSPHRASE";
+
+const KEYSTORE_VERSION: u32 = 3;
+const CIPHER: &str = "aes-128-ctr";
+const DKLEN: usize = 32;
+// geth standard scrypt, 256MB and about a second per key
+const SCRYPT_LOG_N: u8 = 18;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const PBKDF2_C: u32 = 262144;
+// scrypt memory a key file may ask for, 128 * r * n bytes
+const MAX_SCRYPT_MEMORY: u128 = 1 << 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum KdfParams {
+    Scrypt { dklen: usize, n: u64, r: u32, p: u32, salt: String },
+    Pbkdf2 { c: u32, dklen: usize, prf: String, salt: String },
+}
+
+// encrypted secret, mac = keccak(derived key[16..32] ++ ciphertext)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Crypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    pub ciphertext: String,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+    pub mac: String,
+}
+
+// Web3 Secret Storage v3, the format of geth and clef
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AccountKeyFile {
+    pub version: u32,
+    pub id: String,
+    pub address: String, // hex without 0x
+    #[serde(alias = "Crypto")]
+    pub crypto: Crypto,
+}
+
+// the same envelope around a BLS secret, public keys stay readable for
+// registration
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlsKeyFile {
+    pub version: u32,
+    pub id: String,
+    pub kind: String, // always bls
+    pub pkx1: String,
+    pub pkx2: String,
+    pub pky1: String,
+    pub pky2: String,
+    pub crypto: Crypto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    Scrypt,
+    Pbkdf2,
+}
+
+impl FromStr for Kdf {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Kdf, String> {
+        match s {
+            "scrypt" => Ok(Kdf::Scrypt),
+            "pbkdf2" => Ok(Kdf::Pbkdf2),
+            _ => Err(format!("unknown kdf {}, expect scrypt or pbkdf2", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    Eth, // secp256k1 account signing contract txs
+    Bls, // alt_bn128 key signing votes
+}
+
+impl FromStr for KeyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<KeyKind, String> {
+        match s {
+            "eth" => Ok(KeyKind::Eth),
+            "bls" => Ok(KeyKind::Bls),
+            _ => Err(format!("unknown key kind {}, expect eth or bls", s)),
+        }
+    }
+}
+
+// what a key file holds, read without the passphrase
+#[derive(Debug, Clone)]
+pub struct KeyInfo {
+    pub kind: KeyKind,
+    pub encrypted: bool,
+    pub public: String, // address of eth keys, pkx1 of bls keys
+}
+
+// passphrase from the file if given, else from the env
+pub fn read_passphrase(file: Option<&str>) -> Option<String> {
+    match file {
+        Some(path) => {
+            let passphrase = fs::read_to_string(path).
+                unwrap_or_else(|e| panic!("unable to read passphrase file {}: {}", path, e));
+            Some(passphrase.trim_end_matches(|c| c == '\n' || c == '\r').to_string())
+        },
+        None => std::env::var(PASSPHRASE_ENV).ok(),
+    }
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+// random uuid v4
+fn new_id() -> String {
+    let mut b = random_bytes(16);
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    format!("{}-{}-{}-{}-{}",
+        hex::encode(&b[0..4]), hex::encode(&b[4..6]), hex::encode(&b[6..8]),
+        hex::encode(&b[8..10]), hex::encode(&b[10..16]))
+}
+
+fn keccak(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha3::keccak256();
+    hasher.input(data);
+    let mut digest = [0u8; 32];
+    hasher.result(&mut digest);
+    digest
+}
+
+fn decode_hex(field: &str, value: &str) -> Result<Vec<u8>, String> {
+    hex::decode(value.trim_start_matches("0x")).map_err(|_| format!("invalid hex in {}", field))
+}
+
+fn derive_key(params: &KdfParams, passphrase: &str) -> Result<Vec<u8>, String> {
+    match params {
+        KdfParams::Scrypt { dklen, n, r, p, salt } => {
+            // what ScryptParams::new would otherwise assert
+            let log_n = n.trailing_zeros();
+            if !n.is_power_of_two() || *n <= 1 || *r == 0 || *p == 0 || *dklen < 32 ||
+                (*r as u64) * (*p as u64) >= 1 << 30 ||
+                log_n >= r.saturating_mul(16) ||
+                128 * (*r as u128) * (*n as u128) > MAX_SCRYPT_MEMORY
+            {
+                return Err(format!("unsupported scrypt params n={} r={} p={} dklen={}", n, r, p, dklen));
+            }
+            let salt = decode_hex("salt", salt)?;
+            let params = ScryptParams::new(log_n as u8, *r, *p);
+            let mut key = vec![0u8; *dklen];
+            scrypt(passphrase.as_bytes(), &salt, &params, &mut key);
+            Ok(key)
+        },
+        KdfParams::Pbkdf2 { c, dklen, prf, salt } => {
+            if prf != "hmac-sha256" || *c == 0 || *dklen < 32 {
+                return Err(format!("unsupported pbkdf2 params prf={} c={} dklen={}", prf, c, dklen));
+            }
+            let salt = decode_hex("salt", salt)?;
+            let mut mac = Hmac::new(Sha256::new(), passphrase.as_bytes());
+            let mut key = vec![0u8; *dklen];
+            pbkdf2(&mut mac, &salt, *c, &mut key);
+            Ok(key)
+        },
+    }
+}
+
+fn mac_of(key: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    let mut input = key[16..32].to_vec();
+    input.extend_from_slice(ciphertext);
+    keccak(&input)
+}
+
+fn aes_ctr(key: &[u8], iv: &[u8], input: &[u8]) -> Vec<u8> {
+    let mut output = vec![0u8; input.len()];
+    aes::ctr(KeySize::KeySize128, &key[0..16], iv).process(input, &mut output);
+    output
+}
+
+fn encrypt(secret: &[u8], passphrase: &str, kdf: Kdf) -> Crypto {
+    let salt = hex::encode(random_bytes(32));
+    let params = match kdf {
+        Kdf::Scrypt => KdfParams::Scrypt {
+            dklen: DKLEN,
+            n: 1 << SCRYPT_LOG_N,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt: salt,
+        },
+        Kdf::Pbkdf2 => KdfParams::Pbkdf2 {
+            c: PBKDF2_C,
+            dklen: DKLEN,
+            prf: "hmac-sha256".to_string(),
+            salt: salt,
+        },
+    };
+    let key = derive_key(&params, passphrase).expect("kdf params of our own");
+    let iv = random_bytes(16);
+    let ciphertext = aes_ctr(&key, &iv, secret);
+    Crypto {
+        cipher: CIPHER.to_string(),
+        cipherparams: CipherParams { iv: hex::encode(&iv) },
+        ciphertext: hex::encode(&ciphertext),
+        kdf: match kdf {
+            Kdf::Scrypt => "scrypt".to_string(),
+            Kdf::Pbkdf2 => "pbkdf2".to_string(),
+        },
+        kdfparams: params,
+        mac: hex::encode(&mac_of(&key, &ciphertext)),
+    }
+}
+
+fn decrypt(crypto: &Crypto, passphrase: &str) -> Result<Vec<u8>, String> {
+    if crypto.cipher != CIPHER {
+        return Err(format!("unsupported cipher {}", crypto.cipher));
+    }
+    let key = derive_key(&crypto.kdfparams, passphrase)?;
+    let ciphertext = decode_hex("ciphertext", &crypto.ciphertext)?;
+    let mac = decode_hex("mac", &crypto.mac)?;
+    if !fixed_time_eq(&mac_of(&key, &ciphertext), &mac) {
+        return Err("wrong passphrase or corrupted keystore".to_string());
+    }
+    let iv = decode_hex("iv", &crypto.cipherparams.iv)?;
+    if iv.len() != 16 {
+        return Err(format!("iv of {} bytes, expect 16", iv.len()));
+    }
+    Ok(aes_ctr(&key, &iv, &ciphertext))
+}
+
+// address = last 20 bytes of keccak(uncompressed public key without prefix)
+pub fn address_of(private_key: &[u8]) -> Result<Address, String> {
+    let secp = Secp256k1::new();
+    let sk = SecretKey::from_slice(private_key).map_err(|_| "invalid secp256k1 private key".to_string())?;
+    let pk = PublicKey::from_secret_key(&secp, &sk).serialize_uncompressed();
+    Ok(Address::from_slice(&keccak(&pk[1..])[12..]))
+}
+
+pub fn new_account() -> Account {
+    loop {
+        let private_key = random_bytes(32);
+        // almost every 32 bytes are a valid key
+        if let Ok(address) = address_of(&private_key) {
+            return Account {
+                address: address,
+                private_key: hex::encode(&private_key),
+            };
+        }
+    }
+}
+
+pub fn encrypt_account(account: &Account, passphrase: &str, kdf: Kdf) -> Result<AccountKeyFile, String> {
+    let private_key = decode_hex("private_key", &account.private_key)?;
+    let address = address_of(&private_key)?;
+    if address != account.address {
+        return Err(format!("private key is of {:?}, not of {:?}", address, account.address));
+    }
+    Ok(AccountKeyFile {
+        version: KEYSTORE_VERSION,
+        id: new_id(),
+        address: hex::encode(address.as_bytes()),
+        crypto: encrypt(&private_key, passphrase, kdf),
+    })
+}
+
+pub fn encrypt_bls_key(key: &BLSKeyStr, passphrase: &str, kdf: Kdf) -> Result<BlsKeyFile, String> {
+    let sk = U256::from_dec_str(&key.sk).map_err(|_| "invalid bls secret".to_string())?;
+    let mut secret = [0u8; 32];
+    sk.to_big_endian(&mut secret);
+    Ok(BlsKeyFile {
+        version: KEYSTORE_VERSION,
+        id: new_id(),
+        kind: "bls".to_string(),
+        pkx1: key.pkx1.clone(),
+        pkx2: key.pkx2.clone(),
+        pky1: key.pky1.clone(),
+        pky2: key.pky2.clone(),
+        crypto: encrypt(&secret, passphrase, kdf),
+    })
+}
+
+// keystores are only readable by their owner and never overwritten
+pub fn save<T: Serialize>(path: &str, key_file: &T) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(key_file).map_err(|e| format!("{}", e))?;
+    let mut file = OpenOptions::new().
+        write(true).
+        create_new(true).
+        mode(0o600).
+        open(path).
+        map_err(|e| format!("unable to create {}: {}", path, e))?;
+    file.write_all(&json).map_err(|e| format!("unable to write {}: {}", path, e))
+}
+
+fn read_json(path: &str) -> Result<Value, String> {
+    let bytes = fs::read(path).map_err(|e| format!("unable to read {}: {}", path, e))?;
+    serde_json::from_slice(&bytes).map_err(|e| format!("unable to deser {}: {}", path, e))
+}
+
+fn is_encrypted(json: &Value) -> bool {
+    json.get("crypto").is_some() || json.get("Crypto").is_some()
+}
+
+fn passphrase_for<'a>(path: &str, passphrase: Option<&'a str>) -> Result<&'a str, String> {
+    passphrase.ok_or(format!("{} is encrypted, give --passphrase_file or set {}", path, PASSPHRASE_ENV))
+}
+
+// an encrypted account, or a plaintext one as before
+pub fn load_account(path: &str, passphrase: Option<&str>) -> Result<Account, String> {
+    let json = read_json(path)?;
+    if !is_encrypted(&json) {
+        warn!("account {} is plaintext, encrypt it with importKey", path);
+        let account: Account = serde_json::from_value(json).
+            map_err(|e| format!("unable to deser account {}: {}", path, e))?;
+        // a bad key fails here rather than when the first tx is signed
+        let address = address_of(&decode_hex("private_key", &account.private_key)?)?;
+        if address != account.address {
+            return Err(format!("private key of {} is of {:?}, not of {:?}", path, address, account.address));
+        }
+        return Ok(account);
+    }
+    let key_file: AccountKeyFile = serde_json::from_value(json).
+        map_err(|e| format!("unable to deser keystore {}: {}", path, e))?;
+    let private_key = decrypt(&key_file.crypto, passphrase_for(path, passphrase)?)?;
+    let address = address_of(&private_key)?;
+    if decode_hex("address", &key_file.address)? != address.as_bytes() {
+        return Err(format!("private key of {} is of {:?}, not of 0x{}", path, address, key_file.address));
+    }
+    Ok(Account {
+        address: address,
+        private_key: hex::encode(&private_key),
+    })
+}
+
+// an encrypted bls key, or a plaintext one as before
+pub fn load_bls_key(path: &str, passphrase: Option<&str>) -> Result<BLSKeyStr, String> {
+    let json = read_json(path)?;
+    if !is_encrypted(&json) {
+        warn!("bls key {} is plaintext, encrypt it with importKey", path);
+        return serde_json::from_value(json).map_err(|e| format!("unable to deser bls key {}: {}", path, e));
+    }
+    let key_file: BlsKeyFile = serde_json::from_value(json).
+        map_err(|e| format!("unable to deser keystore {}: {}", path, e))?;
+    let secret = decrypt(&key_file.crypto, passphrase_for(path, passphrase)?)?;
+    Ok(BLSKeyStr {
+        sk: U256::from_big_endian(&secret).to_string(),
+        pkx1: key_file.pkx1,
+        pkx2: key_file.pkx2,
+        pky1: key_file.pky1,
+        pky2: key_file.pky2,
+    })
+}
+
+// address of an account, without its passphrase
+pub fn read_address(path: &str) -> Result<Address, String> {
+    let json = read_json(path)?;
+    let address = json.
+        get("address").
+        and_then(|a| a.as_str()).
+        ok_or(format!("no address in {}", path))?;
+    let address = decode_hex("address", address)?;
+    if address.len() != 20 {
+        return Err(format!("invalid address in {}", path));
+    }
+    Ok(Address::from_slice(&address))
+}
+
+// pkx1, pkx2, pky1, pky2 of a bls key, without its passphrase
+pub fn read_bls_public(path: &str) -> Result<(U256, U256, U256, U256), String> {
+    let json = read_json(path)?;
+    let field = |name: &str| json.
+        get(name).
+        and_then(|v| v.as_str()).
+        and_then(|v| U256::from_dec_str(v).ok()).
+        ok_or(format!("no {} in {}", name, path));
+    Ok((field("pkx1")?, field("pkx2")?, field("pky1")?, field("pky2")?))
+}
+
+pub fn describe(path: &str) -> Result<KeyInfo, String> {
+    let json = read_json(path)?;
+    let encrypted = is_encrypted(&json);
+    if let Some(pkx1) = json.get("pkx1").and_then(|v| v.as_str()) {
+        return Ok(KeyInfo {
+            kind: KeyKind::Bls,
+            encrypted: encrypted,
+            public: pkx1.to_string(),
+        });
+    }
+    let address = read_address(path)?;
+    Ok(KeyInfo {
+        kind: KeyKind::Eth,
+        encrypted: encrypted,
+        public: format!("{:?}", address),
+    })
+}
+
+// plaintext key files as written by hand and by the bls binary
+pub enum PlainKey {
+    Eth(Account),
+    Bls(BLSKeyStr),
+}
+
+pub fn read_plaintext(path: &str) -> Result<PlainKey, String> {
+    let json = read_json(path)?;
+    if is_encrypted(&json) {
+        return Err(format!("{} is already encrypted", path));
+    }
+    if json.get("sk").is_some() {
+        serde_json::from_value(json).
+            map(PlainKey::Bls).
+            map_err(|e| format!("unable to deser bls key {}: {}", path, e))
+    } else {
+        serde_json::from_value(json).
+            map(PlainKey::Eth).
+            map_err(|e| format!("{} is neither an account nor a bls key: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors of the Web3 Secret Storage definition, passphrase
+    // testpassword, with the address of their key added
+    const PBKDF2_VECTOR: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+            "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf": "pbkdf2",
+            "kdfparams": {
+                "c": 262144,
+                "dklen": 32,
+                "prf": "hmac-sha256",
+                "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "address": "008aeeda4d805471df9b2a5b0f38a0c3bcba786b",
+        "version": 3
+    }"#;
+
+    const SCRYPT_VECTOR: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "83dbcc02d8ccb40e466191a123791e0e" },
+            "ciphertext": "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 262144,
+                "p": 8,
+                "r": 1,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "address": "008aeeda4d805471df9b2a5b0f38a0c3bcba786b",
+        "version": 3
+    }"#;
+
+    const VECTOR_KEY: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+
+    // a fresh path under the temp dir, removed if a previous run left it
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("keystore-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    fn write_temp(name: &str, json: &str) -> String {
+        let path = temp_path(name);
+        fs::write(&path, json).unwrap();
+        path
+    }
+
+    fn vector_address() -> Address {
+        Address::from_slice(&hex::decode("008aeeda4d805471df9b2a5b0f38a0c3bcba786b").unwrap())
+    }
+
+    #[test]
+    fn decrypt_pbkdf2_vector() {
+        let path = write_temp("pbkdf2", PBKDF2_VECTOR);
+        let account = load_account(&path, Some("testpassword")).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(account.private_key, VECTOR_KEY);
+        assert_eq!(account.address, vector_address());
+    }
+
+    #[test]
+    fn decrypt_scrypt_vector() {
+        let path = write_temp("scrypt", SCRYPT_VECTOR);
+        let account = load_account(&path, Some("testpassword")).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(account.private_key, VECTOR_KEY);
+        assert_eq!(account.address, vector_address());
+    }
+
+    #[test]
+    fn wrong_passphrase_fails_on_mac() {
+        let path = write_temp("wrong-passphrase", PBKDF2_VECTOR);
+        let result = load_account(&path, Some("wrongpassword"));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.err(), Some("wrong passphrase or corrupted keystore".to_string()));
+    }
+
+    #[test]
+    fn address_has_to_match_the_key() {
+        let json = PBKDF2_VECTOR.replace("008aeeda4d805471df9b2a5b0f38a0c3bcba786b", "0000000000000000000000000000000000000001");
+        let path = write_temp("other-address", &json);
+        let result = load_account(&path, Some("testpassword"));
+        fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().contains("not of 0x0000000000000000000000000000000000000001"));
+    }
+
+    #[test]
+    fn encrypt_then_load() {
+        let account = new_account();
+        let key_file = encrypt_account(&account, "round trip", Kdf::Pbkdf2).unwrap();
+        let path = temp_path("round-trip");
+        save(&path, &key_file).unwrap();
+        let loaded = load_account(&path, Some("round trip"));
+        let wrong = load_account(&path, Some("round trap"));
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.address, account.address);
+        assert_eq!(loaded.private_key, account.private_key);
+        assert!(wrong.is_err());
+    }
+
+    #[test]
+    fn malformed_kdf_params_fail() {
+        let salt = "ab".to_string();
+        let scrypt = |n: u64, r: u32, p: u32| KdfParams::Scrypt { dklen: 32, n: n, r: r, p: p, salt: salt.clone() };
+        for params in &[scrypt(0, 8, 1), scrypt(1, 8, 1), scrypt(3, 8, 1), scrypt(1 << 10, 0, 1), scrypt(1 << 10, 8, 0), scrypt(1 << 40, 8, 1)] {
+            assert!(derive_key(params, "x").is_err(), "{:?}", params);
+        }
+        let pbkdf2 = KdfParams::Pbkdf2 { c: 0, dklen: 32, prf: "hmac-sha256".to_string(), salt: salt.clone() };
+        assert!(derive_key(&pbkdf2, "x").is_err());
+        let short = KdfParams::Pbkdf2 { c: 1, dklen: 16, prf: "hmac-sha256".to_string(), salt: salt.clone() };
+        assert!(derive_key(&short, "x").is_err());
+    }
+}
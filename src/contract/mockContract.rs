@@ -0,0 +1,131 @@
+use super::interface::{Answer, Response};
+use super::utils::{VoteParams, hash_header, BLOCK_COMMITTED};
+use super::voteBatcher::VoteBackend;
+
+use web3::types::{TransactionReceipt, U256};
+use web3::ethabi::{self, Token};
+use crossbeam::channel::Sender;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+// gas the mock charges, roughly a pairing check per vote
+const MOCK_TX_GAS: u64 = 60000;
+const MOCK_VOTE_GAS: u64 = 200000;
+
+// stands in for the contract behind a vote batcher, for tests and runs
+// without a main chain. Votes commit in block id order per side chain,
+// signatures are taken as valid unless rejected on purpose. A call
+// committing no block reverts
+#[derive(Clone, Default)]
+pub struct MockContract {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    block_ids: HashMap<u64, u64>,      // last committed, by sid
+    rejected: HashSet<(u64, u64)>,     // votes refused as if badly signed
+    batches: Vec<Vec<(u64, u64)>>,     // (sid, block id) of each call
+    gas_used: u64,
+    silent: bool,                      // no BlockCommitted logs, as older contracts
+}
+
+impl MockContract {
+    pub fn new() -> MockContract {
+        MockContract::default()
+    }
+
+    pub fn block_id(&self, sid: u64) -> u64 {
+        self.state.lock().unwrap().block_ids.get(&sid).cloned().unwrap_or(0)
+    }
+
+    pub fn reject(&self, sid: u64, block_id: u64) {
+        self.state.lock().unwrap().rejected.insert((sid, block_id));
+    }
+
+    pub fn batches(&self) -> Vec<Vec<(u64, u64)>> {
+        self.state.lock().unwrap().batches.clone()
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.state.lock().unwrap().gas_used
+    }
+
+    pub fn without_events(self) -> MockContract {
+        self.state.lock().unwrap().silent = true;
+        self
+    }
+}
+
+impl VoteBackend for MockContract {
+    fn submit_votes(&self, votes: &[VoteParams], answer_channel: Sender<Answer>) {
+        let mut state = self.state.lock().unwrap();
+        let mut batch = vec![];
+        let mut logs = vec![];
+        for (header, sid, block_id, _sigx, _sigy, bitset) in votes.iter() {
+            let (sid, block_id) = (sid.as_u64(), block_id.as_u64());
+            batch.push((sid, block_id));
+            let next = state.block_ids.get(&sid).cloned().unwrap_or(0) + 1;
+            if block_id != next || state.rejected.contains(&(sid, block_id)) {
+                continue;
+            }
+            state.block_ids.insert(sid, block_id);
+            logs.push(committed_log(sid, block_id, header, *bitset));
+        }
+        state.batches.push(batch);
+        let gas = MOCK_TX_GAS + MOCK_VOTE_GAS * votes.len() as u64;
+        state.gas_used += gas;
+        let succeeded = !logs.is_empty();
+        if state.silent {
+            logs.clear();
+        }
+        let receipt = mock_receipt(state.batches.len() as u64, gas, logs, succeeded);
+        answer_channel.send(Answer::Success(Response::TxReceipt(receipt)));
+    }
+}
+
+fn committed_log(sid: u64, block_id: u64, header: &str, bitset: U256) -> Value {
+    let mut topic = [0u8; 32];
+    hash_header(BLOCK_COMMITTED.as_bytes(), &mut topic);
+    let mut header_hash = [0u8; 32];
+    hash_header(&hex::decode(header.trim_start_matches("0x")).unwrap_or_default(), &mut header_hash);
+    let data = ethabi::encode(&[
+        Token::Uint(U256::from(sid)),
+        Token::Uint(U256::from(block_id)),
+        Token::FixedBytes(header_hash.to_vec()),
+        Token::FixedBytes(header_hash.to_vec()), // chain hash, not tracked
+        Token::Uint(bitset),
+    ]);
+    json!({
+        "address": format!("0x{}", hex::encode([0u8; 20])),
+        "topics": [format!("0x{}", hex::encode(topic))],
+        "data": format!("0x{}", hex::encode(data)),
+        "blockHash": null,
+        "blockNumber": null,
+        "transactionHash": null,
+        "transactionIndex": null,
+        "logIndex": null,
+        "transactionLogIndex": null,
+        "logType": null,
+        "removed": false,
+    })
+}
+
+// receipts are built from their rpc json, as a node would answer
+fn mock_receipt(nonce: u64, gas: u64, logs: Vec<Value>, succeeded: bool) -> TransactionReceipt {
+    let status = if succeeded { "0x1" } else { "0x0" };
+    serde_json::from_value(json!({
+        "transactionHash": format!("0x{:064x}", nonce),
+        "transactionIndex": "0x0",
+        "blockHash": null,
+        "blockNumber": null,
+        "cumulativeGasUsed": format!("0x{:x}", gas),
+        "gasUsed": format!("0x{:x}", gas),
+        "contractAddress": null,
+        "logs": logs,
+        "status": status,
+        "root": null,
+        "logsBloom": format!("0x{}", hex::encode(vec![0u8; 256])),
+    })).expect("mock receipt")
+}
@@ -1,6 +1,12 @@
 pub mod contract;
+pub mod events;
+pub mod gas;
 pub mod interface;
+pub mod keystore;
+pub mod mockContract;
+pub mod txManager;
 pub mod utils;
+pub mod voteBatcher;
 
 use super::primitive;
 use super::network;
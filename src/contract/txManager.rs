@@ -0,0 +1,357 @@
+use super::interface::{Answer, Response, Error};
+use super::contract::Account;
+use super::gas::{GasConfig, FeeMode, sign_tx};
+
+use web3::types::{Address, Bytes, U256, TransactionReceipt, BlockNumber, CallRequest};
+use web3::futures::Future;
+
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam::channel::{self, Sender, Receiver, RecvTimeoutError};
+use log::{info, warn};
+use crate::experiment::snapshot::PERFORMANCE_COUNTER;
+
+// a contract call changing state, sent on chain by the tx manager
+pub struct TxRequest {
+    pub label: &'static str, // contract function, for logs
+    pub data: Vec<u8>,       // abi encoded call
+    pub gas: Option<U256>,   // own gas limit, else by the gas limit policy
+    pub vote_of: Option<usize>, // block id of a vote, for telemetry
+    pub answer_channel: Option<Sender<Answer>>, // final receipt or failure
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TxConfig {
+    pub confirmations: u64,      // blocks from the one including a tx to the tip
+    pub poll: Duration,          // between receipt checks
+    pub stuck_timeout: Duration, // without receipt before the gas price is bumped
+    pub bump_percent: u64,       // geth takes a replacement from 10% more
+    pub max_pending: usize,      // txs sent but not final at once
+    pub max_backoff: Duration,   // longest pause after the node failed to answer
+}
+
+impl Default for TxConfig {
+    fn default() -> Self {
+        TxConfig {
+            confirmations: 1,
+            poll: Duration::from_secs(1),
+            stuck_timeout: Duration::from_secs(30),
+            bump_percent: 20,
+            max_pending: 16,
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+// a write holding a nonce, with the hash of every replacement sent
+struct Pending {
+    write: TxRequest,
+    nonce: U256,
+    gas: U256,
+    fees: FeeMode,
+    hashes: Vec<web3::types::H256>,
+    sent: Instant,
+}
+
+// owns the nonces of the account, writes are sent in order with locally
+// allocated nonces and followed until final
+pub struct TxManager {
+    web3: web3::api::Web3<web3::transports::Http>,
+    account: Account,
+    contract_address: Address,
+    gas_config: GasConfig,
+    config: TxConfig,
+    next_nonce: Option<U256>, // None until read from the pending count of the chain
+    queue: VecDeque<TxRequest>,
+    pending: Vec<Pending>, // by nonce
+    backoff: Duration,       // doubled on every transient failure in a row
+    resume_at: Option<Instant>, // no write is sent before
+}
+
+impl TxManager {
+    pub fn new(
+        rpc_url: &str,
+        account: Account,
+        contract_address: Address,
+        gas_config: GasConfig,
+        config: TxConfig,
+    ) -> TxManager {
+        let (eloop, http) = web3::transports::Http::new(rpc_url).unwrap();
+        eloop.into_remote();
+        let web3 = web3::api::Web3::new(http);
+        TxManager {
+            web3: web3,
+            account: account,
+            contract_address: contract_address,
+            gas_config: gas_config,
+            config: config,
+            next_nonce: None,
+            queue: VecDeque::new(),
+            pending: vec![],
+            backoff: config.poll,
+            resume_at: None,
+        }
+    }
+
+    pub fn start(mut self) -> Sender<TxRequest> {
+        let (write_tx, write_rx) = channel::unbounded();
+        let _ = thread::Builder::new()
+            .name("tx-manager".to_string())
+            .spawn(move || {
+                loop {
+                    if !self.receive(&write_rx) {
+                        return;
+                    }
+                    self.send_queued();
+                    self.check_pending();
+                }
+            });
+        write_tx
+    }
+
+    // false once no write can come any more and none is left
+    fn receive(&mut self, write_rx: &Receiver<TxRequest>) -> bool {
+        match write_rx.recv_timeout(self.config.poll) {
+            Ok(write) => self.queue.push_back(write),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                if self.queue.is_empty() && self.pending.is_empty() {
+                    return false;
+                }
+                thread::sleep(self.config.poll);
+            },
+        }
+        while let Ok(write) = write_rx.try_recv() {
+            self.queue.push_back(write);
+        }
+        true
+    }
+
+    fn allocate_nonce(&mut self) -> Result<U256, Error> {
+        match self.next_nonce {
+            Some(nonce) => Ok(nonce),
+            None => {
+                let nonce = self.web3.eth()
+                    .transaction_count(self.account.address, Some(BlockNumber::Pending))
+                    .wait()?;
+                self.next_nonce = Some(nonce);
+                Ok(nonce)
+            },
+        }
+    }
+
+    fn send(&self, pending: &Pending, fees: &FeeMode) -> Result<web3::types::H256, Error> {
+        let signed_tx = sign_tx(
+            fees,
+            self.gas_config.chain_id,
+            pending.nonce,
+            self.contract_address,
+            pending.gas,
+            &pending.write.data,
+            &self.account.private_key,
+        ).map_err(Error::Rejected)?;
+        self.web3.eth()
+            .send_raw_transaction(Bytes::from(signed_tx))
+            .wait()
+            .map_err(Error::from)
+    }
+
+    fn gas_limit(&self, write: &TxRequest) -> Result<U256, Error> {
+        if let Some(gas) = write.gas {
+            return Ok(gas);
+        }
+        let call_request = CallRequest {
+            from: Some(self.account.address),
+            to: self.contract_address,
+            gas_price: None,
+            gas: None,
+            data: Some(Bytes::from(write.data.clone())),
+            value: None,
+        };
+        let estimate = self.web3.eth()
+            .estimate_gas(call_request, None)
+            .wait()?;
+        Ok(self.gas_config.limit.from_estimate(estimate))
+    }
+
+    // the write goes back to the head of the queue, and nothing is sent
+    // until the node had time to recover
+    fn back_off(&mut self, write: TxRequest) {
+        warn!("{} requeued, retry in {:?}", write.label, self.backoff);
+        self.queue.push_front(write);
+        self.resume_at = Some(Instant::now() + self.backoff);
+        self.backoff = (self.backoff * 2).min(self.config.max_backoff);
+    }
+
+    fn send_queued(&mut self) {
+        if let Some(resume_at) = self.resume_at {
+            if Instant::now() < resume_at {
+                return;
+            }
+            self.resume_at = None;
+        }
+        while self.pending.len() < self.config.max_pending {
+            let write = match self.queue.pop_front() {
+                Some(w) => w,
+                None => return,
+            };
+            let nonce = match self.allocate_nonce() {
+                Ok(n) => n,
+                Err(e) => {
+                    warn!("unable to read nonce of {:?}: {}", self.account.address, e);
+                    self.back_off(write);
+                    return;
+                },
+            };
+            let gas = match self.gas_limit(&write) {
+                Ok(gas) => gas,
+                Err(e) if e.is_transient() => {
+                    warn!("{} gas not estimated: {}", write.label, e);
+                    self.back_off(write);
+                    return;
+                },
+                Err(e) => {
+                    // a call that would revert fails its estimate
+                    warn!("{} gas not estimated: {}", write.label, e);
+                    if let Some(ch) = &write.answer_channel {
+                        ch.send(Answer::Fail(e));
+                    }
+                    continue;
+                },
+            };
+            let mut pending = Pending {
+                write: write,
+                nonce: nonce,
+                gas: gas,
+                fees: self.gas_config.fees,
+                hashes: vec![],
+                sent: Instant::now(),
+            };
+            match self.send(&pending, &pending.fees) {
+                Ok(hash) => {
+                    info!("{} sent with nonce {} as {:?}", pending.write.label, nonce, hash);
+                    self.next_nonce = Some(nonce + 1);
+                    self.backoff = self.config.poll;
+                    pending.hashes.push(hash);
+                    self.pending.push(pending);
+                },
+                Err(e) => {
+                    // the nonce is unused unless the chain says otherwise, read
+                    // it again before the next write
+                    self.next_nonce = None;
+                    let write = pending.write;
+                    if let Error::NonceConflict(_) = e {
+                        warn!("{} nonce {} taken, retry with a fresh one", write.label, nonce);
+                        self.queue.push_front(write);
+                        return;
+                    }
+                    // the node may have gone away for a moment
+                    if e.is_transient() {
+                        warn!("{} not sent: {}", write.label, e);
+                        self.back_off(write);
+                        return;
+                    }
+                    warn!("{} not sent: {}", write.label, e);
+                    if let Some(ch) = &write.answer_channel {
+                        ch.send(Answer::Fail(e));
+                    }
+                },
+            }
+        }
+    }
+
+    fn find_receipt(&self, pending: &Pending) -> Option<TransactionReceipt> {
+        pending.hashes.
+            iter().
+            rev().
+            filter_map(|hash| self.web3.eth().transaction_receipt(*hash).wait().ok().flatten()).
+            next()
+    }
+
+    fn check_pending(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let tip = match self.web3.eth().block_number().wait() {
+            Ok(n) => n.as_u64(),
+            Err(e) => {
+                warn!("unable to read main chain height: {:?}", e);
+                return;
+            },
+        };
+        let mut i = 0;
+        while i < self.pending.len() {
+            match self.find_receipt(&self.pending[i]) {
+                Some(receipt) => {
+                    // a reorg may take the receipt back until it is deep enough
+                    let mined = receipt.block_number.map(|n| n.as_u64());
+                    if let Some(mined) = mined {
+                        if tip + 1 >= mined + self.config.confirmations {
+                            let pending = self.pending.remove(i);
+                            self.finish(pending, receipt);
+                            continue;
+                        }
+                    }
+                },
+                None => {
+                    if self.pending[i].sent.elapsed() >= self.config.stuck_timeout {
+                        self.bump(i);
+                    }
+                },
+            }
+            i += 1;
+        }
+    }
+
+    // replace a stuck tx by the same nonce at higher fees
+    fn bump(&mut self, i: usize) {
+        let nonce = self.pending[i].nonce;
+        let fees = match self.pending[i].fees.bump(self.config.bump_percent, self.gas_config.max_gas_price) {
+            Some(fees) => fees,
+            None => {
+                warn!("{} with nonce {} stuck at max gas price", self.pending[i].write.label, nonce);
+                self.pending[i].sent = Instant::now();
+                return;
+            },
+        };
+        let result = self.send(&self.pending[i], &fees);
+        let pending = &mut self.pending[i];
+        pending.sent = Instant::now();
+        match result {
+            Ok(hash) => {
+                info!("{} with nonce {} replaced at {:?} as {:?}", pending.write.label, nonce, fees, hash);
+                pending.fees = fees;
+                pending.hashes.push(hash);
+            },
+            // an earlier hash may be mined by now, the receipt tells next time
+            Err(e) => warn!("{} with nonce {} not replaced: {}", pending.write.label, nonce, e),
+        }
+    }
+
+    fn finish(&self, pending: Pending, receipt: TransactionReceipt) {
+        if let Some(gas) = receipt.gas_used {
+            PERFORMANCE_COUNTER.record_gas_used(pending.write.label, gas.as_usize());
+        }
+        let success = receipt.status.map_or(true, |s| s.as_u64() == 1);
+        let answer = if success {
+            if let Some(block_id) = pending.write.vote_of {
+                PERFORMANCE_COUNTER.record_submit_block_stop(block_id);
+            }
+            info!("{} with nonce {} confirmed in {:?}", pending.write.label, pending.nonce, receipt.transaction_hash);
+            Answer::Success(Response::TxReceipt(receipt))
+        } else {
+            warn!("{} with nonce {} reverted in {:?}", pending.write.label, pending.nonce, receipt.transaction_hash);
+            // a failed tx using all of its gas ran out of it
+            let reason = format!("{} in {:?}", pending.write.label, receipt.transaction_hash);
+            if receipt.gas_used == Some(pending.gas) {
+                Answer::Fail(Error::OutOfGas(reason))
+            } else {
+                Answer::Fail(Error::Reverted(reason))
+            }
+        };
+        if let Some(ch) = &pending.write.answer_channel {
+            ch.send(answer);
+        }
+    }
+}
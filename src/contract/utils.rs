@@ -1,9 +1,12 @@
-use std::process::Command;
-use web3::types::{Address, U256};
+use std::process::{Command, Stdio};
+use std::io::Write;
+use web3::types::{Address, U256, Log, H256};
+use web3::ethabi::{self, ParamType, Token};
 
 use crypto::sha3::Sha3;
 use crypto::digest::Digest;
 use secp256k1::{Secp256k1, SecretKey};
+use secp256k1::recovery::{RecoverableSignature, RecoveryId};
 use crate::primitive::block::Block;
 use bincode::{deserialize};
 use serde::{Serialize, Deserialize};
@@ -18,7 +21,7 @@ pub struct BLSKeyStr{
     pub pky2: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct BLSKey{
     pub sk: U256,
     pub pkx1: U256,
@@ -99,6 +102,57 @@ pub fn _encode_submitVote(block: String, sid: U256, bid: U256, sigx: U256, sigy:
     return function_abi;
 }
 
+const SUBMIT_VOTES: &str = "submitVotes(bytes[],uint256[],uint256[],uint256[],uint256[],uint256[])";
+pub const BLOCK_COMMITTED: &str = "BlockCommitted(uint256,uint256,bytes32,bytes32,uint256)";
+
+// a vote as taken by submitVote: header, sid, bid, sigx, sigy, bitset
+pub type VoteParams = (String, U256, U256, U256, U256, U256);
+
+// one array per submitVote parameter, the i-th entries make the i-th vote
+pub fn _encode_submitVotes(votes: &[VoteParams]) -> Vec<u8> {
+    let uints = |param: fn(&VoteParams) -> U256| Token::Array(
+        votes.iter().map(|v| Token::Uint(param(v))).collect()
+    );
+    let headers = Token::Array(votes.
+        iter().
+        map(|v| Token::Bytes(hex::decode(v.0.trim_start_matches("0x")).expect("header not hex"))).
+        collect());
+    let mut selector = [0u8; 32];
+    hash_header(SUBMIT_VOTES.as_bytes(), &mut selector);
+    let mut function_abi = selector[0..4].to_vec();
+    function_abi.extend(ethabi::encode(&[
+        headers,
+        uints(|v| v.1),
+        uints(|v| v.2),
+        uints(|v| v.3),
+        uints(|v| v.4),
+        uints(|v| v.5),
+    ]));
+    function_abi
+}
+
+// (sid, block id) of the BlockCommitted logs of a receipt, the votes of a
+// batch left out were rejected without reverting the others
+pub fn _decode_committed(logs: &[Log]) -> Vec<(u64, u64)> {
+    let mut topic = [0u8; 32];
+    hash_header(BLOCK_COMMITTED.as_bytes(), &mut topic);
+    let topic = H256::from(topic);
+    let params = [
+        ParamType::Uint(256), ParamType::Uint(256),
+        ParamType::FixedBytes(32), ParamType::FixedBytes(32),
+        ParamType::Uint(256),
+    ];
+    logs.
+        iter().
+        filter(|log| log.topics.get(0) == Some(&topic)).
+        filter_map(|log| ethabi::decode(&params, &log.data.0).ok()).
+        filter_map(|tokens| match (&tokens[0], &tokens[1]) {
+            (Token::Uint(sid), Token::Uint(bid)) => Some((sid.as_u64(), bid.as_u64())),
+            _ => None,
+        }).
+        collect()
+}
+
 pub fn _encode_resetSideChain(sid: U256) -> Vec<u8> {
     let command = format!("./ethabi encode function --lenient ./abi.json resetSideChain -p {}", sid);
     let output = Command::new("sh").arg("-c")
@@ -137,12 +191,16 @@ pub fn _decode_sendBlock(input: &str) -> (String, usize) {
     (block, block_id)
 }
 
-pub fn _sign_bls(msg: String, key_file: String, bin_path: &str) -> (String, String) {
-    let command = bin_path.to_string() + &format!( "/sign -msg={} -key={}", msg, key_file);
-    //info!("command {}", command.clone());
-    let output = Command::new("sh").arg("-c")
-        .arg(command)
-        .output().unwrap();
+// the key goes to the binary on stdin, so the secret never touches the disk
+pub fn _sign_bls(msg: String, key: &BLSKeyStr, bin_path: &str) -> (String, String) {
+    let mut child = Command::new(format!("{}/sign", bin_path))
+        .arg(format!("-msg={}", msg))
+        .arg("-key=-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(&serde_json::to_vec(key).unwrap()).unwrap();
+    let output = child.wait_with_output().unwrap();
 
     //let function_abi = hex::decode(std::str::from_utf8(&output.stdout).unwrap().trim()).unwrap();
     let sig = std::str::from_utf8(&output.stdout).unwrap().split("\n");
@@ -151,6 +209,38 @@ pub fn _sign_bls(msg: String, key_file: String, bin_path: &str) -> (String, Stri
 
 }
 
+// pairing check of the binary, msg_hash is the keccak of the signed message
+// and pub_key the pkx1, pkx2, pky1, pky2 of the signer
+pub fn _verify_bls(msg_hash: &[u8; 32], sigx: &str, sigy: &str, pub_key: &(U256, U256, U256, U256), bin_path: &str) -> bool {
+    let (pkx1, pkx2, pky1, pky2) = pub_key;
+    let output = Command::new(format!("{}/sign", bin_path))
+        .arg("-verify")
+        .arg(format!("-hash={}", hex::encode(msg_hash)))
+        .arg(format!("-sigx={}", sigx))
+        .arg(format!("-sigy={}", sigy))
+        .arg(format!("-pkx1={}", pkx1))
+        .arg(format!("-pkx2={}", pkx2))
+        .arg(format!("-pky1={}", pky1))
+        .arg(format!("-pky2={}", pky2))
+        .output();
+    match output {
+        Ok(output) => std::str::from_utf8(&output.stdout).map_or(false, |out| out.trim() == "true"),
+        Err(e) => {
+            warn!("unable to run {}/sign: {}", bin_path, e);
+            false
+        },
+    }
+}
+
+// fresh bls key of the binary, printed to stdout
+pub fn _gen_bls(bin_path: &str) -> Option<BLSKeyStr> {
+    let output = Command::new(format!("{}/sign", bin_path))
+        .arg("-gen")
+        .arg("-key=-")
+        .output().ok()?;
+    serde_json::from_slice(&output.stdout).ok()
+}
+
 //pub fn _aggregate_sig(x1: String, y1: String, x2: String, y2: String)-> (String, String) {
     //let command = format!("./aggregate -x1={} -y1={} -x2={} -y2={}", x1, y1, x2, y2);
     //println!("command {}", command.clone());
@@ -221,6 +311,33 @@ pub fn _sign_block(block: &str, private_key: &[u8]) -> String {
     return format!("{}{}{}", hex::encode(r), hex::encode(s), hex::encode([v.to_i32() as u8 + 27]));
 }
 
+// recoverable ecdsa signature of a 32 bytes digest, r || s || v
+pub fn sign_digest(digest: &[u8], private_key: &[u8]) -> Option<Vec<u8>> {
+    let secp = Secp256k1::new();
+    let sk = SecretKey::from_slice(private_key).ok()?;
+    let msg = secp256k1::Message::from_slice(digest).ok()?;
+    let sig = secp.sign_recoverable(&msg, &sk);
+    let (v, data) = sig.serialize_compact();
+    let mut signature = data.to_vec();
+    signature.push(v.to_i32() as u8);
+    Some(signature)
+}
+
+// eth address of the key that signed the digest
+pub fn recover_address(digest: &[u8], signature: &[u8]) -> Option<Address> {
+    if signature.len() != 65 {
+        return None;
+    }
+    let secp = Secp256k1::new();
+    let msg = secp256k1::Message::from_slice(digest).ok()?;
+    let v = RecoveryId::from_i32(signature[64] as i32).ok()?;
+    let sig = RecoverableSignature::from_compact(&signature[0..64], v).ok()?;
+    let pk = secp.recover(&msg, &sig).ok()?;
+    let mut hash = [0u8; 32];
+    hash_header(&pk.serialize_uncompressed()[1..], &mut hash);
+    Some(Address::from_slice(&hash[12..]))
+}
+
 pub fn _convert_u256(value: U256) -> ethereum_types::U256 {
     let U256(ref arr) = value;
     let mut ret = [0; 4];
@@ -0,0 +1,387 @@
+use super::interface::{Answer, Response, Error};
+use super::txManager::TxRequest;
+use super::utils::{VoteParams, _encode_submitVotes, _decode_committed};
+
+use web3::types::U256;
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam::channel::{self, Sender, Receiver, RecvTimeoutError, TryRecvError};
+use log::{info, warn};
+use crate::experiment::snapshot::PERFORMANCE_COUNTER;
+
+// gas limit of a submitVote, a batch gets as much per vote
+pub const VOTE_GAS: u64 = 950000;
+// gas limit of a main chain block, no tx may ask for more
+pub const BLOCK_GAS_LIMIT: u64 = 30000000;
+
+// a vote waiting for its batch
+pub struct VoteCall {
+    pub params: VoteParams,
+    pub answer_channel: Option<Sender<Answer>>, // outcome of this block only
+}
+
+impl VoteCall {
+    fn sid(&self) -> u64 {
+        self.params.1.as_u64()
+    }
+
+    fn block_id(&self) -> u64 {
+        self.params.2.as_u64()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    pub max_votes: usize,    // votes per submitVotes call, 1 sends each by submitVote
+    pub max_delay: Duration, // a vote waits at most this long for others
+    pub poll: Duration,      // between checks of batches sent
+    pub max_gas: u64,        // gas of a whole batch, bounds max_votes
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_votes: 1,
+            max_delay: Duration::from_secs(2),
+            poll: Duration::from_millis(100),
+            max_gas: BLOCK_GAS_LIMIT,
+        }
+    }
+}
+
+impl BatchConfig {
+    // votes in a batch whose gas still fits in max_gas, at least one
+    pub fn batch_size(&self) -> usize {
+        let by_gas = (self.max_gas / VOTE_GAS).max(1) as usize;
+        self.max_votes.min(by_gas).max(1)
+    }
+}
+
+// where batches go, the contract through the tx manager or a mock of it
+pub trait VoteBackend: Send + 'static {
+    // one call for all votes, answered once final with a receipt holding a
+    // BlockCommitted log per committed vote
+    fn submit_votes(&self, votes: &[VoteParams], answer_channel: Sender<Answer>);
+}
+
+impl VoteBackend for Sender<TxRequest> {
+    fn submit_votes(&self, votes: &[VoteParams], answer_channel: Sender<Answer>) {
+        let request = TxRequest {
+            label: "submitVotes",
+            data: _encode_submitVotes(votes),
+            gas: Some(U256::from(VOTE_GAS) * U256::from(votes.len())),
+            vote_of: None,
+            answer_channel: Some(answer_channel),
+        };
+        // the dropped answer channel fails the batch
+        if self.send(request).is_err() {
+            warn!("tx manager stopped, submitVotes dropped");
+        }
+    }
+}
+
+struct InFlight {
+    votes: Vec<VoteCall>,
+    answer: Receiver<Answer>,
+}
+
+// buffers ready votes and commits them in one contract call once enough are
+// there or the oldest ran out of its latency budget
+pub struct VoteBatcher<B: VoteBackend> {
+    backend: B,
+    config: BatchConfig,
+    buffer: Vec<(Instant, VoteCall)>, // by arrival
+    in_flight: Vec<InFlight>,
+}
+
+impl<B: VoteBackend> VoteBatcher<B> {
+    pub fn new(backend: B, config: BatchConfig) -> VoteBatcher<B> {
+        VoteBatcher {
+            backend: backend,
+            config: config,
+            buffer: vec![],
+            in_flight: vec![],
+        }
+    }
+
+    pub fn start(mut self) -> Sender<VoteCall> {
+        let (vote_tx, vote_rx) = channel::unbounded();
+        let _ = thread::Builder::new()
+            .name("vote-batcher".to_string())
+            .spawn(move || {
+                loop {
+                    if !self.receive(&vote_rx) {
+                        return;
+                    }
+                    self.flush_due();
+                    self.check_in_flight();
+                }
+            });
+        vote_tx
+    }
+
+    // false once no vote can come any more and none is left
+    fn receive(&mut self, vote_rx: &Receiver<VoteCall>) -> bool {
+        let timeout = match self.buffer.first() {
+            Some((arrived, _)) => self.config.
+                max_delay.
+                checked_sub(arrived.elapsed()).
+                unwrap_or(Duration::from_millis(0)).
+                min(self.config.poll),
+            None => self.config.poll,
+        };
+        match vote_rx.recv_timeout(timeout) {
+            Ok(vote) => self.buffer.push((Instant::now(), vote)),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                // nothing to wait for any more
+                while !self.buffer.is_empty() {
+                    self.send_batch();
+                }
+                if self.in_flight.is_empty() {
+                    return false;
+                }
+                thread::sleep(self.config.poll);
+            },
+        }
+        while let Ok(vote) = vote_rx.try_recv() {
+            self.buffer.push((Instant::now(), vote));
+        }
+        true
+    }
+
+    fn flush_due(&mut self) {
+        loop {
+            let full = self.buffer.len() >= self.config.batch_size();
+            let expired = match self.buffer.first() {
+                Some((arrived, _)) => arrived.elapsed() >= self.config.max_delay,
+                None => false,
+            };
+            if !full && !expired {
+                return;
+            }
+            self.send_batch();
+        }
+    }
+
+    fn send_batch(&mut self) {
+        let n = self.buffer.len().min(self.config.batch_size());
+        let mut votes: Vec<VoteCall> = self.buffer.drain(..n).map(|(_, vote)| vote).collect();
+        // the contract takes the blocks of a side chain in order
+        votes.sort_by_key(|v| (v.sid(), v.block_id()));
+        let params: Vec<VoteParams> = votes.iter().map(|v| v.params.clone()).collect();
+        let (answer_tx, answer_rx) = channel::bounded(1);
+        info!("submit votes of {:?} in one batch", votes.iter().map(|v| (v.sid(), v.block_id())).collect::<Vec<_>>());
+        self.backend.submit_votes(&params, answer_tx);
+        self.in_flight.push(InFlight {
+            votes: votes,
+            answer: answer_rx,
+        });
+    }
+
+    fn check_in_flight(&mut self) {
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            let answer = match self.in_flight[i].answer.try_recv() {
+                Ok(answer) => answer,
+                Err(TryRecvError::Empty) => {
+                    i += 1;
+                    continue;
+                },
+                Err(TryRecvError::Disconnected) => Answer::Fail(Error::Stopped),
+            };
+            let batch = self.in_flight.remove(i);
+            split(batch.votes, answer);
+        }
+    }
+}
+
+// per block outcomes of a final batch
+fn split(votes: Vec<VoteCall>, answer: Answer) {
+    let receipt = match answer {
+        Answer::Success(Response::TxReceipt(receipt)) => receipt,
+        Answer::Success(_) => {
+            fail_all(votes, Error::Decode("unexpected answer to submitVotes".to_string()));
+            return;
+        },
+        Answer::Fail(reason) => {
+            fail_all(votes, reason);
+            return;
+        },
+    };
+    PERFORMANCE_COUNTER.record_vote_batch(votes.len());
+    let committed = _decode_committed(&receipt.logs);
+    // a contract without the event tells the outcome of the whole tx only
+    let succeeded = receipt.status.map_or(true, |s| s.as_u64() == 1);
+    if committed.is_empty() && !succeeded {
+        fail_all(votes, Error::Reverted(format!("submitVotes in {:?}", receipt.transaction_hash)));
+        return;
+    }
+    for vote in votes {
+        let (sid, block_id) = (vote.sid(), vote.block_id());
+        let outcome = if committed.contains(&(sid, block_id)) || committed.is_empty() {
+            PERFORMANCE_COUNTER.record_submit_block_stop(block_id as usize);
+            Answer::Success(Response::TxReceipt(receipt.clone()))
+        } else {
+            warn!("block {} of side chain {} not committed by {:?}", block_id, sid, receipt.transaction_hash);
+            Answer::Fail(Error::Rejected(format!("block {} of side chain {} not committed by {:?}", block_id, sid, receipt.transaction_hash)))
+        };
+        if let Some(ch) = vote.answer_channel {
+            ch.send(outcome);
+        }
+    }
+}
+
+fn fail_all(votes: Vec<VoteCall>, reason: Error) {
+    warn!("batch of {} votes failed: {}", votes.len(), reason);
+    for vote in votes {
+        if let Some(ch) = vote.answer_channel {
+            ch.send(Answer::Fail(reason.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mockContract::MockContract;
+
+    fn vote(sid: u64, block_id: u64) -> (VoteCall, Receiver<Answer>) {
+        let (answer_tx, answer_rx) = channel::bounded(1);
+        let params = (
+            format!("0x{:02x}", block_id),
+            U256::from(sid),
+            U256::from(block_id),
+            U256::zero(),
+            U256::zero(),
+            U256::from(1),
+        );
+        let call = VoteCall {
+            params: params,
+            answer_channel: Some(answer_tx),
+        };
+        (call, answer_rx)
+    }
+
+    fn config(max_votes: usize, max_delay: Duration) -> BatchConfig {
+        BatchConfig {
+            max_votes: max_votes,
+            max_delay: max_delay,
+            poll: Duration::from_millis(10),
+            max_gas: BLOCK_GAS_LIMIT,
+        }
+    }
+
+    fn committed(answer_rx: &Receiver<Answer>) -> bool {
+        match answer_rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(Answer::Success(Response::TxReceipt(_))) => true,
+            Ok(_) => false,
+            Err(_) => panic!("vote not answered"),
+        }
+    }
+
+    #[test]
+    fn batch_at_size_cap() {
+        let mock = MockContract::new();
+        let votes = VoteBatcher::new(mock.clone(), config(3, Duration::from_secs(60))).start();
+        let answers: Vec<Receiver<Answer>> = (1..=4).
+            map(|block_id| {
+                let (call, answer_rx) = vote(0, block_id);
+                votes.send(call).unwrap();
+                answer_rx
+            }).
+            collect();
+        // the fourth vote waits for its latency budget, far away
+        for answer_rx in answers[0..3].iter() {
+            assert!(committed(answer_rx));
+        }
+        assert_eq!(mock.batches(), vec![vec![(0, 1), (0, 2), (0, 3)]]);
+        assert!(answers[3].try_recv().is_err());
+    }
+
+    #[test]
+    fn batch_capped_by_gas() {
+        let mut config = config(100, Duration::from_secs(60));
+        assert_eq!(config.batch_size(), (BLOCK_GAS_LIMIT / VOTE_GAS) as usize);
+        config.max_gas = VOTE_GAS * 2;
+        assert_eq!(config.batch_size(), 2);
+        let mock = MockContract::new();
+        let votes = VoteBatcher::new(mock.clone(), config).start();
+        let answers: Vec<Receiver<Answer>> = (1..=2).
+            map(|block_id| {
+                let (call, answer_rx) = vote(0, block_id);
+                votes.send(call).unwrap();
+                answer_rx
+            }).
+            collect();
+        for answer_rx in answers.iter() {
+            assert!(committed(answer_rx));
+        }
+        assert_eq!(mock.batches(), vec![vec![(0, 1), (0, 2)]]);
+    }
+
+    #[test]
+    fn flush_at_latency_budget() {
+        let mock = MockContract::new();
+        let delay = Duration::from_millis(100);
+        let votes = VoteBatcher::new(mock.clone(), config(10, delay)).start();
+        let start = Instant::now();
+        let (call, answer_rx) = vote(0, 1);
+        votes.send(call).unwrap();
+        assert!(committed(&answer_rx));
+        assert!(start.elapsed() >= delay);
+        assert_eq!(mock.batches(), vec![vec![(0, 1)]]);
+    }
+
+    #[test]
+    fn split_receipt_per_vote() {
+        let mock = MockContract::new();
+        mock.reject(1, 1);
+        let votes = VoteBatcher::new(mock.clone(), config(3, Duration::from_secs(60))).start();
+        // sorted by side chain and block id before the call
+        let (a, a_rx) = vote(0, 2);
+        let (b, b_rx) = vote(1, 1);
+        let (c, c_rx) = vote(0, 1);
+        for call in vec![a, b, c] {
+            votes.send(call).unwrap();
+        }
+        assert!(committed(&a_rx));
+        assert!(!committed(&b_rx));
+        assert!(committed(&c_rx));
+        assert_eq!(mock.batches(), vec![vec![(0, 1), (0, 2), (1, 1)]]);
+        assert_eq!(mock.block_id(0), 2);
+        assert_eq!(mock.block_id(1), 0);
+    }
+
+    #[test]
+    fn rejected_batch() {
+        let mock = MockContract::new();
+        mock.reject(0, 1);
+        let votes = VoteBatcher::new(mock.clone(), config(2, Duration::from_secs(60))).start();
+        // block 2 cannot commit before block 1, so nothing does
+        let (a, a_rx) = vote(0, 1);
+        let (b, b_rx) = vote(0, 2);
+        votes.send(a).unwrap();
+        votes.send(b).unwrap();
+        for answer_rx in vec![a_rx, b_rx] {
+            match answer_rx.recv_timeout(Duration::from_secs(5)) {
+                Ok(Answer::Fail(Error::Reverted(_))) => (),
+                _ => panic!("expected the batch to revert"),
+            }
+        }
+        assert_eq!(mock.block_id(0), 0);
+    }
+
+    #[test]
+    fn receipt_status_without_events() {
+        let mock = MockContract::new().without_events();
+        let votes = VoteBatcher::new(mock.clone(), config(2, Duration::from_secs(60))).start();
+        let (a, a_rx) = vote(0, 1);
+        let (b, b_rx) = vote(0, 2);
+        votes.send(a).unwrap();
+        votes.send(b).unwrap();
+        assert!(committed(&a_rx));
+        assert!(committed(&b_rx));
+        assert_eq!(mock.block_id(0), 2);
+    }
+}
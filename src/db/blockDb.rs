@@ -5,15 +5,16 @@ use std::collections::{HashMap, VecDeque};
 use super::cmtda::H256 as CMTH256;
 use chain::block::Block as SBlock;
 use chain::constants::{NUM_BASE_SYMBOL};
-use super::network::message::{Samples};
+use super::network::message::{Samples, Evidence};
 use rocksdb::{self, ColumnFamilyDescriptor, Options, SliceTransform, DB};
 use bincode::{deserialize, serialize};
 
 const SYMBOL_CF: &str = "SYMBOL";
 const BLOCK_CF: &str = "BLOCK";
+const EVIDENCE_CF: &str = "EVIDENCE";
 
 pub struct BlockDb {
-    pub block_record: VecDeque<u64>, // hack for reducing storage
+    pub block_record: VecDeque<(u64, u64)>, // (sid, block id), hack for reducing storage
     pub thresh: usize,
     pub num_sample: u64, // used by scale node
     pub num_block: u64,
@@ -27,7 +28,8 @@ impl BlockDb {
         DB::destroy(&Options::default(), &path).unwrap();
         //let block_cf = ColumnFamilyDescriptor::new(BLOCK_CF, Options::default());
         let symbol_cf = ColumnFamilyDescriptor::new(SYMBOL_CF, Options::default());
-        let cfs = vec![symbol_cf];
+        let evidence_cf = ColumnFamilyDescriptor::new(EVIDENCE_CF, Options::default());
+        let cfs = vec![symbol_cf, evidence_cf];
         let mut opts = Options::default();
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
@@ -42,17 +44,18 @@ impl BlockDb {
         }  
     }
     
-    pub fn insert_sblock(&mut self, block_id: u64, sblock: SBlock){
+    // samples and blocks are keyed by (sid, block id), side chains share the db
+    pub fn insert_sblock(&mut self, sid: u64, block_id: u64, sblock: SBlock){
         self.num_block += 1;
         //let block_cf = self.db.cf_handle(BLOCK_CF).unwrap();
         //let serialized = serialize(&sblock).unwrap();
-        //let block_id = serialize(&block_id).unwrap();
+        //let block_id = serialize(&(sid, block_id)).unwrap();
         //self.db.put_cf(block_cf, &block_id, &serialized).unwrap(); 
     }
 
-    pub fn get_sblock(&mut self, block_id: u64) -> Option<SBlock>{
+    pub fn get_sblock(&mut self, sid: u64, block_id: u64) -> Option<SBlock>{
         //let block_cf = self.db.cf_handle(BLOCK_CF).unwrap();
-        //let block_id = serialize(&block_id).unwrap();
+        //let block_id = serialize(&(sid, block_id)).unwrap();
         //let serialized = self.db.get_pinned_cf(block_cf, &block_id).unwrap();
         //match serialized {
             //Some(block) => Some(deserialize(&block).unwrap()),
@@ -62,26 +65,26 @@ impl BlockDb {
     }
 
     // return if there is redundant elements
-    pub fn insert_cmt_sample(&mut self, block_id: u64 , chunk: &Samples) -> bool {
+    pub fn insert_cmt_sample(&mut self, sid: u64, block_id: u64 , chunk: &Samples) -> bool {
         let symbol_cf = self.db.cf_handle(SYMBOL_CF).unwrap();
         let serialized = serialize(&chunk).unwrap();
-        let key = serialize(&block_id).unwrap();
+        let key = serialize(&(sid, block_id)).unwrap();
         self.db.put_cf(symbol_cf, &key, &serialized).unwrap(); 
         self.num_sample += 1;
-        self.block_record.push_back(block_id);
+        self.block_record.push_back((sid, block_id));
         // remove one block for saving storage
         if self.block_record.len() > self.thresh {
             let id = self.block_record.pop_front().unwrap();
             let id = serialize(&id).unwrap();
-            self.db.delete(&id);
+            self.db.delete_cf(symbol_cf, &id);
         }
         info!("curr staroge size {}", self.block_record.len());
         true
     }
 
-    pub fn get_chunk(&self, block_id: u64) -> Option<Samples> {
+    pub fn get_chunk(&self, sid: u64, block_id: u64) -> Option<Samples> {
         let symbol_cf = self.db.cf_handle(SYMBOL_CF).unwrap();
-        let block_id = serialize(&block_id).unwrap();
+        let block_id = serialize(&(sid, block_id)).unwrap();
         let serialized = self.db.get_pinned_cf(symbol_cf, &block_id).unwrap();
         match serialized {
             Some(chunk) => Some(deserialize(&chunk).unwrap()),
@@ -89,6 +92,30 @@ impl BlockDb {
         }
     }
 
+    // one evidence per offender and slot, return false if already known
+    pub fn insert_evidence(&mut self, evidence: &Evidence) -> bool {
+        let evidence_cf = self.db.cf_handle(EVIDENCE_CF).unwrap();
+        let key = match evidence {
+            Evidence::Proposal(a, _) => serialize(&(0u8, a.sid, a.block_id, a.proposer_account.as_bytes())).unwrap(),
+            Evidence::Vote(a, _) => serialize(&(1u8, a.sid, a.block_id, a.signer)).unwrap(),
+        };
+        if self.db.get_pinned_cf(evidence_cf, &key).unwrap().is_some() {
+            return false;
+        }
+        let serialized = serialize(evidence).unwrap();
+        self.db.put_cf(evidence_cf, &key, &serialized).unwrap();
+        true
+    }
+
+    pub fn get_evidence(&self) -> Vec<Evidence> {
+        let evidence_cf = self.db.cf_handle(EVIDENCE_CF).unwrap();
+        self.db.
+            iterator_cf(evidence_cf, rocksdb::IteratorMode::Start).
+            unwrap().
+            map(|(_, v)| deserialize(&v).unwrap()).
+            collect()
+    }
+
     pub fn get_num_blocks(&self) -> u64 {
        self.num_block as u64
     }
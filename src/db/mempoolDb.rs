@@ -0,0 +1,165 @@
+use std::collections::{BTreeSet, HashMap};
+use super::cmtda::{BlockHeader, Transaction};
+use super::cmtda::H256 as CMTH256;
+use chain::decoder::{Symbol};
+use rocksdb::{self, ColumnFamilyDescriptor, Options, DB};
+use bincode::{deserialize, serialize};
+
+const TX_CF: &str = "TX";
+const DISPERSAL_CF: &str = "DISPERSAL";
+const PROPOSAL_CF: &str = "PROPOSAL";
+
+// dispersal state of a proposed block, samples keyed by scale id
+pub type Dispersal = (BlockHeader, HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>)>);
+
+// own proposal not known final, with its transactions
+pub type StoredProposal = (BlockHeader, Vec<Transaction>);
+
+// pending transactions, own proposals and dispersal state of a side chain
+// mempool, kept across restarts unlike the block db
+pub struct MempoolDb {
+    pub db: rocksdb::DB,
+    dispersal_ids: BTreeSet<u64>, // block ids with dispersal state stored
+}
+
+impl MempoolDb {
+    pub fn new<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> MempoolDb {
+        let tx_cf = ColumnFamilyDescriptor::new(TX_CF, Options::default());
+        let dispersal_cf = ColumnFamilyDescriptor::new(DISPERSAL_CF, Options::default());
+        let proposal_cf = ColumnFamilyDescriptor::new(PROPOSAL_CF, Options::default());
+        let cfs = vec![tx_cf, dispersal_cf, proposal_cf];
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let db = DB::open_cf_descriptors(&opts, path, cfs).unwrap();
+
+        let mut mempool_db = MempoolDb {
+            db: db,
+            dispersal_ids: BTreeSet::new(),
+        };
+        mempool_db.dispersal_ids = mempool_db.get_dispersal_ids();
+        mempool_db
+    }
+
+    // seq orders transactions by arrival when replayed
+    pub fn insert_transaction(&mut self, hash: &CMTH256, seq: u64, transaction: &Transaction) {
+        let tx_cf = self.db.cf_handle(TX_CF).unwrap();
+        let tx_bytes: Vec<u8> = ser::serialize(transaction).into();
+        let serialized = serialize(&(seq, tx_bytes)).unwrap();
+        self.db.put_cf(tx_cf, &hash[..], &serialized).unwrap();
+    }
+
+    pub fn remove_transaction(&mut self, hash: &CMTH256) {
+        let tx_cf = self.db.cf_handle(TX_CF).unwrap();
+        self.db.delete_cf(tx_cf, &hash[..]).unwrap();
+    }
+
+    // in arrival order, with their sequence numbers
+    pub fn get_transactions(&self) -> Vec<(u64, Transaction)> {
+        let tx_cf = self.db.cf_handle(TX_CF).unwrap();
+        let mut transactions: Vec<(u64, Transaction)> = self.db.
+            iterator_cf(tx_cf, rocksdb::IteratorMode::Start).
+            unwrap().
+            filter_map(|(_, v)| {
+                let (seq, tx_bytes): (u64, Vec<u8>) = deserialize(&v).ok()?;
+                let tx: Transaction = ser::deserialize(&tx_bytes as &[u8]).ok()?;
+                Some((seq, tx))
+            }).
+            collect();
+        transactions.sort_by_key(|(seq, _)| *seq);
+        transactions
+    }
+
+    // dispersal state is kept for the latest keep_blocks block ids only
+    pub fn insert_dispersal(
+        &mut self,
+        block_id: u64,
+        header: &BlockHeader,
+        symbols_by_scale_id: &HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>)>,
+        keep_blocks: usize,
+    ) {
+        let dispersal_cf = self.db.cf_handle(DISPERSAL_CF).unwrap();
+        let header_bytes: Vec<u8> = ser::serialize(header).into();
+        let serialized = serialize(&(header_bytes, symbols_by_scale_id)).unwrap();
+        // big endian, so that iteration goes by block id
+        self.db.put_cf(dispersal_cf, &block_id.to_be_bytes(), &serialized).unwrap();
+        self.dispersal_ids.insert(block_id);
+
+        while self.dispersal_ids.len() > keep_blocks {
+            let oldest = *self.dispersal_ids.iter().next().unwrap();
+            self.remove_dispersal(oldest);
+        }
+    }
+
+    pub fn remove_dispersal(&mut self, block_id: u64) {
+        let dispersal_cf = self.db.cf_handle(DISPERSAL_CF).unwrap();
+        self.db.delete_cf(dispersal_cf, &block_id.to_be_bytes()).unwrap();
+        self.dispersal_ids.remove(&block_id);
+    }
+
+    pub fn insert_proposal(&mut self, block_id: u64, header: &BlockHeader, transactions: &[Transaction]) {
+        let proposal_cf = self.db.cf_handle(PROPOSAL_CF).unwrap();
+        let header_bytes: Vec<u8> = ser::serialize(header).into();
+        let tx_bytes: Vec<Vec<u8>> = transactions.
+            iter().
+            map(|tx| ser::serialize(tx).into()).
+            collect();
+        let serialized = serialize(&(header_bytes, tx_bytes)).unwrap();
+        self.db.put_cf(proposal_cf, &block_id.to_be_bytes(), &serialized).unwrap();
+    }
+
+    pub fn remove_proposal(&mut self, block_id: u64) {
+        let proposal_cf = self.db.cf_handle(PROPOSAL_CF).unwrap();
+        self.db.delete_cf(proposal_cf, &block_id.to_be_bytes()).unwrap();
+    }
+
+    pub fn get_proposals(&self) -> Vec<(u64, StoredProposal)> {
+        let proposal_cf = self.db.cf_handle(PROPOSAL_CF).unwrap();
+        self.db.
+            iterator_cf(proposal_cf, rocksdb::IteratorMode::Start).
+            unwrap().
+            filter_map(|(k, v)| {
+                let mut id = [0u8; 8];
+                id.copy_from_slice(&k);
+                let (header_bytes, tx_bytes): (Vec<u8>, Vec<Vec<u8>>) = deserialize(&v).ok()?;
+                let header: BlockHeader = ser::deserialize(&header_bytes as &[u8]).ok()?;
+                let transactions: Option<Vec<Transaction>> = tx_bytes.
+                    iter().
+                    map(|b| ser::deserialize(b as &[u8]).ok()).
+                    collect();
+                Some((u64::from_be_bytes(id), (header, transactions?)))
+            }).
+            collect()
+    }
+
+    fn get_dispersal_ids(&self) -> BTreeSet<u64> {
+        let dispersal_cf = self.db.cf_handle(DISPERSAL_CF).unwrap();
+        self.db.
+            iterator_cf(dispersal_cf, rocksdb::IteratorMode::Start).
+            unwrap().
+            map(|(k, _)| {
+                let mut id = [0u8; 8];
+                id.copy_from_slice(&k);
+                u64::from_be_bytes(id)
+            }).
+            collect()
+    }
+
+    pub fn get_dispersals(&self) -> Vec<(u64, Dispersal)> {
+        let dispersal_cf = self.db.cf_handle(DISPERSAL_CF).unwrap();
+        self.db.
+            iterator_cf(dispersal_cf, rocksdb::IteratorMode::Start).
+            unwrap().
+            filter_map(|(k, v)| {
+                let mut id = [0u8; 8];
+                id.copy_from_slice(&k);
+                let (header_bytes, symbols_by): (Vec<u8>, HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>)>) =
+                    deserialize(&v).ok()?;
+                let header: BlockHeader = ser::deserialize(&header_bytes as &[u8]).ok()?;
+                Some((u64::from_be_bytes(id), (header, symbols_by)))
+            }).
+            collect()
+    }
+}
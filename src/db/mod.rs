@@ -3,6 +3,7 @@ use super::cmtda;
 use super::network;
 
 pub mod blockDb;
+pub mod mempoolDb;
 pub mod utxoDb;
 
 
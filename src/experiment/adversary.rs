@@ -0,0 +1,86 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::fs::File;
+use chain::decoder::{Symbol};
+use super::network::message::Samples;
+
+// misbehaviour injected into a node for adversarial experiments, everything
+// is off by default
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Adversary {
+    // side node
+    pub incorrect_layers: Vec<usize>, // coded layers encoded incorrectly
+    pub withhold_from: HashSet<u64>,  // scale ids never sent their samples
+    pub equivocate: bool,             // propose a second header in every slot
+    // scale node
+    pub sign_without_data: bool,      // vote without waiting for samples
+    pub refuse_retrieval: bool,       // ignore ScaleGetAllChunks
+    pub corrupt_retrieval: bool,      // flip the bytes of returned symbols
+}
+
+impl Adversary {
+    // comma separated behaviours, e.g.
+    // "incorrect_layers=0:2,withhold_from=1:3,equivocate"
+    pub fn parse(spec: &str) -> Result<Adversary, String> {
+        let mut adversary = Adversary::default();
+        for item in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let mut parts = item.splitn(2, '=');
+            let name = parts.next().unwrap();
+            let value = parts.next();
+            match (name, value) {
+                ("incorrect_layers", Some(v)) => {
+                    adversary.incorrect_layers = parse_list(v)?;
+                },
+                ("withhold_from", Some(v)) => {
+                    adversary.withhold_from = parse_list(v)?.into_iter().collect();
+                },
+                ("equivocate", None) => adversary.equivocate = true,
+                ("sign_without_data", None) => adversary.sign_without_data = true,
+                ("refuse_retrieval", None) => adversary.refuse_retrieval = true,
+                ("corrupt_retrieval", None) => adversary.corrupt_retrieval = true,
+                _ => return Err(format!("unknown adversary behaviour {}", item)),
+            }
+        }
+        Ok(adversary)
+    }
+
+    pub fn from_file(path: &str) -> Result<Adversary, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        serde_json::from_reader(file).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn is_honest(&self) -> bool {
+        self.incorrect_layers.is_empty() &&
+            self.withhold_from.is_empty() &&
+            !self.equivocate &&
+            !self.sign_without_data &&
+            !self.refuse_retrieval &&
+            !self.corrupt_retrieval
+    }
+
+    // the correct flags taken by Block::new
+    pub fn coding_layers(&self, num_layer: usize) -> Vec<bool> {
+        (0..num_layer).
+            map(|l| !self.incorrect_layers.contains(&l)).
+            collect()
+    }
+
+    pub fn corrupt(&self, samples: &mut Samples) {
+        for layer in samples.symbols.iter_mut() {
+            for symbol in layer.iter_mut() {
+                match symbol {
+                    Symbol::Base(bytes) => bytes.iter_mut().for_each(|b| *b = !*b),
+                    Symbol::Upper(bytes) => bytes.iter_mut().for_each(|b| *b = !*b),
+                    Symbol::Empty => (),
+                }
+            }
+        }
+    }
+}
+
+fn parse_list<T: std::str::FromStr>(list: &str) -> Result<Vec<T>, String> {
+    list.split(':').
+        map(|s| s.parse::<T>().map_err(|_| format!("invalid number {}", s))).
+        collect()
+}
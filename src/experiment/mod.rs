@@ -1,5 +1,6 @@
 pub mod transactionGenerator;
 pub mod snapshot;
+pub mod adversary;
 
 use super::mempool;
 use super::network;
@@ -1,6 +1,9 @@
 use serde::{Serialize, Deserialize};
 use std::sync::atomic::{AtomicUsize, AtomicU64, AtomicU32, Ordering, AtomicBool};
 use std::time::{SystemTime, UNIX_EPOCH, Duration};
+use std::collections::{HashMap, BTreeMap};
+use std::net::SocketAddr;
+use std::sync::Mutex;
 
 lazy_static! {
     pub static ref PERFORMANCE_COUNTER: Counter = { Counter::default() };
@@ -55,7 +58,46 @@ pub struct Counter {
     block_latency: AtomicUsize,
 
     gas: AtomicUsize,
+    vote_batches: AtomicUsize,  // submitVotes calls final on chain
+    batched_votes: AtomicUsize, // votes in them, committed or not
+
+    queued_msgs: AtomicUsize,   // messages waiting in outgoing peer queues
+    queued_bytes: AtomicUsize,
+    max_queued_bytes: AtomicUsize,
+    dropped_msgs: AtomicUsize,  // rejected by a full outgoing queue
+    rejected_proposals: AtomicUsize, // out of the proposer schedule
+    equivocations: AtomicUsize,
+    withheld_timeouts: AtomicUsize, // vote deadline passed without any sample
+    partial_timeouts: AtomicUsize,  // vote deadline passed with some samples
+    withheld_reports: AtomicUsize,  // same, reported by other scale nodes
+    partial_reports: AtomicUsize,
+    token_regenerations: AtomicUsize, // tokens recreated after a silent holder
+    rejected_transactions: AtomicUsize, // refused by mempool admission
+    evicted_transactions: AtomicUsize,  // dropped from a full mempool
+    requeued_transactions: AtomicUsize, // back in the mempool from failed proposals
+
+    // frame bytes on the wire, length prefix included
+    sent_msgs: AtomicUsize,
+    sent_bytes: AtomicUsize,
+    recv_msgs: AtomicUsize,
+    recv_bytes: AtomicUsize,
+    traffic_by_type: Mutex<HashMap<&'static str, Traffic>>,
+    traffic_by_peer: Mutex<HashMap<SocketAddr, Traffic>>,
+    gas_by_call: Mutex<HashMap<&'static str, GasUse>>, // final contract txs
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Traffic {
+    sent_msgs:  usize,
+    sent_bytes: usize,
+    recv_msgs:  usize,
+    recv_bytes: usize,
+}
 
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct GasUse {
+    txs: usize,
+    gas: usize,
 }
 
 impl Counter {
@@ -86,6 +128,109 @@ impl Counter {
         self.gas.fetch_add(gas, Ordering::Relaxed);
     }
 
+    pub fn record_vote_batch(&self, votes: usize) {
+        self.vote_batches.fetch_add(1, Ordering::Relaxed);
+        self.batched_votes.fetch_add(votes, Ordering::Relaxed);
+    }
+
+    pub fn record_gas_used(&self, call: &'static str, gas: usize) {
+        self.record_gas_update(gas);
+        let mut gas_by_call = self.gas_by_call.lock().unwrap();
+        let used = gas_by_call.entry(call).or_insert_with(GasUse::default);
+        used.txs += 1;
+        used.gas += gas;
+    }
+
+    pub fn record_queue_push(&self, bytes: usize) {
+        self.queued_msgs.fetch_add(1, Ordering::Relaxed);
+        let queued = self.queued_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let mut max = self.max_queued_bytes.load(Ordering::Relaxed);
+        while queued > max {
+            let prev = self.max_queued_bytes.compare_and_swap(max, queued, Ordering::Relaxed);
+            if prev == max {
+                break;
+            }
+            max = prev;
+        }
+    }
+
+    pub fn record_queue_pop(&self, bytes: usize) {
+        self.queued_msgs.fetch_sub(1, Ordering::Relaxed);
+        self.queued_bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_queue_drop(&self) {
+        self.dropped_msgs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected_proposal(&self) {
+        self.rejected_proposals.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_equivocation(&self) {
+        self.equivocations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_availability_timeout(&self, withheld: bool) {
+        if withheld {
+            self.withheld_timeouts.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.partial_timeouts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_availability_report(&self, withheld: bool) {
+        if withheld {
+            self.withheld_reports.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.partial_reports.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_token_regeneration(&self) {
+        self.token_regenerations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected_transaction(&self) {
+        self.rejected_transactions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_evicted_transaction(&self) {
+        self.evicted_transactions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_requeued_transactions(&self, num: usize) {
+        self.requeued_transactions.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn record_sent(&self, peer: &SocketAddr, msg_type: &'static str, bytes: usize) {
+        self.sent_msgs.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(bytes, Ordering::Relaxed);
+        let mut by_type = self.traffic_by_type.lock().unwrap();
+        let traffic = by_type.entry(msg_type).or_default();
+        traffic.sent_msgs += 1;
+        traffic.sent_bytes += bytes;
+        drop(by_type);
+        let mut by_peer = self.traffic_by_peer.lock().unwrap();
+        let traffic = by_peer.entry(*peer).or_default();
+        traffic.sent_msgs += 1;
+        traffic.sent_bytes += bytes;
+    }
+
+    pub fn record_recv(&self, peer: &SocketAddr, msg_type: &'static str, bytes: usize) {
+        self.recv_msgs.fetch_add(1, Ordering::Relaxed);
+        self.recv_bytes.fetch_add(bytes, Ordering::Relaxed);
+        let mut by_type = self.traffic_by_type.lock().unwrap();
+        let traffic = by_type.entry(msg_type).or_default();
+        traffic.recv_msgs += 1;
+        traffic.recv_bytes += bytes;
+        drop(by_type);
+        let mut by_peer = self.traffic_by_peer.lock().unwrap();
+        let traffic = by_peer.entry(*peer).or_default();
+        traffic.recv_msgs += 1;
+        traffic.recv_bytes += bytes;
+    }
+
     // should not be used later
     pub fn store_chain_depth(&self, chain_len: usize) {
         self.chain_depth.store(chain_len, Ordering::Relaxed);
@@ -237,11 +382,43 @@ impl Counter {
             block_latency: self.block_latency.load(Ordering::Relaxed),
             coll_latency: self.coll_latency.load(Ordering::Relaxed),
             gas: self.gas.load(Ordering::Relaxed),
+            vote_batches: self.vote_batches.load(Ordering::Relaxed),
+            batched_votes: self.batched_votes.load(Ordering::Relaxed),
             propose_num: self.propose_num.load(Ordering::Relaxed),
             sign_num: self.sign_num.load(Ordering::Relaxed) as usize,
             submit_num: self.submit_num.load(Ordering::Relaxed) as usize,
             block_num: self.block_num.load(Ordering::Relaxed) as usize,
             coll_num: self.coll_num.load(Ordering::Relaxed) as usize,
+            queued_msgs: self.queued_msgs.load(Ordering::Relaxed),
+            queued_bytes: self.queued_bytes.load(Ordering::Relaxed),
+            max_queued_bytes: self.max_queued_bytes.load(Ordering::Relaxed),
+            dropped_msgs: self.dropped_msgs.load(Ordering::Relaxed),
+            rejected_proposals: self.rejected_proposals.load(Ordering::Relaxed),
+            equivocations: self.equivocations.load(Ordering::Relaxed),
+            withheld_timeouts: self.withheld_timeouts.load(Ordering::Relaxed),
+            partial_timeouts: self.partial_timeouts.load(Ordering::Relaxed),
+            withheld_reports: self.withheld_reports.load(Ordering::Relaxed),
+            partial_reports: self.partial_reports.load(Ordering::Relaxed),
+            token_regenerations: self.token_regenerations.load(Ordering::Relaxed),
+            rejected_transactions: self.rejected_transactions.load(Ordering::Relaxed),
+            evicted_transactions: self.evicted_transactions.load(Ordering::Relaxed),
+            requeued_transactions: self.requeued_transactions.load(Ordering::Relaxed),
+            sent_msgs: self.sent_msgs.load(Ordering::Relaxed),
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            recv_msgs: self.recv_msgs.load(Ordering::Relaxed),
+            recv_bytes: self.recv_bytes.load(Ordering::Relaxed),
+            traffic_by_type: self.traffic_by_type.lock().unwrap().
+                iter().
+                map(|(k, v)| (k.to_string(), v.clone())).
+                collect(),
+            traffic_by_peer: self.traffic_by_peer.lock().unwrap().
+                iter().
+                map(|(k, v)| (k.to_string(), v.clone())).
+                collect(),
+            gas_by_call: self.gas_by_call.lock().unwrap().
+                iter().
+                map(|(k, v)| (k.to_string(), v.clone())).
+                collect(),
         }
     }
 }
@@ -267,10 +444,35 @@ pub struct Snapshot {
     coll_latency:            usize,
 
     gas:                     usize,
+    vote_batches:            usize,
+    batched_votes:           usize,
 
     propose_num:             usize,
     sign_num:                usize,
     submit_num:              usize,
     block_num:               usize,
     coll_num:                usize,
+
+    queued_msgs:             usize,
+    queued_bytes:            usize,
+    max_queued_bytes:        usize,
+    dropped_msgs:            usize,
+    rejected_proposals:      usize,
+    equivocations:           usize,
+    withheld_timeouts:       usize,
+    partial_timeouts:        usize,
+    withheld_reports:        usize,
+    partial_reports:         usize,
+    token_regenerations:     usize,
+    rejected_transactions:   usize,
+    evicted_transactions:    usize,
+    requeued_transactions:   usize,
+
+    sent_msgs:               usize,
+    sent_bytes:              usize,
+    recv_msgs:               usize,
+    recv_bytes:              usize,
+    traffic_by_type:         BTreeMap<String, Traffic>, // keyed by message name
+    traffic_by_peer:         BTreeMap<String, Traffic>, // keyed by peer address
+    gas_by_call:             BTreeMap<String, GasUse>,  // keyed by contract function
 }
@@ -145,8 +145,15 @@ impl TransactionGenerator {
 
     fn send_to_mempool(&mut self, transactions: Vec<Transaction>) {
         let mut mempool = self.mempool.lock().expect("tx gen lock mempool");
-        mempool.insert_transactions(transactions);
+        let rejected = mempool.
+            insert_transactions(transactions).
+            iter().
+            filter(|r| r.is_err()).
+            count();
         drop(mempool);
+        if rejected > 0 {
+            info!("mempool rejects {} generated transactions", rejected);
+        }
     }
 
 
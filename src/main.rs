@@ -7,31 +7,44 @@ use mio_extras::channel::{self};
 use clap::{Arg, App, SubCommand, ArgMatches};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use system_rust::network::message::{ServerSignal, ConnectResult, ConnectHandle, Message};
+use system_rust::network::message::{ServerSignal, ConnectResult, ConnectHandle, Message, MessageLimits};
 use system_rust::network::performer;
 
 use system_rust::network::server;
-use system_rust::mempool::scheduler::{Scheduler, Token};
+use system_rust::network::discovery::{self, Discovery, ScaleSet};
+use system_rust::network::queue::{QueueConfig, Priority};
+use system_rust::mempool::scheduler::{Scheduler, Token, SchedulingMode};
+use system_rust::mempool::clock::{SlotClock, WallClock, ChainClock, ChainSource, ClockKind};
 use system_rust::db::blockDb::{BlockDb};
+use system_rust::db::mempoolDb::{MempoolDb};
 use system_rust::blockchain::blockchain::{BlockChain};
-use system_rust::mempool::mempool::{Mempool};
+use system_rust::blockchain::sidechain::{SideChain};
+use system_rust::mempool::mempool::{Mempool, MempoolConfig, EvictionPolicy};
 use system_rust::contract::contract::{Contract, Account};
+use system_rust::contract::txManager::{TxConfig};
+use system_rust::contract::events::{EventConfig};
+use system_rust::contract::voteBatcher::{BatchConfig};
+use system_rust::contract::gas::{GasConfig, ChainProfile, FeeMode, GasLimit};
 use std::sync::{Arc, Mutex};
 use system_rust::api::apiServer::ApiServer;
 use system_rust::experiment::transactionGenerator::{TransactionGenerator};
 use std::net::{SocketAddr};
+use std::collections::{BTreeMap, HashMap};
 use crossbeam::channel as cbchannel;
 use log::{info, warn, error, debug};
 use system_rust::mainChainManager::{Manager};
 use system_rust::cmtda::{read_codes};
 use chain::decoder::{Code};
-use system_rust::contract::interface::{Handle, Answer};
+use system_rust::contract::interface::{Handle, Answer, Retry, request_with_retry};
+use system_rust::contract::interface::Error as ContractError;
 use system_rust::contract::interface::Message as ContractMessage;
 use system_rust::contract::interface::Response as ContractResponse;
-use system_rust::contract::utils::{BLSKey, BLSKeyStr};
+use system_rust::contract::utils::{BLSKey, BLSKeyStr, _get_key_as_vec, _gen_bls};
+use system_rust::contract::keystore::{self, Kdf, KeyKind, PlainKey};
 use system_rust::primitive::block::{ContractState};
-use web3::types::Address;
+use web3::types::{Address, U256};
 use system_rust::experiment::snapshot::PERFORMANCE_COUNTER;
+use system_rust::experiment::adversary::Adversary;
 
 fn main() {
     env_logger::init();
@@ -40,93 +53,160 @@ fn main() {
         (author: "Anonymous Submitter")
         (about: "simple blockchain network")
         (@arg known_peer: -c --connect ... [PEER] "Sets ip to connect to")
-        (@arg side_node: -r --side_node ... [SIDE] "Sets side ip to connect to")
+        (@arg side_node: -r --side_node ... [SIDE] "Sets side ip to connect to, sid@ip:port for side chains other than 0")
         (@arg peer_addr: -i --p2p [ADDR]  "Sets ip to listen")
         (@arg api_addr: -a --api_addr [ADDR] "Sets port for api")
         (@arg account: -d --account  [ACCOUNT] "Sets account address")
         (@arg contract_addr: -f --contract_addr [ADDR] "Sets ETH contract address")
         (@arg node_url: -u --node_url [HTTP] "Sets ETH node https url")
         (@arg key: -k --key  +takes_value "Sets key address")
+        (@arg passphrase_file: --passphrase_file +takes_value "file holding the passphrase of encrypted account and key files, else KEYSTORE_PASSPHRASE")
         //(@arg has_token: -t --has_token "Sets init token")
-        (@arg scale_id: -s --scale_id  +takes_value "Sets scalechain node")
+        (@arg scale_id: -s --scale_id  +takes_value "Sets scalechain node, read from the contract registry by default")
         (@arg ldpc: -l --ldpc  +takes_value "get ldpc file path")
-        (@arg num_scale: -n --num_scale +takes_value "get number scale node")
+        (@arg num_scale: -n --num_scale +takes_value "get number scale node, read from the contract registry by default")
+        (@arg discovery_interval: --discovery_interval +takes_value "seconds between reads of the scale node registry, default 30")
         (@arg binary_dir: -b --binary_dir +takes_value "get bls binary")
         (@arg abi_path: -j --abi_path +takes_value "get api_path")
         (@arg num_side: -e --num_side +takes_value "get num side")
         (@arg slot_time: -t --slot_time +takes_value "get slot time")
         (@arg start_time: --start_time +takes_value "contract starting time, measured in UNIX EPOCH")
+        (@arg scheduling: --scheduling +takes_value "proposer scheduling, slot or token, default slot")
+        (@arg slot_clock: --slot_clock +takes_value "slot source, wall, contract (committed block id) or main_chain (block number), default wall")
+        (@arg blocks_per_slot: --blocks_per_slot +takes_value "main chain blocks per slot of the main_chain slot clock, default 1")
+        (@arg clock_poll: --clock_poll +takes_value "millis between reads of the contract and main_chain slot clocks, default 1000")
+        (@arg token_timeout: --token_timeout +takes_value "slots without a token pass before the ring regenerates it, default 3")
+        (@arg pack_deadline: --pack_deadline +takes_value "fraction of its slot a proposer waits for a full block before packing what is pending, default 0.5")
+        (@arg adversary: --adversary +takes_value "misbehaviours of this node, e.g. incorrect_layers=0:2,withhold_from=1,equivocate,sign_without_data,refuse_retrieval,corrupt_retrieval")
+        (@arg adversary_config: --adversary_config +takes_value "json file of the misbehaviours of this node")
+        (@arg redundancy: --redundancy +takes_value "times each coded symbol is sampled by scale nodes on average, default 1")
+        (@arg vote_deadline: --vote_deadline +takes_value "slots a scale node waits for its samples before reporting them unavailable, default 1")
+        (@arg mempool_bytes: --mempool_bytes +takes_value "serialized transactions a side chain mempool holds at most, default 8 blocks")
+        (@arg proposal_timeout: --proposal_timeout +takes_value "slots before transactions of an uncommitted proposal go back to the mempool, default 10")
+        (@arg mempool_db: --mempool_db +takes_value "directory persisting mempools across restarts, in memory only if absent")
+        (@arg mempool_eviction: --mempool_eviction +takes_value "policy of a full mempool, oldest (evict oldest) or reject (reject new), default oldest")
+        (@arg confirmations: --confirmations +takes_value "main chain blocks on top of a contract tx before it is final, default 1")
+        (@arg stuck_timeout: --stuck_timeout +takes_value "secs without receipt before a contract tx is resent at a higher gas price, default 30")
+        (@arg chain_profile: --chain_profile +takes_value "chain id and gas defaults of kovan, sepolia, holesky or devnet, default devnet")
+        (@arg chain_id: --chain_id +takes_value "chain id signed into contract txs, default of the chain profile")
+        (@arg gas_price: --gas_price +takes_value "gwei per gas of legacy contract txs")
+        (@arg max_fee: --max_fee +takes_value "gwei fee cap per gas, sends EIP-1559 contract txs")
+        (@arg max_priority_fee: --max_priority_fee +takes_value "gwei tip per gas of EIP-1559 contract txs, default 2")
+        (@arg gas_limit: --gas_limit +takes_value "gas limit of contract txs, estimate, estimate:<margin percent> or a number, default estimate:20")
+        (@arg max_gas_price: --max_gas_price +takes_value "gwei per gas replacements of stuck contract txs stop at")
+        (@arg event_poll: --event_poll +takes_value "millis between reads of contract events, default 1000")
+        (@arg event_cursor: --event_cursor +takes_value "directory persisting how far contract logs were read, in memory only if absent")
+        (@arg vote_batch: --vote_batch +takes_value "votes committed by one submitVotes tx, default 1 (a submitVote tx each)")
+        (@arg vote_batch_delay: --vote_batch_delay +takes_value "millis a vote waits for others to fill its batch, default 2000")
+        (@arg reorg_depth: --reorg_depth +takes_value "main chain blocks on top of a committed side block before a reorg cannot revert it, default 12")
+        (@arg max_clock_skew: --max_clock_skew +takes_value "millis a proposal may arrive outside of its slot, default 500")
+        (@arg max_control_size: --max_control_size +takes_value "max bytes of a control or signature message")
+        (@arg max_tx_size: --max_tx_size +takes_value "max bytes of a transaction message")
+        (@arg max_proposal_size: --max_proposal_size +takes_value "max bytes of a block proposal message")
+        (@arg max_sample_size: --max_sample_size +takes_value "max bytes of a coded samples message")
+        (@arg rate_limit: --rate_limit +takes_value "outgoing bytes per second to each peer, unlimited by default")
+        (@arg burst: --burst +takes_value "bytes a peer can send at once under the rate limit")
+        (@arg bulk_queue_size: --bulk_queue_size +takes_value "max bytes of samples queued for each peer")
         (@subcommand addScaleNode =>
             (@arg contract_addr: -f --contract_addr [ADDR] "Sets ETH contract address")
             (@arg node_url: -u --node_url [HTTP] "Sets ETH node https url")
-            (@arg account: --account [ACCOUNT] "get account file")
+            (@arg account: --account +takes_value +required "get account file")
+            (@arg passphrase_file: --passphrase_file +takes_value "file holding the account passphrase, else KEYSTORE_PASSPHRASE")
             (@arg new_account: --new_account +takes_value "get account file")
             (@arg keyfile: --keyfile +takes_value "get key file")
-            (@arg ip_addr: --ip_addr +takes_value "get ip_addr")
+            (@arg ip_addr: --ip_addr +takes_value "get p2p address, ip:port")
+            (@arg chain_profile: --chain_profile +takes_value "kovan, sepolia, holesky or devnet, default devnet")
+            (@arg chain_id: --chain_id +takes_value "chain id signed into the tx")
         )
         (@subcommand getCurrState =>
             (@arg account: --account [ACCOUNT]  "get account file")
+            (@arg passphrase_file: --passphrase_file +takes_value "file holding the account passphrase, else KEYSTORE_PASSPHRASE")
             (@arg contract_addr: -f --contract_addr [ADDR] "Sets ETH contract address")
             (@arg node_url: -u --node_url [HTTP] "Sets ETH node https url")
+            (@arg sid: --sid +takes_value "side chain id, default 0")
         )
         (@subcommand resetChain =>
-            (@arg account: --account [ACCOUNT]  "get account file")
+            (@arg account: --account +takes_value +required "get account file")
+            (@arg passphrase_file: --passphrase_file +takes_value "file holding the account passphrase, else KEYSTORE_PASSPHRASE")
             (@arg contract_addr: -f --contract_addr [ADDR] "Sets ETH contract address")
             (@arg node_url: -u --node_url [HTTP] "Sets ETH node https url")
+            (@arg sid: --sid +takes_value "side chain id, default 0")
+            (@arg chain_profile: --chain_profile +takes_value "kovan, sepolia, holesky or devnet, default devnet")
+            (@arg chain_id: --chain_id +takes_value "chain id signed into the tx")
         )
         (@subcommand getScaleNodes =>
             (@arg account: --account [ACCOUNT]  "get account file")
+            (@arg passphrase_file: --passphrase_file +takes_value "file holding the account passphrase, else KEYSTORE_PASSPHRASE")
             (@arg contract_addr: -f --contract_addr [ADDR] "Sets ETH contract address")
             (@arg node_url: -u --node_url [HTTP] "Sets ETH node https url")
         )
+        (@subcommand newKey =>
+            (@arg out: --out +takes_value +required "keystore file to create")
+            (@arg kind: --kind +takes_value "eth (account) or bls, default eth")
+            (@arg kdf: --kdf +takes_value "scrypt or pbkdf2, default scrypt")
+            (@arg binary_dir: -b --binary_dir +takes_value "get bls binary, for bls keys")
+            (@arg passphrase_file: --passphrase_file +takes_value "file holding the new passphrase, else KEYSTORE_PASSPHRASE")
+        )
+        (@subcommand importKey =>
+            (@arg from: --from +takes_value +required "plaintext account or bls key file")
+            (@arg out: --out +takes_value +required "keystore file to create")
+            (@arg kdf: --kdf +takes_value "scrypt or pbkdf2, default scrypt")
+            (@arg passphrase_file: --passphrase_file +takes_value "file holding the new passphrase, else KEYSTORE_PASSPHRASE")
+        )
+        (@subcommand listKeys =>
+            (@arg dir: --dir +takes_value +required "directory of account and key files")
+        )
     )
     .get_matches();
 
     match matches.subcommand() {
         ("addScaleNode", Some(m)) => {
             let contract = get_contract_instance(&m);
-            let account: Account = match m.value_of("new_account") {
-                Some(account_path) => {
-                    let file = File::open(account_path).unwrap();
-                    serde_json::from_reader(file).expect("deser account")
-                },
-                None => panic!("unable to locate account"),
-            };
+            // only public parts of the registered node, no passphrase needed
+            let new_account = m.
+                value_of("new_account").
+                expect("unable to locate account");
+            let address = keystore::read_address(new_account).
+                unwrap_or_else(|e| panic!("{}", e));
             let key_path = m.
                 value_of("keyfile").
                 expect("missing key file");           
-            let key_file = File::open(key_path).unwrap();
-            let key_str: BLSKeyStr = match serde_json::from_reader(key_file) {
-                Ok(k) => k,
+            let (pkx1, pkx2, pky1, pky2) = match keystore::read_bls_public(key_path) {
+                Ok(pk) => pk,
                 Err(e) => {
-                    error!("unable to deser keyfile {:?}", key_path);
+                    error!("unable to read keyfile {:?}: {}", key_path, e);
                     return;
                 }
             };
-            let key: BLSKey = BLSKey::new(key_str);
             let ip_addr = m.
                 value_of("ip_addr").
                 unwrap().
                 to_string();
-            info!("get scale id {:?}", account.address);
-            match contract._get_scale_id(account.address.clone()) {
+            info!("get scale id {:?}", address);
+            match contract._get_scale_id(address.clone()) {
                 Some(i) => {
                     if i.as_usize() == 0 {
+                        let (answer_tx, answer_rx) = cbchannel::bounded(1);
                         contract.add_scale_node(
-                            account.address,
+                            address,
                             ip_addr,
-                            key.pkx1, key.pkx2, 
-                            key.pky1, key.pky2
+                            pkx1, pkx2, 
+                            pky1, pky2,
+                            Some(answer_tx),
                         );
+                        wait_for_write(answer_rx);
                     }
                 },
                 None => {
+                    let (answer_tx, answer_rx) = cbchannel::bounded(1);
                     contract.add_scale_node(
-                        account.address,
+                        address,
                         ip_addr,
-                        key.pkx1, key.pkx2, 
-                        key.pky1, key.pky2
+                        pkx1, pkx2, 
+                        pky1, pky2,
+                        Some(answer_tx),
                     );
+                    wait_for_write(answer_rx);
                     println!("Registered Address");
                 }
             }
@@ -134,16 +214,20 @@ fn main() {
         },
         ("getCurrState", Some(m)) => {
             let contract = get_contract_instance(&m);
-            let state = contract._get_curr_state(0); 
+            let sid = get_sid(&m);
+            let state = contract._get_curr_state(sid).unwrap_or_else(|e| panic!("unable to read state: {}", e));
             println!("hash: {:?}\nblock_id: {:?}", state.curr_hash, state.block_id);
             return;
         },
         ("resetChain", Some(m)) => {
             let contract = get_contract_instance(&m);
-            let mut state = contract._get_curr_state(0); 
+            let sid = get_sid(&m);
+            let mut state = contract._get_curr_state(sid).unwrap_or_else(|e| panic!("unable to read state: {}", e));
             if state.block_id != 0 {
-                contract.reset_chain(0); 
-                state = contract._get_curr_state(0); 
+                let (answer_tx, answer_rx) = cbchannel::bounded(1);
+                contract.reset_chain(sid, Some(answer_tx)); 
+                wait_for_write(answer_rx);
+                state = contract._get_curr_state(sid).unwrap_or_else(|e| panic!("unable to read state: {}", e));
             }
             println!("hash: {:?}\nblock_id: {:?}", state.curr_hash, state.block_id);
             assert!(state.block_id==0);
@@ -151,24 +235,82 @@ fn main() {
         },
         ("getScaleNodes", Some(m)) => {
             let contract = get_contract_instance(&m);
-            let num_scale = contract._count_scale_nodes(); 
-            println!("{:?}", num_scale);
-            let mut scale_list = vec![];
-            let mut scale_pub = vec![];
             // the node 0 is considered special for current contract design
-            for i in 1..num_scale {
-                scale_list.push(contract._get_scale_node(i));
-            }
-
-            for i in scale_list.iter() {
-                scale_pub.push(contract._get_scale_pub_key(*i));
+            let scale_nodes = contract._get_scale_nodes().unwrap_or_else(|e| panic!("unable to read scale nodes: {}", e));
+            println!("num scale node(node 0 does not count): {}", scale_nodes.len());
+            for node in scale_nodes.iter() {
+                println!("{} {:?} {} {:?}", node.index, node.address, node.ip_addr, node.pub_key);
             }
-            
-            println!("num scale node(node 0 does not count): {}", num_scale-1);
-            println!("{:?}", scale_list);
-            println!("{:?}", scale_pub);
             return;
         }
+        ("newKey", Some(m)) => {
+            let out = m.value_of("out").unwrap();
+            let kind = match m.value_of("kind") {
+                Some(kind) => kind.parse::<KeyKind>().unwrap_or_else(|e| panic!("{}", e)),
+                None => KeyKind::Eth,
+            };
+            let passphrase = get_new_passphrase(&m);
+            let kdf = get_kdf(&m);
+            let saved = match kind {
+                KeyKind::Eth => {
+                    let account = keystore::new_account();
+                    println!("address: {:?}", account.address);
+                    keystore::encrypt_account(&account, &passphrase, kdf).
+                        and_then(|key_file| keystore::save(out, &key_file))
+                },
+                KeyKind::Bls => {
+                    let bin_path = m.value_of("binary_dir").expect("missing binary path");
+                    let key = _gen_bls(bin_path).expect("bls binary gave no key");
+                    println!("pkx1: {}\npkx2: {}\npky1: {}\npky2: {}", key.pkx1, key.pkx2, key.pky1, key.pky2);
+                    keystore::encrypt_bls_key(&key, &passphrase, kdf).
+                        and_then(|key_file| keystore::save(out, &key_file))
+                },
+            };
+            saved.unwrap_or_else(|e| panic!("{}", e));
+            println!("saved {}", out);
+            return;
+        },
+        ("importKey", Some(m)) => {
+            let from = m.value_of("from").unwrap();
+            let out = m.value_of("out").unwrap();
+            let passphrase = get_new_passphrase(&m);
+            let kdf = get_kdf(&m);
+            let saved = match keystore::read_plaintext(from).unwrap_or_else(|e| panic!("{}", e)) {
+                PlainKey::Eth(account) => keystore::encrypt_account(&account, &passphrase, kdf).
+                    and_then(|key_file| keystore::save(out, &key_file)),
+                PlainKey::Bls(key) => keystore::encrypt_bls_key(&key, &passphrase, kdf).
+                    and_then(|key_file| keystore::save(out, &key_file)),
+            };
+            saved.unwrap_or_else(|e| panic!("{}", e));
+            // the plaintext is left for the operator to check and shred
+            println!("saved {}, remove {} once checked", out, from);
+            return;
+        },
+        ("listKeys", Some(m)) => {
+            let dir = m.value_of("dir").unwrap();
+            let mut paths: Vec<_> = std::fs::read_dir(dir).
+                unwrap_or_else(|e| panic!("unable to read {}: {}", dir, e)).
+                filter_map(|entry| entry.ok()).
+                map(|entry| entry.path()).
+                filter(|path| path.is_file()).
+                collect();
+            paths.sort();
+            for path in paths.iter() {
+                let path = path.to_string_lossy();
+                match keystore::describe(&path) {
+                    Ok(info) => {
+                        let kind = match info.kind {
+                            KeyKind::Eth => "eth",
+                            KeyKind::Bls => "bls",
+                        };
+                        let state = if info.encrypted { "encrypted" } else { "plaintext" };
+                        println!("{} {} {} {}", path, kind, state, info.public);
+                    },
+                    Err(e) => println!("{} unknown {}", path, e),
+                }
+            }
+            return;
+        },
         _ => {},
     }
 
@@ -205,18 +347,105 @@ fn main() {
     let abi_path = matches.value_of("abi_path").expect("missing json abi path");
     let key_path = matches.value_of("key").expect("missing key file");
     let ldpc_path = matches.value_of("ldpc").expect("missing ldpc file");
-    let mut scale_id: u64 = matches.value_of("scale_id").expect("missing scaleid").parse::<u64>().unwrap();
-    let mut num_scale: u64 = matches.value_of("num_scale").expect("missing number of scale").parse::<u64>().unwrap();
     let mut slot_time: f32 = matches.value_of("slot_time").expect("missing slot time").parse::<f32>().unwrap();
-    let mut start_time: f64 = matches.value_of("start_time").expect("missing starting time").parse::<f64>().unwrap();
+    let scheduling = match matches.value_of("scheduling") {
+        Some(mode) => mode.parse::<SchedulingMode>().unwrap_or_else(|e| panic!("{}", e)),
+        None => SchedulingMode::Slot,
+    };
+    let token_timeout = match matches.value_of("token_timeout") {
+        Some(slots) => slots.parse::<f32>().expect("token_timeout needs to be numeric"),
+        None => 3.0,
+    };
+    let pack_deadline = match matches.value_of("pack_deadline") {
+        Some(fraction) => fraction.parse::<f32>().expect("pack_deadline needs to be numeric"),
+        None => 0.5,
+    };
+    let slot_clock = match matches.value_of("slot_clock") {
+        Some(kind) => kind.parse::<ClockKind>().unwrap_or_else(|e| panic!("{}", e)),
+        None => ClockKind::Wall,
+    };
+    let blocks_per_slot = match matches.value_of("blocks_per_slot") {
+        Some(n) => n.parse::<u64>().expect("blocks_per_slot needs to be numeric"),
+        None => 1,
+    };
+    let clock_poll = match matches.value_of("clock_poll") {
+        Some(millis) => millis.parse::<u64>().expect("clock_poll needs to be numeric"),
+        None => 1000,
+    };
+    let mut mempool_config = MempoolConfig::default();
+    if let Some(bytes) = matches.value_of("mempool_bytes") {
+        mempool_config.max_bytes = bytes.parse::<usize>().expect("mempool_bytes needs to be numeric");
+    }
+    if let Some(policy) = matches.value_of("mempool_eviction") {
+        mempool_config.eviction = policy.parse::<EvictionPolicy>().unwrap_or_else(|e| panic!("{}", e));
+    }
+    let proposal_timeout = match matches.value_of("proposal_timeout") {
+        Some(slots) => slots.parse::<f32>().expect("proposal_timeout needs to be numeric"),
+        None => 10.0,
+    };
+    mempool_config.proposal_timeout = time::Duration::from_secs_f32(slot_time * proposal_timeout);
+    let mut tx_config = TxConfig::default();
+    if let Some(n) = matches.value_of("confirmations") {
+        tx_config.confirmations = n.parse::<u64>().expect("confirmations needs to be numeric");
+    }
+    if let Some(secs) = matches.value_of("stuck_timeout") {
+        tx_config.stuck_timeout = time::Duration::from_secs(secs.parse::<u64>().expect("stuck_timeout needs to be numeric"));
+    }
+    let gas_config = get_gas_config(&matches);
+    let mut event_config = EventConfig::default();
+    if let Some(millis) = matches.value_of("event_poll") {
+        event_config.poll = time::Duration::from_millis(millis.parse::<u64>().expect("event_poll needs to be numeric"));
+    }
+    event_config.cursor_dir = matches.value_of("event_cursor").map(|dir| dir.to_string());
+    if let Some(depth) = matches.value_of("reorg_depth") {
+        event_config.confirmations = depth.parse::<u64>().expect("reorg_depth needs to be numeric");
+    }
+    let reorg_depth = event_config.confirmations;
+    let mut batch_config = BatchConfig::default();
+    if let Some(votes) = matches.value_of("vote_batch") {
+        batch_config.max_votes = votes.parse::<usize>().expect("vote_batch needs to be numeric").max(1);
+    }
+    if let Some(millis) = matches.value_of("vote_batch_delay") {
+        batch_config.max_delay = time::Duration::from_millis(millis.parse::<u64>().expect("vote_batch_delay needs to be numeric"));
+    }
+    // only wall clock slots need synchronized clocks
+    let needs_start_time = scheduling == SchedulingMode::Slot && slot_clock == ClockKind::Wall;
+    let mut start_time: f64 = match matches.value_of("start_time") {
+        Some(t) => t.parse::<f64>().unwrap(),
+        None if !needs_start_time => time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap().as_secs_f64(),
+        None => panic!("missing starting time"),
+    };
     let start_sec: u64 = start_time.floor() as u64;
     let start_millis: u64 = ((start_time - start_time.floor())*1000.0).floor() as u64;
 
-    PERFORMANCE_COUNTER.record_scale_id(scale_id as usize);
+    let mut msg_limits = MessageLimits::default();
+    if let Some(size) = matches.value_of("max_control_size") {
+        msg_limits.max_control = size.parse::<usize>().expect("max_control_size needs to be numeric");
+    }
+    if let Some(size) = matches.value_of("max_tx_size") {
+        msg_limits.max_transaction = size.parse::<usize>().expect("max_tx_size needs to be numeric");
+    }
+    if let Some(size) = matches.value_of("max_proposal_size") {
+        msg_limits.max_proposal = size.parse::<usize>().expect("max_proposal_size needs to be numeric");
+    }
+    if let Some(size) = matches.value_of("max_sample_size") {
+        msg_limits.max_samples = size.parse::<usize>().expect("max_sample_size needs to be numeric");
+    }
 
     info!("sec    {}", start_sec);
     info!("millis {}", start_millis);
 
+    let mut queue_config = QueueConfig::default();
+    if let Some(rate) = matches.value_of("rate_limit") {
+        queue_config.rate = Some(rate.parse::<u64>().expect("rate_limit needs to be numeric"));
+    }
+    if let Some(burst) = matches.value_of("burst") {
+        queue_config.burst = burst.parse::<u64>().expect("burst needs to be numeric");
+    }
+    if let Some(size) = matches.value_of("bulk_queue_size") {
+        queue_config.capacity[Priority::Bulk as usize] = size.parse::<usize>().expect("bulk_queue_size needs to be numeric");
+    }
+
     // get neighnors
     let mut neighbors = vec![];
     if let Some(known_peers) =  matches.values_of("known_peer") {
@@ -229,45 +458,89 @@ fn main() {
         }
     }
 
-    let mut sidenodes = vec![];
+    // side node rings of every side chain served, in slot order
+    let mut sidenodes_by: BTreeMap<u64, Vec<SocketAddr>> = BTreeMap::new();
     if let Some(side_nodes) =  matches.values_of("side_node") {
-        let side_nodes: Vec<String> = side_nodes.map(|x| x.to_owned()).collect();
         for peer in side_nodes {
-            match peer.parse::<SocketAddr>() {
-                Ok(addr) => sidenodes.push(addr),
-                Err(_) => panic!("parse peer addr error"),
+            match parse_side_node(peer) {
+                Some((sid, addr)) => sidenodes_by.entry(sid).or_insert(vec![]).push(addr),
+                None => panic!("parse side node addr error"),
             }
         }
     }
-
-    let num_side = sidenodes.len() as u64;
+    if sidenodes_by.is_empty() {
+        sidenodes_by.insert(0, vec![]);
+    }
 
 
     //let has_token = sidenodes[0] == p2p_addr;
 
     //println!("side_nodes {:?}", sidenodes);
 
-    let is_scale_node: bool = (scale_id > 0);
-    
     // get accounts
     info!("api socket {:?}", api_socket);
+    let passphrase = keystore::read_passphrase(matches.value_of("passphrase_file"));
     let account: Account = match matches.value_of("account") {
-        Some(account_path) => {
-            let file = File::open(account_path).unwrap();
-            serde_json::from_reader(file).expect("deser account")
-        },
+        Some(account_path) => keystore::
+            load_account(account_path, passphrase.as_ref().map(|p| p.as_str())).
+            unwrap_or_else(|e| panic!("{}", e)),
         None => panic!("unable to locate account"),
     };
 
-    let key_file = File::open(key_path).unwrap();
-    let key_str: BLSKeyStr = match serde_json::from_reader(key_file) {
+    // scale set from the contract registry unless given by hand
+    let arg_scale_id = matches.value_of("scale_id").map(|s| s.parse::<u64>().expect("scale_id needs to be numeric"));
+    let arg_num_scale = matches.value_of("num_scale").map(|s| s.parse::<u64>().expect("num_scale needs to be numeric"));
+    let (scale_id, num_scale, registry) = match (arg_scale_id, arg_num_scale) {
+        // keys of the registry come with the first discovery refresh
+        (Some(scale_id), Some(num_scale)) => (scale_id, num_scale, vec![]),
+        _ => {
+            let registry = Contract::instance(&account, rpc_url, &contract_addr, get_gas_config(&matches)).
+                _get_scale_nodes().
+                unwrap_or_else(|e| panic!("unable to read scale node registry: {}", e));
+            let scale_id = arg_scale_id.unwrap_or(discovery::scale_id_of(&registry, &account.address));
+            let num_scale = arg_num_scale.unwrap_or(registry.len() as u64);
+            info!("registry has {} scale nodes, local scale id {}", registry.len(), scale_id);
+            (scale_id, num_scale, registry)
+        }
+    };
+    let max_clock_skew = match matches.value_of("max_clock_skew") {
+        Some(millis) => millis.parse::<u64>().expect("max_clock_skew needs to be numeric"),
+        None => 500,
+    };
+    let adversary = match (matches.value_of("adversary"), matches.value_of("adversary_config")) {
+        (Some(spec), _) => Adversary::parse(spec).unwrap_or_else(|e| panic!("{}", e)),
+        (None, Some(path)) => Adversary::from_file(path).unwrap_or_else(|e| panic!("{}", e)),
+        (None, None) => Adversary::default(),
+    };
+    if !adversary.is_honest() {
+        warn!("running as adversary {:?}", adversary);
+    }
+    let redundancy = match matches.value_of("redundancy") {
+        Some(r) => r.parse::<f32>().expect("redundancy needs to be numeric"),
+        None => 1.0,
+    };
+    let vote_deadline = match matches.value_of("vote_deadline") {
+        Some(slots) => slots.parse::<f32>().expect("vote_deadline needs to be numeric"),
+        None => 1.0,
+    };
+    let discovery_interval = match matches.value_of("discovery_interval") {
+        Some(secs) => secs.parse::<u64>().expect("discovery_interval needs to be numeric"),
+        None => 30,
+    };
+
+    PERFORMANCE_COUNTER.record_scale_id(scale_id as usize);
+    let scale_set = Arc::new(Mutex::new(ScaleSet::new(scale_id, num_scale, registry)));
+
+    let is_scale_node: bool = (scale_id > 0);
+
+    let key_str: BLSKeyStr = match keystore::load_bls_key(key_path, passphrase.as_ref().map(|p| p.as_str())) {
         Ok(k) => k,
         Err(e) => {
-            error!("unable to deser keyfile {:?}", key_path);
+            error!("unable to load keyfile {:?}: {}", key_path, e);
             return;
         }
     };
-    let key: BLSKey = BLSKey::new(key_str);
+    let key: BLSKey = BLSKey::new(key_str.clone());
     
 
     // roles
@@ -275,7 +548,6 @@ fn main() {
         value_of("peer_addr").
         unwrap();
     let block_db = Arc::new(Mutex::new(BlockDb::new(block_db_path)));
-    let blockchain = Arc::new(Mutex::new(BlockChain::new()));
 
     let (task_sender, task_receiver) =cbchannel::unbounded();
 
@@ -283,25 +555,15 @@ fn main() {
         task_sender.clone(), 
         p2p_addr,
         is_scale_node,
+        msg_limits,
+        queue_config,
     );
     server_ctx.start();
 
-    let (schedule_handle_sender, schedule_handle_receiver) = cbchannel::unbounded();
     let (contract_handle_sender, contract_handle_receiver) = cbchannel::unbounded();
-    let (manager_handle_sender, manager_handle_receiver) = cbchannel::unbounded();
     let k_set: Vec<u64> = vec![128,64,32,16,8,4];//vec![32,16,8];//  //128,64,32,16,8
     let (codes_for_encoding, codes_for_decoding) = read_codes(k_set.clone(), ldpc_path);
-    let mempool = Arc::new(Mutex::new(Mempool::new(
-        contract_handle_sender.clone(),
-        schedule_handle_sender.clone(),
-        p2p_addr.clone(),
-        codes_for_encoding.clone(),
-        codes_for_decoding.clone(),
-    )));
 
-    
-
-    //let token = init_token(has_token, p2p_addr.clone(), &sidenodes);
 
     let contract = Contract::new(
         account.clone(),
@@ -313,86 +575,186 @@ fn main() {
         abi_path.to_string(),
         rpc_url,
         &contract_addr,
+        gas_config,
+        tx_config,
+        event_config,
+        batch_config,
     );
 
-    let manager = Manager::new(
-        contract_handle_sender.clone(),
-        blockchain.clone(),
-        mempool.clone(),
-        server_handle.control_tx.clone(),
-        p2p_addr.clone(),
-        manager_handle_receiver,
-        block_db.clone(),
-        codes_for_encoding.clone(),
-        codes_for_decoding.clone(),
-        k_set.clone()
-    );
+    // chain clocks read the contract
+    contract.start();
+    let wall_clock: Arc<dyn SlotClock> = Arc::new(WallClock::new(start_sec, start_millis, slot_time));
+    let main_chain_clock: Option<Arc<dyn SlotClock>> = match slot_clock {
+        ClockKind::MainChain => Some(ChainClock::start(
+            contract_handle_sender.clone(),
+            ChainSource::MainChainHeight { blocks_per_slot: blocks_per_slot },
+            time::Duration::from_millis(clock_poll),
+            slot_time,
+        )),
+        _ => None,
+    };
 
-    //if scale_id == 0 {
-        //manager.start();
-    //}
-
-    let scheduler = Scheduler::new(
-        p2p_addr.clone(), 
-        None, 
-        mempool.clone(), 
-        server_handle.control_tx.clone(), 
-        schedule_handle_receiver.clone(), 
-        blockchain.clone(),
-        contract_handle_sender.clone(),
-        //side_id as u64,
-        sidenodes.clone(),
-        account.address.clone(),
-        slot_time,
-        start_sec,
-        start_millis,
-        num_scale,
-        codes_for_encoding.clone(),
-    );
-    if scale_id == 0 {
+    // registered accounts of the side nodes, proposals are checked against them
+    let mut accounts_by: HashMap<u64, HashMap<SocketAddr, Address>> = HashMap::new();
+    let registry_reader = Contract::instance(&account, rpc_url, &contract_addr, get_gas_config(&matches));
+    for (sid, sidenodes) in sidenodes_by.iter().filter(|(_, nodes)| !nodes.is_empty()) {
+        let registered = registry_reader.
+            _get_side_nodes(*sid as usize).
+            unwrap_or_else(|e| panic!("unable to read side nodes of side chain {}: {}", sid, e));
+        let accounts: HashMap<SocketAddr, Address> = registered.
+            iter().
+            filter_map(|(address, ip_addr)| discovery::parse_registered_addr(ip_addr).map(|addr| (addr, *address))).
+            collect();
+        for addr in sidenodes.iter().filter(|addr| !accounts.contains_key(addr)) {
+            warn!("side node {:?} of side chain {} is not registered, its proposals are rejected", addr, sid);
+        }
+        accounts_by.insert(*sid, accounts);
+    }
+
+    // registry changes seen by the managers, followed by discovery
+    let (registry_sender, registry_receiver) = cbchannel::unbounded();
+
+    // one chain, mempool and scheduler per side chain
+    let mut chains: HashMap<u64, SideChain> = HashMap::new();
+    let mut tx_controls = HashMap::new();
+    for (sid, sidenodes) in sidenodes_by.iter() {
+        let sid = *sid;
+        let (schedule_handle_sender, schedule_handle_receiver) = cbchannel::unbounded();
+        let (manager_handle_sender, manager_handle_receiver) = cbchannel::unbounded();
+        let blockchain = Arc::new(Mutex::new(BlockChain::new(reorg_depth)));
+        let clock: Arc<dyn SlotClock> = match slot_clock {
+            ClockKind::Wall => wall_clock.clone(),
+            ClockKind::Contract => ChainClock::start(
+                contract_handle_sender.clone(),
+                ChainSource::ContractBlockId(sid),
+                time::Duration::from_millis(clock_poll),
+                slot_time,
+            ),
+            ClockKind::MainChain => main_chain_clock.clone().unwrap(),
+        };
+        let mempool = Arc::new(Mutex::new(Mempool::new(
+            contract_handle_sender.clone(),
+            schedule_handle_sender.clone(),
+            p2p_addr.clone(),
+            codes_for_encoding.clone(),
+            codes_for_decoding.clone(),
+            mempool_config,
+            matches.value_of("mempool_db").map(|dir| MempoolDb::new(format!("{}/side-{}", dir, sid))),
+        )));
+
+        let manager = Manager::new(
+            contract_handle_sender.clone(),
+            sid,
+            blockchain.clone(),
+            mempool.clone(),
+            server_handle.control_tx.clone(),
+            p2p_addr.clone(),
+            manager_handle_receiver,
+            block_db.clone(),
+            codes_for_encoding.clone(),
+            codes_for_decoding.clone(),
+            k_set.clone(),
+            registry_sender.clone(),
+        );
 
-        scheduler.start();
+        if scale_id == 0 {
+            manager.start();
+        }
+
+        // the first side node of the ring creates the token
+        let has_token = scheduling == SchedulingMode::TokenRing && sidenodes.first() == Some(&p2p_addr);
+        let token = init_token(has_token, p2p_addr.clone(), sidenodes);
+        let scheduler = Scheduler::new(
+            p2p_addr.clone(), 
+            token, 
+            mempool.clone(), 
+            server_handle.control_tx.clone(), 
+            schedule_handle_receiver.clone(), 
+            blockchain.clone(),
+            contract_handle_sender.clone(),
+            sid,
+            sidenodes.clone(),
+            accounts_by.get(&sid).cloned().unwrap_or_default(),
+            account.address.clone(),
+            _get_key_as_vec(account.private_key.clone()),
+            slot_time,
+            clock.clone(),
+            scale_set.clone(),
+            codes_for_encoding.clone(),
+            redundancy,
+            adversary.clone(),
+            scheduling,
+            token_timeout,
+            pack_deadline,
+        );
+        if scale_id == 0 && sidenodes.contains(&p2p_addr) {
+            scheduler.start();
+        }
+
+        let (tx_gen, tx_control_sender) = TransactionGenerator::new(mempool.clone());
+        tx_gen.start();
+        tx_controls.insert(sid, tx_control_sender);
+
+        chains.insert(sid, SideChain {
+            sid: sid,
+            chain: blockchain,
+            mempool: mempool,
+            sidenodes: sidenodes.clone(),
+            accounts: accounts_by.remove(&sid).unwrap_or_default(),
+            scheduler_handler: schedule_handle_sender,
+            manager_source: manager_handle_sender,
+            clock: clock,
+        });
     }
-    contract.start();
+    drop(registry_sender);
+    info!("serving side chains {:?}", sidenodes_by.keys().collect::<Vec<_>>());
 
     // create main actors
     let mut performer = performer::new(
         task_receiver, 
-        blockchain.clone(), 
+        chains.clone(), 
         block_db.clone(),
-        mempool.clone(),
-        schedule_handle_sender.clone(),
         contract_handle_sender.clone(),
         p2p_addr.clone(),
-        key_path.to_string(),
-        scale_id,
+        key_str,
+        scale_set.clone(),
         0,
         server_handle.control_tx.clone(),
-        manager_handle_sender.clone(),
-        num_scale,
         bin_path,
-        num_side,
         account.address.clone(),
         slot_time,
-        sidenodes.clone(),
-        start_sec,
-        start_millis,
+        max_clock_skew,
+        scheduling,
+        vote_deadline,
+        token_timeout,
+        redundancy,
+        adversary,
     );
     performer.start();
 
-    let (tx_gen, tx_control_sender) = TransactionGenerator::new(mempool.clone());
-    tx_gen.start();
-
     ApiServer::start(
         api_socket, 
-        tx_control_sender, 
-        mempool.clone(), 
+        tx_controls, 
+        chains.clone(), 
         contract_handle_sender.clone(), 
-        blockchain.clone(),
         block_db.clone(),
         server_handle.control_tx.clone(),
+        p2p_addr,
     );
 
+    let discovery = Discovery::new(
+        contract_handle_sender.clone(),
+        server::Handle { control_tx: server_handle.control_tx.clone() },
+        p2p_addr.clone(),
+        account.address.clone(),
+        time::Duration::from_secs(discovery_interval),
+        registry_receiver,
+        scale_set.clone(),
+        (arg_scale_id, arg_num_scale),
+        &neighbors,
+    );
+    discovery.start();
+
     let mut num_connected = 0;
     for neighbor in neighbors.iter() {
         let addr: SocketAddr = neighbor.to_string().parse().unwrap();
@@ -432,22 +794,23 @@ pub fn init_token(
 ) -> Option<Token> {
     let mut token: Option<Token> = None;
     if has_token {
-        info!("creating token");
-        let number_token = sidenodes.len();
-        let mut tok = Token {
-            version: 0,
-            ring_size: 0,
-            node_list: vec![],
-        };
-        for node in sidenodes.iter() {
-            tok.ring_size += 1;
-            tok.node_list.push(node.clone());
-        }
-        token = Some(tok);
+        info!("{:?} creating token", listen_socket);
+        token = Some(Token::new(sidenodes.clone()));
     }
     token
 }
 
+// "ip:port" is a side node of side chain 0, "sid@ip:port" of side chain sid
+pub fn parse_side_node(spec: &str) -> Option<(u64, SocketAddr)> {
+    let mut parts = spec.rsplitn(2, '@');
+    let addr = parts.next()?.parse::<SocketAddr>().ok()?;
+    let sid = match parts.next() {
+        Some(sid) => sid.parse::<u64>().ok()?,
+        None => 0,
+    };
+    Some((sid, addr))
+}
+
 pub fn parse_addr_file(filename: &str) -> Vec<SocketAddr> {
     let f = File::open(filename).expect("Unable to open file");
     let f = BufReader::new(f);
@@ -459,46 +822,85 @@ pub fn parse_addr_file(filename: &str) -> Vec<SocketAddr> {
     }
     neighbors
 }
-pub fn sync_chain(contract_channel: cbchannel::Sender<Handle>) -> usize {
-    let (answer_tx, answer_rx) = cbchannel::bounded(1);
-    let handle = Handle {
-        message: ContractMessage::SyncChain,
-        answer_channel: Some(answer_tx),
+pub fn sync_chain(contract_channel: cbchannel::Sender<Handle>) -> Result<usize, ContractError> {
+    match request_with_retry(&contract_channel, ContractMessage::SyncChain, &Retry::default())? {
+        ContractResponse::SyncChain(chain_len) => Ok(chain_len),
+        _ => Err(ContractError::Decode("answer to SyncChain: invalid response type".to_string())),
+    }
+}
+
+// passphrase of a keystore about to be written
+pub fn get_new_passphrase(m: &ArgMatches) -> String {
+    match keystore::read_passphrase(m.value_of("passphrase_file")) {
+        Some(passphrase) if !passphrase.is_empty() => passphrase,
+        _ => panic!("missing passphrase, give --passphrase_file or set {}", keystore::PASSPHRASE_ENV),
+    }
+}
+
+pub fn get_kdf(m: &ArgMatches) -> Kdf {
+    match m.value_of("kdf") {
+        Some(kdf) => kdf.parse::<Kdf>().unwrap_or_else(|e| panic!("{}", e)),
+        None => Kdf::Scrypt,
+    }
+}
+
+pub fn get_sid(m : &ArgMatches) -> usize {
+    match m.value_of("sid") {
+        Some(sid) => sid.parse::<usize>().expect("sid needs to be numeric"),
+        None => 0,
+    }
+}
+
+pub fn get_gas_config(m: &ArgMatches) -> GasConfig {
+    let gwei = |name: &str, v: &str| {
+        let gwei = v.parse::<u64>().unwrap_or_else(|_| panic!("{} needs to be numeric", name));
+        U256::from(1000000000u64) * U256::from(gwei)
     };
-    contract_channel.send(handle);
-    let chain_len = match answer_rx.recv() {
-        Ok(answer) => {
-            match answer {
-                Answer::Success(response) => {
-                    match response {
-                        ContractResponse::SyncChain(chain_len) => chain_len,
-                        _ => {
-                            panic!("answer to GetMainNodes: invalid response type");
-                        },
-                    }
-                },
-                Answer::Fail(reason) => {
-                    panic!("sync chain failure");
-                },
-            }
-        },
-        Err(e) => {
-            panic!("main contract channel broke");
-        },
-    };   
-    chain_len
+    let mut gas_config = match m.value_of("chain_profile") {
+        Some(profile) => profile.parse::<ChainProfile>().unwrap_or_else(|e| panic!("{}", e)).gas_config(),
+        None => GasConfig::default(),
+    };
+    if let Some(id) = m.value_of("chain_id") {
+        gas_config.chain_id = id.parse::<u32>().expect("chain_id needs to be numeric");
+    }
+    if let Some(v) = m.value_of("gas_price") {
+        gas_config.fees = FeeMode::Legacy { gas_price: gwei("gas_price", v) };
+    }
+    if let Some(v) = m.value_of("max_fee") {
+        let max_priority_fee = match m.value_of("max_priority_fee") {
+            Some(tip) => gwei("max_priority_fee", tip),
+            None => gwei("max_priority_fee", "2"),
+        };
+        gas_config.fees = FeeMode::Eip1559 { max_fee: gwei("max_fee", v), max_priority_fee: max_priority_fee };
+    } else if let (Some(tip), FeeMode::Eip1559 { max_fee, .. }) = (m.value_of("max_priority_fee"), gas_config.fees) {
+        gas_config.fees = FeeMode::Eip1559 { max_fee: max_fee, max_priority_fee: gwei("max_priority_fee", tip) };
+    }
+    if let Some(limit) = m.value_of("gas_limit") {
+        gas_config.limit = limit.parse::<GasLimit>().unwrap_or_else(|e| panic!("{}", e));
+    }
+    if let Some(v) = m.value_of("max_gas_price") {
+        gas_config.max_gas_price = gwei("max_gas_price", v);
+    }
+    gas_config
+}
+
+// contract writes of the cli exit only once final
+pub fn wait_for_write(answer_rx: cbchannel::Receiver<Answer>) {
+    match answer_rx.recv() {
+        Ok(Answer::Success(_)) => (),
+        Ok(Answer::Fail(reason)) => panic!("contract write failed: {}", reason),
+        Err(_) => panic!("contract tx manager stopped"),
+    }
 }
 
 pub fn get_contract_instance(m : &ArgMatches) -> Contract {
+    let passphrase = keystore::read_passphrase(m.value_of("passphrase_file"));
     let account: Account = match m.value_of("account") {
-        Some(account_path) => {
-            let file = File::open(account_path).unwrap();
-            serde_json::from_reader(file).expect("deser account")
-        },
-        None => {
-            let file = File::open("accounts/account1").unwrap();
-            serde_json::from_reader(file).expect("deser account")
-        }
+        Some(account_path) => keystore::
+            load_account(account_path, passphrase.as_ref().map(|p| p.as_str())).
+            unwrap_or_else(|e| panic!("{}", e)),
+        // reads need no account, subcommands writing require one
+        None => Account::default(),
     };
     let contract_addr = m.
         value_of("contract_addr").
@@ -512,6 +914,7 @@ pub fn get_contract_instance(m : &ArgMatches) -> Contract {
         &account,
         rpc_url,
         &contract_addr,
+        get_gas_config(m),
         );
     contract
 }
@@ -1,5 +1,5 @@
-use crossbeam::channel::{self, Sender, Receiver, TryRecvError};
-use super::contract::interface::{Handle, Answer};
+use crossbeam::channel::{self, Sender, Receiver, select};
+use super::contract::interface::{Handle, ContractEvent, request};
 use super::primitive::block::ContractState;
 use std::{thread, time};
 use mio_extras::channel::Sender as MioSender;
@@ -29,15 +29,24 @@ use ser::{deserialize, serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 use super::experiment::snapshot::PERFORMANCE_COUNTER;
 
+// main chain height is read this often, for states to confirm without new
+// side chain blocks
+const MAIN_HEIGHT_POLL: time::Duration = time::Duration::from_secs(5);
+// between failed subscriptions, doubled up to the max
+const SUBSCRIBE_BACKOFF: time::Duration = time::Duration::from_secs(1);
+const MAX_SUBSCRIBE_BACKOFF: time::Duration = time::Duration::from_secs(60);
+
 pub struct Manager {
     pub contract_handler: Sender<Handle>,
+    pub sid: u64,
     pub chain: Arc<Mutex<BlockChain>>, 
     pub mempool: Arc<Mutex<Mempool>>, 
     pub block_db: Arc<Mutex<BlockDb>>,
     pub server_control_sender: MioSender<ServerSignal>,
     pub addr: SocketAddr,
     pub manager_sink: Receiver<(u64, Option<Samples>)>,
-    pub chunk_senders: HashMap<u64, Sender<Option<Samples>> >,
+    pub chunk_senders: HashMap<u64, Sender<Option<Samples>> >, // running decode jobs
+    pub registry_sender: Sender<ContractEvent>, // registry changes, to discovery
     pub codes_for_encoding: Vec<Code>,
     pub codes_for_decoding: Vec<Code>,
     pub k_set: Vec<u64>,
@@ -48,7 +57,7 @@ pub struct JobManager {
     addr: SocketAddr,
     server_control_sender: MioSender<ServerSignal>,
     chunk_receiver: Receiver<Option<Samples>>,
-    block_source: Sender<Result<SBlock, CodingErr>>,
+    block_source: Sender<(u64, Result<SBlock, CodingErr>)>,
     codes_for_encoding: Vec<Code>,
     codes_for_decoding: Vec<Code>,
     k_set: Vec<u64>,
@@ -146,7 +155,7 @@ fn collect_cmt_chunks(job_manager: JobManager) {
                                         block_size_in_bytes:0 
                                     };
 
-                                    job_manager.block_source.send(Ok(r));
+                                    job_manager.block_source.send((job_manager.state.block_id, Ok(r)));
                                     break;
                                 },
                                 Err(proof) => (),
@@ -155,7 +164,8 @@ fn collect_cmt_chunks(job_manager: JobManager) {
                     }
                 }
             }
-           _ => panic!("{:?} job manager error", job_manager.addr),
+            // the job was dropped, its block reverted by a main chain reorg
+            Err(_) => return,
         }
     }
 }
@@ -163,8 +173,9 @@ fn collect_cmt_chunks(job_manager: JobManager) {
 impl Manager {
     pub fn new(
         contract_handler: Sender<Handle>, 
+        sid: u64,
         chain: Arc<Mutex<BlockChain>>,
-        mempool: Arc<Mutex<Mempool>>, //TODO for forgetting certain blocks
+        mempool: Arc<Mutex<Mempool>>,
         server_control_sender: MioSender<ServerSignal>,
         addr: SocketAddr,     
         manager_sink: Receiver<(u64, Option<Samples>)>,
@@ -172,9 +183,11 @@ impl Manager {
         codes_for_encoding: Vec<Code>,
         codes_for_decoding: Vec<Code>,
         k_set: Vec<u64>,
+        registry_sender: Sender<ContractEvent>,
     ) -> Manager {
         Manager {
             contract_handler: contract_handler,
+            sid: sid,
             chain: chain,
             mempool: mempool,
             server_control_sender: server_control_sender,
@@ -185,216 +198,271 @@ impl Manager {
             codes_for_encoding: codes_for_encoding,
             codes_for_decoding: codes_for_decoding,
             k_set: k_set,
+            registry_sender: registry_sender,
         }
     }
 
-    // spawn a new thread pulling for update from mainchain 
+    // spawn a new thread following the contract events of the side chain,
+    // the chunks routed to its jobs and the blocks they decode
     pub fn start(mut self){
         thread::spawn(move || {
-            let mut blocks_sink: HashMap<u64, Receiver<Result<SBlock, CodingErr>>> = HashMap::new();
-            let mut register_blocks: HashMap<u64, ContractState> = HashMap::new();
-            let mut ready_blocks: HashMap<u64, ContractState> = HashMap::new();
+            let (job_sender, job_results) = channel::unbounded();
+            // curr hash, if the event had it, and main chain block by block id
+            let mut register_blocks: HashMap<u64, (Option<H256>, Option<(u64, H256)>)> = HashMap::new();
+            let mut ready_blocks: HashMap<u64, (Option<H256>, Option<(u64, H256)>, BlockHeader)> = HashMap::new();
             let mut longest_id = 0;
-            let mut start = SystemTime::now();
 
-            let check_hash = false;
-            let interval = time::Duration::from_secs(2000);
-            thread::sleep(interval);
+            let tip_id = self.chain.lock().unwrap().get_latest_state().expect("blockchain does not have state").block_id;
+            let mut events = subscribe(&self.contract_handler, self.sid, tip_id);
+            let main_height_poll = channel::tick(MAIN_HEIGHT_POLL);
 
             loop {
-                let mut rm: Vec<u64> = vec![];
-                // check if any threads finish
-                for (block_id, block_sink) in &blocks_sink {
-                    match block_sink.try_recv() {
-                        Err(TryRecvError::Empty) => (),
-                        Err(TryRecvError::Disconnected) => panic!("block sink broken"),
-                        Ok(result) => {
-                            // a thread has finished processing cmt
-                            match result {
-                                Ok(sblock) => {
-                                    //info!("{:?} cmt finishes", self.addr);
-                                    rm.push(*block_id);
-                                    let mut sblock_db = self.block_db.lock().unwrap();
-                                    sblock_db.insert_sblock(*block_id as u64, sblock);
-                                    drop(sblock_db);
+                select! {
+                    recv(main_height_poll) -> _ => {
+                        match request(&self.contract_handler, ContractMessage::GetBlockNumber, MAIN_HEIGHT_POLL) {
+                            Ok(ContractResponse::BlockNumber(number)) => self.confirm(number),
+                            Ok(_) => warn!("{:?} main chain height: invalid response type", self.addr),
+                            Err(e) => warn!("{:?} unable to read main chain height: {}", self.addr, e),
+                        }
+                    },
+                    // job distributor to threads sender receiver
+                    recv(self.manager_sink) -> chunk => match chunk {
+                        Ok((block_id, chunk)) => match self.chunk_senders.get(&block_id) {
+                            // late chunks of a decoded block
+                            None => debug!("{:?} no cmt job for block {}", self.addr, block_id),
+                            Some(chunk_sender) => { chunk_sender.send(chunk); },
+                        },
+                        Err(_) => {
+                            info!("{:?} manager sink closed, manager stops", self.addr);
+                            return;
+                        },
+                    },
+                    // a job has finished processing cmt
+                    recv(job_results) -> result => {
+                        let (block_id, result) = result.expect("manager holds a job sender");
+                        self.chunk_senders.remove(&block_id);
+                        match result {
+                            // jobs of blocks reverted or reset in the meantime are stale
+                            Ok(_) if !register_blocks.contains_key(&block_id) => (),
+                            Ok(sblock) => {
+                                //info!("{:?} cmt finishes", self.addr);
+                                // drops transactions of our proposal, or requeues
+                                // them if another block took the id
+                                let mut mempool = self.mempool.lock().unwrap();
+                                mempool.commit_block(block_id, &sblock.block_header);
+                                mempool.record_decoded(block_id, &sblock.transactions);
+                                drop(mempool);
 
-                                    // update ready chain
-                                    let state = register_blocks.remove(block_id).expect("get block state");
-                                    ready_blocks.insert(*block_id, state);
+                                let header = sblock.block_header.clone();
+                                let mut sblock_db = self.block_db.lock().unwrap();
+                                sblock_db.insert_sblock(self.sid, block_id as u64, sblock);
+                                drop(sblock_db);
 
-                                    // update blockchain
-                                    let mut local_chain = self.chain.lock().unwrap();
-                                    let tip_state = local_chain.get_latest_state().unwrap();
-                                    //info!("{:?} tip_state {:?} longest_id {}", self.addr, tip_state, longest_id);
+                                // update ready chain
+                                let (curr_hash, main_block) = register_blocks.remove(&block_id).expect("get block state");
+                                ready_blocks.insert(block_id, (curr_hash, main_block, header));
+                                self.extend_chain(&mut ready_blocks, longest_id);
+                            },
+                            Err(e) => {
+                                // decoded again once the block is seen again
+                                register_blocks.remove(&block_id);
+                                info!("{:?} cmt handler error for block {}: {:?}", self.addr, block_id, e);
+                            },
+                        }
+                    },
+                    recv(events) -> event => match event {
+                        Ok(ContractEvent::BlockCommitted { block_id, header_hash, curr_hash, main_block, .. }) => {
+                            // if task is already handled
+                            let tip_id = self.chain.lock().unwrap().get_latest_state().expect("blockchain does not have state").block_id;
+                            if block_id <= tip_id || 
+                                register_blocks.contains_key(&block_id) || 
+                                ready_blocks.contains_key(&block_id) 
+                            {
+                                continue;
+                            }
+                            let curr_time = time::SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+                            info!("update curr time {}.{}", curr_time.as_secs(), curr_time.subsec_millis());
+                            PERFORMANCE_COUNTER.record_block_stop(block_id);
+                            let mut mempool = self.mempool.lock().unwrap();
+                            mempool.observe_tip(block_id, header_hash.map(|h| h.0));
+                            drop(mempool);
 
-                                    let mut curr_hash = tip_state.curr_hash.clone();
-                                    // test if update block chain
-                                    for i in (tip_state.block_id+1) .. (longest_id+1) {
-                                        match ready_blocks.get(&i) {
-                                            None => (),//info!("{:?} block {} is missing", self.addr, i),
-                                            Some(s) => {
-                                                ////info!("{:?} db get block {:?}", self.addr, i);
-                                                //let mut sblock_db = self.block_db.lock().unwrap();
-                                                //let header = match sblock_db.get_sblock(*block_id as u64) {
-                                                    //Some(b) => b.block_header.clone(),
-                                                    //None => unreachable!(),
-                                                //};
-                                                //drop(sblock_db);
+                            if longest_id < block_id {
+                                longest_id = block_id;
+                            }
+                            PERFORMANCE_COUNTER.record_coll_block_update(block_id);
+                            if let Some((number, _)) = main_block {
+                                self.confirm(number);
+                            }
 
-                                                //let mut hash  = [0u8; 32];
-                                                //let header_bytes = serialize(&header);
-                                                //// get hash in the same way as bls
-                                                //let mut hasher = Sha256::new();;
-                                                //hasher.input(&header_bytes);
-                                                //hasher.result(&mut hash);
-                                                //let hash_str = hex::encode(&hash);
-                                                ////info!("header hash {:?}", hash_str);
+                            // scale nodes only look at the block id
+                            let curr_hash: Option<H256> = curr_hash.map(|h| h.into());
+                            let state = ContractState {
+                                curr_hash: curr_hash.unwrap_or_default(),
+                                block_id: block_id,
+                            };
 
-                                                ////let mut curr_hash_str: String = hex::encode(&curr_hash.0);
-                                                //let v = [ curr_hash.0, hash].concat();
-                                                //let mut sec_hasher = Sha256::new();
-                                                //let mut hash  = [0u8; 32];
-                                                //sec_hasher.input(&v);
-                                                //sec_hasher.result(&mut hash);
+                            // get block from scale node network
+                            let (chunk_sender, chunk_receiver) = crossbeam::channel::unbounded();
+                            let main_block = main_block.map(|(number, hash)| (number, hash.into()));
+                            register_blocks.insert(block_id, (curr_hash, main_block));
+                            self.chunk_senders.insert(block_id, chunk_sender);
+                            let mut job_manager = JobManager {
+                                state: state.clone(), 
+                                addr: self.addr.clone(),
+                                server_control_sender: self.server_control_sender.clone(),
+                                chunk_receiver: chunk_receiver,
+                                block_source: job_sender.clone(),
+                                k_set: self.k_set.clone(),
+                                codes_for_encoding: self.codes_for_encoding.clone(),
+                                codes_for_decoding: self.codes_for_decoding.clone(),
+                            };
 
-                                                //// compare if smart contract hash equals to local
-                                                //let new_hash = H256(hash);
-                                                ////info!("new hash {:?}", new_hash);
-                                                //if  new_hash != s.curr_hash {
-                                                    //info!("{:?}, inconsistent hash {:?} smart contract {} hash {:?}", 
-                                                          //self.addr,
-                                                          //new_hash, 
-                                                          //i,
-                                                          //s.curr_hash); 
-                                                    //if check_hash{
-                                                        //break; 
-                                                    //}
-                                                //}
+                            // create a new handler for each block
+                            thread::spawn(move || {
+                                collect_cmt_chunks(job_manager);
+                            });
 
-                                                //info!("{:?} local chain update to {:?}", self.addr, s);
+                            // broadcast get all chunks
+                            let response_msg = Message::ScaleGetAllChunks(self.sid, state.clone());
+                            //info!("{:?} broadcase ScaleGetAllChunks {:?}", self.addr, state);
+                            let signal = ServerSignal::ServerBroadcast(response_msg);
+                            self.server_control_sender.send(signal);
+                        },
+                        Ok(ContractEvent::ChainReset { sid }) => {
+                            // the side chain starts over from genesis, running jobs
+                            // stop once their chunk senders are dropped
+                            let reverted = self.chain.lock().unwrap().rollback(1);
+                            register_blocks.clear();
+                            ready_blocks.clear();
+                            self.chunk_senders.clear();
+                            longest_id = 0;
 
-                                                local_chain.append(s);
-                                                curr_hash = s.curr_hash;
-                                                PERFORMANCE_COUNTER.record_chain_update();
-                                            },
-                                        }
-                                    }
-                                    drop(local_chain);
-                                },
-                                Err(e) => info!("cmt handler error "),
+                            let mut mempool = self.mempool.lock().unwrap();
+                            mempool.revert_blocks(&reverted);
+                            drop(mempool);
+                            warn!("{:?} side chain {} reset on the main chain, reverted side blocks {:?}", self.addr, sid, reverted);
+                        },
+                        Ok(ContractEvent::Reorg { main_number, .. }) => {
+                            let mut local_chain = self.chain.lock().unwrap();
+                            let reverted = local_chain.rollback_main(main_number);
+                            let tip_id = local_chain.get_latest_state().expect("blockchain does not have state").block_id;
+                            drop(local_chain);
+
+                            // jobs of blocks committed from main_number on start over once
+                            // they commit again, those of unknown main chain blocks too
+                            let dropped: Vec<u64> = register_blocks.
+                                iter().
+                                map(|(id, (_, m))| (*id, *m)).
+                                chain(ready_blocks.iter().map(|(id, (_, m, _))| (*id, *m))).
+                                filter(|(_, m)| m.map_or(true, |(number, _)| number >= main_number)).
+                                map(|(id, _)| id).
+                                collect();
+                            for block_id in dropped.iter().chain(reverted.iter()) {
+                                register_blocks.remove(block_id);
+                                ready_blocks.remove(block_id);
+                                self.chunk_senders.remove(block_id);
                             }
-                        }
-                    }
-                }
-                // romove finished threads handler
-                for block_id in &rm {
-                    blocks_sink.remove(block_id);
-                }
+                            longest_id = register_blocks.
+                                keys().
+                                chain(ready_blocks.keys()).
+                                cloned().
+                                fold(tip_id, u64::max);
 
-                // job distributor to threads sender receiver
-                match self.manager_sink.try_recv() {
-                    Err(TryRecvError::Empty) => (),
-                    Err(TryRecvError::Disconnected) => panic!("manager sink broken"),
-                    Ok((block_id, chunk)) => {
-                        match self.chunk_senders.get(&block_id) {
-                            None => info!("{:?} Error  no cmt get all request", self.addr),
-                            Some(chunk_sender) => chunk_sender.send(chunk).unwrap(),
-                        }
-                    }
+                            let mut mempool = self.mempool.lock().unwrap();
+                            mempool.revert_blocks(&reverted);
+                            drop(mempool);
+                            warn!("{:?} main chain reorg from block {} reverted side blocks {:?}", self.addr, main_number, reverted);
+                        },
+                        // the registry changed, discovery reconnects to it
+                        Ok(event @ ContractEvent::ScaleNodeAdded { .. }) |
+                        Ok(event @ ContractEvent::SideNodeAdded { .. }) |
+                        Ok(event @ ContractEvent::SideNodeDeleted { .. }) => {
+                            info!("{:?} contract event {:?}", self.addr, event);
+                            self.registry_sender.send(event);
+                        },
+                        Ok(event) => info!("{:?} contract event {:?}", self.addr, event),
+                        Err(_) => {
+                            // the event source gave up, follow again from the local tip,
+                            // blocks with running jobs are skipped when seen again
+                            let tip_id = self.chain.lock().unwrap().get_latest_state().expect("blockchain does not have state").block_id;
+                            warn!("{:?} contract event channel broke, resubscribe from block {}", self.addr, tip_id);
+                            events = subscribe(&self.contract_handler, self.sid, tip_id);
+                        },
+                    },
                 }
+            }
+        });
+    }
 
-                let interval = time::Duration::from_millis(20000000);
-                thread::sleep(interval);
-                let curr_time = time::SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                info!("curr time {}.{}", curr_time.as_secs(), curr_time.subsec_millis());
-
-                // check state every  sec
-                //if start.elapsed().unwrap() > time::Duration::from_millis(2000) {
-                    //check current state
-                    //info!("{:?} check smart contract", self.addr);
-                    start = SystemTime::now();
-                    let (answer_tx, answer_rx) = channel::bounded(1);
-                    let handle = Handle {
-                        message: ContractMessage::GetCurrState(0),
-                        answer_channel: Some(answer_tx),
-                    };
-                    self.contract_handler.send(handle);
-                    let mut curr_state: Option<ContractState> = None;
-                    match answer_rx.recv() {
-                        Ok(answer) => {
-                            match answer {
-                                Answer::Success(resp) => {
-                                    match resp {
-                                        ContractResponse::GetCurrState(state) => {
-                                            
-                                            let mut local_chain = self.chain.lock().unwrap();
-                                            let tip_state = local_chain.get_latest_state().expect("blockchain does not have state");
-                                            drop(local_chain);
-                                            // Ask performer to do the task
-                                            if tip_state != state {
-                                                let curr_time = time::SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-                                                info!("update curr time {}.{}", curr_time.as_secs(), curr_time.subsec_millis());
-                                                PERFORMANCE_COUNTER.record_block_stop(state.block_id);
-                                                //let mut mempool = self.mempool.lock().unwrap();
-                                                //mempool.remove_block(state.block_id);
-                                                //drop(mempool);
-                                                
-                                                if (false) {
-                                                    // if get correct block from side chain network
-                                                } else {
-                                                    //info!("*********Fetch a new state tip {:?} smart {:?}", tip_state, state);
-                                                    // if task is already handled
-                                                    if self.chunk_senders.contains_key(&state.block_id) {
-                                                        continue;
-                                                    } 
-                                                    //info!("{:?}, update start: mainchain new state {:?} tip_state {:?}", self.addr, state, tip_state);
-                                                    if longest_id < state.block_id {
-                                                        longest_id = state.block_id;
-                                                    }
-                                                    PERFORMANCE_COUNTER.record_coll_block_update(state.block_id);
-
-                                                    // get block from scale node network
-                                                    let (chunk_sender, chunk_receiver) = crossbeam::channel::unbounded();
-                                                    let (block_sender, block_receiver) = crossbeam::channel::unbounded();
-                                                    register_blocks.insert(state.block_id, state.clone());
-                                                    blocks_sink.insert(state.block_id, block_receiver);
-                                                    self.chunk_senders.insert(state.block_id, chunk_sender);
-                                                    let mut job_manager = JobManager {
-                                                        state: state.clone(), 
-                                                        addr: self.addr.clone(),
-                                                        server_control_sender: self.server_control_sender.clone(),
-                                                        chunk_receiver: chunk_receiver,
-                                                        block_source: block_sender,
-                                                        k_set: self.k_set.clone(),
-                                                        codes_for_encoding: self.codes_for_encoding.clone(),
-                                                        codes_for_decoding: self.codes_for_decoding.clone(),
-                                                    };
+    // own blocks buried deep enough in the main chain are final
+    fn confirm(&self, main_height: u64) {
+        let mut local_chain = self.chain.lock().unwrap();
+        local_chain.observe_main_height(main_height);
+        let confirmed = local_chain.get_final_state();
+        drop(local_chain);
+        if let Some(confirmed) = confirmed {
+            self.mempool.lock().unwrap().confirm_blocks(confirmed.block_id);
+        }
+    }
 
-                                                    // create a new handler for each block
-                                                    thread::spawn(move || {
-                                                        collect_cmt_chunks(job_manager);
-                                                   });
+    // append the decoded blocks following the tip, in block id order
+    fn extend_chain(
+        &self, 
+        ready_blocks: &mut HashMap<u64, (Option<H256>, Option<(u64, H256)>, BlockHeader)>, 
+        longest_id: u64
+    ) {
+        let mut local_chain = self.chain.lock().unwrap();
+        let tip_state = local_chain.get_latest_state().unwrap();
+        //info!("{:?} tip_state {:?} longest_id {}", self.addr, tip_state, longest_id);
 
-                                                    // broadcast get all chunks
-                                                    let response_msg = Message::ScaleGetAllChunks(state.clone());
-                                                    //info!("{:?} broadcase ScaleGetAllChunks {:?}", self.addr, state);
-                                                    let signal = ServerSignal::ServerBroadcast(response_msg);
-                                                    self.server_control_sender.send(signal);
-                                                }
-                                            }
-                                        },
-                                        _ => panic!("performer contract get wrong answer"), 
-                                    }
-                                },
-                                _ => panic!("fail"),
-                            }
-                        },
-                        Err(e) => panic!("performer contract channel broke"), 
-                    }
-                //}
+        let mut curr_hash = tip_state.curr_hash.clone();
+        for i in (tip_state.block_id+1) .. (longest_id+1) {
+            let (known_hash, main_block, header) = match ready_blocks.remove(&i) {
+                None => break,
+                Some(b) => b,
+            };
+            let s = ContractState {
+                curr_hash: known_hash.unwrap_or_else(|| chain_hash(&curr_hash, &header)),
+                block_id: i,
+            };
+            //info!("{:?} local chain update to {:?}", self.addr, s);
+            match main_block {
+                Some(main_block) => local_chain.append_committed(&s, main_block),
+                None => local_chain.append(&s),
             }
-        });
+            curr_hash = s.curr_hash;
+            PERFORMANCE_COUNTER.record_chain_update();
+        }
     }
+}
+
+// event stream of the side chain after block_id, tried until the contract
+// handler gives one
+fn subscribe(contract_handler: &Sender<Handle>, sid: u64, block_id: u64) -> Receiver<ContractEvent> {
+    let mut backoff = SUBSCRIBE_BACKOFF;
+    loop {
+        match request(contract_handler, ContractMessage::Subscribe(sid as usize, block_id), MAX_SUBSCRIBE_BACKOFF) {
+            Ok(ContractResponse::Events(events)) => return events,
+            Ok(_) => warn!("subscribe to contract events get wrong answer"),
+            Err(e) => warn!("unable to subscribe to contract events: {}, try again in {:?}", e, backoff),
+        }
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_SUBSCRIBE_BACKOFF);
+    }
+}
 
-    
+// curr hash of the contract after the block, sha256(curr_hash || sha256(header)),
+// for blocks whose event did not carry it
+fn chain_hash(curr_hash: &H256, header: &BlockHeader) -> H256 {
+    let header_bytes = serialize(header);
+    let mut hash = [0u8; 32];
+    let mut hasher = Sha256::new();
+    hasher.input(&header_bytes);
+    hasher.result(&mut hash);
+    let v = [curr_hash.0, hash].concat();
+    let mut sec_hasher = Sha256::new();
+    sec_hasher.input(&v);
+    sec_hasher.result(&mut hash);
+    H256(hash)
 }
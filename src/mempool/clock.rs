@@ -0,0 +1,253 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{self, Duration, SystemTime, Instant};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use crossbeam::channel::Sender;
+use super::contract::interface::{Handle, Retry, request_with_retry};
+use super::contract::interface::Error as ContractError;
+use super::contract::interface::Message as ContractMessage;
+use super::contract::interface::Response as ContractResponse;
+use super::scheduler::{SlotError, get_curr_slot};
+
+// source of the slot schedule, shared by the scheduler proposing in its own
+// slots and the performer checking proposals against them
+pub trait SlotClock: Send + Sync {
+    fn curr_slot(&self) -> u64;
+
+    // time until the slot opens, zero once it has. Clocks that cannot tell
+    // ahead of time return their polling interval
+    fn until_slot(&self, slot: u64) -> Duration;
+
+    // whether a proposal of the slot arriving now is on time
+    fn check_time(&self, slot: u64, max_skew_millis: u64) -> Result<(), SlotError>;
+
+    // false until the clock has read its source once
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    // time since the slot opened, None if the clock cannot tell
+    fn since_slot(&self, _slot: u64) -> Option<Duration> {
+        None
+    }
+}
+
+// slots counted from --start_time on the local clock
+pub struct WallClock {
+    start_sec: u64,
+    start_millis: u64,
+    slot_time: f32,
+}
+
+impl WallClock {
+    pub fn new(start_sec: u64, start_millis: u64, slot_time: f32) -> WallClock {
+        WallClock {
+            start_sec: start_sec,
+            start_millis: start_millis,
+            slot_time: slot_time,
+        }
+    }
+
+    fn open_millis(&self, slot: u64) -> u64 {
+        self.start_sec * 1000 + self.start_millis + slot * (self.slot_time * 1000.0) as u64
+    }
+}
+
+impl SlotClock for WallClock {
+    fn curr_slot(&self) -> u64 {
+        get_curr_slot(self.start_sec, self.start_millis, self.slot_time).0
+    }
+
+    fn until_slot(&self, slot: u64) -> Duration {
+        let open = self.open_millis(slot);
+        let now = now_millis();
+        Duration::from_millis(open.saturating_sub(now))
+    }
+
+    // the slot is widened by max_skew_millis on both ends for clock skew
+    fn check_time(&self, slot: u64, max_skew_millis: u64) -> Result<(), SlotError> {
+        let now_millis = now_millis();
+        let open = self.open_millis(slot);
+        let close = open + (self.slot_time * 1000.0) as u64;
+        if now_millis + max_skew_millis < open {
+            return Err(SlotError::Early(open - now_millis));
+        }
+        if now_millis > close + max_skew_millis {
+            return Err(SlotError::Late(now_millis - close));
+        }
+        Ok(())
+    }
+
+    fn since_slot(&self, slot: u64) -> Option<Duration> {
+        Some(Duration::from_millis(now_millis().saturating_sub(self.open_millis(slot))))
+    }
+}
+
+fn now_millis() -> u64 {
+    let curr_time = time::SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    curr_time.as_secs() * 1000 + curr_time.subsec_millis() as u64
+}
+
+// trusted chain state a chain clock counts slots in
+#[derive(Debug, Clone, Copy)]
+pub enum ChainSource {
+    // block id committed by the side chain in the contract, the proposer of
+    // the next block owns the slot until it is committed
+    ContractBlockId(u64),
+    // main chain block number, a slot every blocks_per_slot blocks
+    MainChainHeight { blocks_per_slot: u64 },
+}
+
+// slots a chain clock remembers the opening of
+const OPENED_HISTORY: usize = 64;
+
+// slots derived from trusted chain state polled through the contract
+// handler, the one clock every node already agrees on
+pub struct ChainClock {
+    source: ChainSource,
+    height: Arc<Mutex<Option<u64>>>, // latest reading of the source, None before the first
+    opened: Arc<Mutex<BTreeMap<u64, Instant>>>, // recent slots by when a reading first showed them
+    poll: Duration,
+    slot_time: f32, // only to report early and late proposals in millis
+}
+
+impl ChainClock {
+    // tries the first reading before returning, a clock still without one
+    // keeps polling and has no slots until it succeeds
+    pub fn start(
+        contract_handler: Sender<Handle>,
+        source: ChainSource,
+        poll: Duration,
+        slot_time: f32,
+    ) -> Arc<ChainClock> {
+        let first = match read_height(&contract_handler, &source, &Retry::default()) {
+            Ok(h) => Some(h),
+            Err(e) => {
+                warn!("unable to read {:?}: {}, keep polling", source, e);
+                None
+            }
+        };
+        let clock = Arc::new(ChainClock {
+            source: source,
+            height: Arc::new(Mutex::new(first)),
+            opened: Arc::new(Mutex::new(BTreeMap::new())),
+            poll: poll,
+            slot_time: slot_time,
+        });
+        let (height, opened) = (clock.height.clone(), clock.opened.clone());
+        let _ = thread::Builder::new()
+            .name("chain-clock".to_string())
+            .spawn(move || {
+                // the slot of the first reading opened at some unknown time before
+                let mut last_slot = first.map(|h| slot_of(&source, h));
+                loop {
+                    thread::sleep(poll);
+                    // a missed reading keeps the last one until the next poll
+                    let once = Retry { attempts: 1, ..Retry::default() };
+                    match read_height(&contract_handler, &source, &once) {
+                        Ok(h) => {
+                            *height.lock().unwrap() = Some(h);
+                            let slot = slot_of(&source, h);
+                            if last_slot.map_or(false, |last| slot > last) {
+                                let mut opened = opened.lock().unwrap();
+                                opened.insert(slot, Instant::now());
+                                while opened.len() > OPENED_HISTORY {
+                                    let oldest = *opened.keys().next().unwrap();
+                                    opened.remove(&oldest);
+                                }
+                            }
+                            last_slot = Some(slot);
+                        },
+                        Err(e) => warn!("unable to read {:?}: {}", source, e),
+                    }
+                }
+            });
+        clock
+    }
+
+    fn slot_millis(&self, slots: u64) -> u64 {
+        slots * (self.slot_time * 1000.0) as u64
+    }
+}
+
+impl SlotClock for ChainClock {
+    fn curr_slot(&self) -> u64 {
+        slot_of(&self.source, self.height.lock().unwrap().unwrap_or(0))
+    }
+
+    fn until_slot(&self, slot: u64) -> Duration {
+        if self.curr_slot() >= slot {
+            Duration::from_millis(0)
+        } else {
+            self.poll
+        }
+    }
+
+    // readings of different nodes lag by up to a poll, so a slot on either
+    // side of ours is tolerated, and as many more as max_skew_millis spans
+    fn check_time(&self, slot: u64, max_skew_millis: u64) -> Result<(), SlotError> {
+        if !self.is_ready() {
+            return Err(SlotError::NoSlot);
+        }
+        let curr_slot = self.curr_slot();
+        let slot_millis = self.slot_millis(1).max(1);
+        let tolerance = 1 + (max_skew_millis + slot_millis - 1) / slot_millis;
+        if slot > curr_slot + tolerance {
+            return Err(SlotError::Early(self.slot_millis(slot - curr_slot - tolerance)));
+        }
+        if slot + tolerance < curr_slot {
+            return Err(SlotError::Late(self.slot_millis(curr_slot - slot - tolerance)));
+        }
+        Ok(())
+    }
+
+    fn is_ready(&self) -> bool {
+        self.height.lock().unwrap().is_some()
+    }
+
+    // only known for slots this clock saw opening, up to a poll late
+    fn since_slot(&self, slot: u64) -> Option<Duration> {
+        self.opened.lock().unwrap().get(&slot).map(|opened| opened.elapsed())
+    }
+}
+
+fn slot_of(source: &ChainSource, height: u64) -> u64 {
+    match *source {
+        ChainSource::ContractBlockId(_) => height,
+        ChainSource::MainChainHeight { blocks_per_slot } => height / blocks_per_slot.max(1),
+    }
+}
+
+fn read_height(contract_handler: &Sender<Handle>, source: &ChainSource, retry: &Retry) -> Result<u64, ContractError> {
+    let message = match source {
+        ChainSource::ContractBlockId(sid) => ContractMessage::GetCurrState(*sid as usize),
+        ChainSource::MainChainHeight { .. } => ContractMessage::GetBlockNumber,
+    };
+    match request_with_retry(contract_handler, message, retry)? {
+        ContractResponse::GetCurrState(state) => Ok(state.block_id),
+        ContractResponse::BlockNumber(number) => Ok(number),
+        _ => Err(ContractError::Decode("invalid response type".to_string())),
+    }
+}
+
+// --slot_clock choices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockKind {
+    Wall,
+    Contract,
+    MainChain,
+}
+
+impl FromStr for ClockKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ClockKind, String> {
+        match s {
+            "wall" => Ok(ClockKind::Wall),
+            "contract" => Ok(ClockKind::Contract),
+            "main_chain" => Ok(ClockKind::MainChain),
+            _ => Err(format!("unknown slot clock {}, expect wall, contract or main_chain", s)),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::{HashMap};
 use super::hash::{H256};
-use super::block::{Block, Header};
+use super::block::{Header};
 use super::blockchain::{BlockChain};
 use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{self};
@@ -23,16 +23,27 @@ use primitives::bytes::{Bytes};
 use ser::{deserialize, serialize};
 use std::net::{SocketAddr};
 use rand::Rng;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{SystemTime, UNIX_EPOCH, Duration, Instant};
 use merkle;
 use ring::digest::{Algorithm, Context, SHA256};
 use merkle::{Hashable, MerkleTree, Proof};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use crate::mempool::scheduler;
+use crate::experiment::snapshot::PERFORMANCE_COUNTER;
+use crate::db::mempoolDb::MempoolDb;
+use super::status::{StatusBoard, TxStatus};
+use crate::network::message::{header_hash};
 #[allow(non_upper_case_globals)]
 static algorithm: &'static Algorithm = &SHA256;
 
+// transactions past their last status remembered for status queries, and
+// for how long
+const STATUS_CAPACITY: usize = 100000;
+const STATUS_MAX_AGE: Duration = Duration::from_secs(3600);
+// block ids an expired proposal is remembered for, in case it commits late
+const EXPIRED_HISTORY: u64 = 64;
+
 //struct Sample {
     //symbols: Vec<Vec<Symbols>>,
     //indices: Vec<Vec<u64>>,
@@ -60,12 +71,75 @@ static algorithm: &'static Algorithm = &SHA256;
     //}
 //}
 
+// which transactions give way once the mempool is full, fees do not exist
+// yet so there is no fee based policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    Oldest,    // evict the oldest transactions to admit new ones
+    RejectNew, // keep the pool, reject arrivals
+}
+
+impl std::str::FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EvictionPolicy, String> {
+        match s {
+            "oldest" => Ok(EvictionPolicy::Oldest),
+            "reject" => Ok(EvictionPolicy::RejectNew),
+            _ => Err(format!("unknown eviction policy {}, expect oldest or reject", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MempoolConfig {
+    pub max_bytes: usize, // serialized transactions kept at most
+    pub eviction: EvictionPolicy,
+    pub proposal_timeout: Duration, // own proposals not committed by then are requeued
+    pub keep_blocks: usize, // latest blocks whose dispersal state is persisted
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        MempoolConfig {
+            max_bytes: 8 * BLOCK_SIZE as usize,
+            eviction: EvictionPolicy::Oldest,
+            proposal_timeout: Duration::from_secs(60),
+            keep_blocks: 8,
+        }
+    }
+}
+
+// why a transaction was not admitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionError {
+    Duplicate,       // same hash already pending
+    TooLarge(usize), // does not fit in a block
+    Full,            // over max_bytes and the policy rejects new ones
+}
+
+// an own proposal, its transactions stay out of the pool until the block is
+// seen committed
+struct InFlight {
+    header: BlockHeader,
+    transactions: Vec<Transaction>,
+    proposed: Instant,
+}
+
 pub struct Mempool {
-    transactions: VecDeque<Transaction>,
+    transactions: VecDeque<(CMTH256, Transaction, usize)>, // hash, tx, size, oldest first
+    index: HashSet<CMTH256>, // hashes of pending transactions
+    num_bytes: usize,
+    config: MempoolConfig,
     block_size: usize,
     contract_handler: Sender<Handle>,
     schedule_handler: Sender<scheduler::Signal>,
-    returned_blocks: VecDeque<Block>,
+    in_flight: HashMap<u64, InFlight>, // by block id
+    committed: HashMap<u64, InFlight>, // own blocks committed but not confirmed, by block id
+    expired: HashMap<u64, (BlockHeader, Vec<CMTH256>)>, // requeued proposals by block id, header and tx hashes
+    db: Option<MempoolDb>, // persists pending transactions and dispersal state
+    next_seq: u64, // arrival order of transactions in the db
+    status: StatusBoard,
     symbols_by: HashMap<u64, HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>) > >,
     headers_by: HashMap<u64, BlockHeader>,
     //block_by: HashMap<u64, CMTBlock>,
@@ -81,43 +155,383 @@ impl Mempool {
         addr: SocketAddr,
         codes_for_encoding: Vec<Code>,
         codes_for_decoding: Vec<Code>,
+        config: MempoolConfig,
+        db: Option<MempoolDb>,
     ) -> Mempool {
         
-        Mempool {
+        let mut mempool = Mempool {
             transactions: VecDeque::with_capacity(200000), 
+            index: HashSet::new(),
+            num_bytes: 0,
+            config: config,
             block_size: BLOCK_SIZE as usize, // in bytes
             contract_handler: contract_handler,
             schedule_handler: schedule_handler,
-            returned_blocks: VecDeque::new(),
+            in_flight: HashMap::new(),
+            committed: HashMap::new(),
+            expired: HashMap::new(),
+            db: db,
+            next_seq: 0,
+            status: StatusBoard::new(STATUS_CAPACITY, STATUS_MAX_AGE),
             symbols_by: HashMap::new(),
             //block_by: HashMap::new(),
             addr: addr,
             codes_for_encoding: codes_for_encoding,
             codes_for_decoding: codes_for_decoding,
             headers_by: HashMap::new(),
-        } 
+        };
+        mempool.recover();
+        mempool
     }
 
-    pub fn transaction_size_in_bytes(&self) -> usize {
-        let mut trans_byte = self.transactions.iter().map(Transaction::bytes).collect::<Vec<Bytes>>();
-        let mut total_size = 0;
-        for tx in &trans_byte {
-            total_size +=  tx.len();
+    // replay what the db kept before a restart. Own proposals not known
+    // final are in flight again, to commit, be superseded or expire
+    fn recover(&mut self) {
+        let db = match &self.db {
+            Some(db) => db,
+            None => return,
+        };
+        let transactions = db.get_transactions();
+        let dispersals = db.get_dispersals();
+        let proposals = db.get_proposals();
+        for (block_id, (header, transactions)) in proposals {
+            for tx in transactions.iter() {
+                self.status.set(&tx.hash(), TxStatus::Proposed(block_id));
+            }
+            self.in_flight.insert(block_id, InFlight {
+                header: header,
+                transactions: transactions,
+                proposed: Instant::now(),
+            });
+        }
+        let proposed: HashSet<CMTH256> = self.in_flight.
+            values().
+            flat_map(|p| p.transactions.iter().map(|tx| tx.hash())).
+            collect();
+        for (seq, tx) in transactions {
+            let hash = tx.hash();
+            self.next_seq = self.next_seq.max(seq + 1);
+            if self.index.contains(&hash) || proposed.contains(&hash) {
+                continue;
+            }
+            let size = tx.bytes().len();
+            self.status.set(&hash, TxStatus::Pending);
+            self.index.insert(hash.clone());
+            self.transactions.push_back((hash, tx, size));
+            self.num_bytes += size;
+        }
+        for (block_id, (header, symbols_by_scale_id)) in dispersals {
+            self.headers_by.insert(block_id, header);
+            self.symbols_by.insert(block_id, symbols_by_scale_id);
         }
-        total_size
+        info!("{:?} mempool recovered {} transactions, {} proposals in flight and {} dispersed blocks", 
+            self.addr, self.transactions.len(), self.in_flight.len(), self.symbols_by.len());
+    }
+
+    pub fn transaction_size_in_bytes(&self) -> usize {
+        self.num_bytes
+    }
+
+    pub fn get_block_size(&self) -> usize {
+        self.block_size
     }
 
     pub fn change_mempool_size(&mut self, size: usize) {
         self.block_size = size;
     }
 
+    // shrinking the cap evicts right away, whatever the policy
+    pub fn change_max_bytes(&mut self, max_bytes: usize) {
+        self.config.max_bytes = max_bytes;
+        while self.num_bytes > self.config.max_bytes {
+            self.evict_oldest();
+        }
+    }
+
+    pub fn get_max_bytes(&self) -> usize {
+        self.config.max_bytes
+    }
+
+    pub fn contains(&self, hash: &CMTH256) -> bool {
+        self.index.contains(hash)
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some((hash, _, size)) = self.transactions.pop_front() {
+            self.index.remove(&hash);
+            self.num_bytes -= size;
+            if let Some(db) = &mut self.db {
+                db.remove_transaction(&hash);
+            }
+            self.status.remove(&hash);
+            PERFORMANCE_COUNTER.record_evicted_transaction();
+        }
+    }
+
+    // out of the pool in a block, an own one in flight or committed, a
+    // decoded one, or requeued from an expired proposal that may still commit
+    fn is_in_block(&self, hash: &CMTH256) -> bool {
+        let in_block = match self.status.get(hash) {
+            Some(TxStatus::Proposed(_)) | Some(TxStatus::Committed(_)) | Some(TxStatus::Retrievable(_)) => true,
+            _ => false,
+        };
+        in_block || self.expired.values().any(|(_, hashes)| hashes.contains(hash))
+    }
+
+    fn admit(&mut self, transaction: Transaction) -> Result<(), AdmissionError> {
+        let hash = transaction.hash();
+        if self.index.contains(&hash) || self.is_in_block(&hash) {
+            return Err(AdmissionError::Duplicate);
+        }
+        let size = transaction.bytes().len();
+        if size > self.block_size || size > self.config.max_bytes {
+            return Err(AdmissionError::TooLarge(size));
+        }
+        if self.num_bytes + size > self.config.max_bytes {
+            match self.config.eviction {
+                EvictionPolicy::RejectNew => return Err(AdmissionError::Full),
+                EvictionPolicy::Oldest => {
+                    while self.num_bytes + size > self.config.max_bytes {
+                        self.evict_oldest();
+                    }
+                },
+            }
+        }
+        if let Some(db) = &mut self.db {
+            db.insert_transaction(&hash, self.next_seq, &transaction);
+        }
+        self.next_seq += 1;
+        self.status.set(&hash, TxStatus::Pending);
+        self.index.insert(hash.clone());
+        self.transactions.push_back((hash, transaction, size));
+        self.num_bytes += size;
+        Ok(())
+    }
+
     pub fn get_num_transaction(&self) -> u64 {
         return self.transactions.len() as u64;
     }
 
-    // TODO change the height in the header, but leave to future when tx has meaning
-    pub fn return_block(&mut self, block: Block) {
-        self.returned_blocks.push_back(block);
+    // put transactions of a failed proposal back in front of the pool, those
+    // submitted again in the meantime are pending already. Being the oldest,
+    // they are the first evicted when the pool is over its cap
+    fn requeue(&mut self, transactions: Vec<Transaction>) {
+        let mut num = 0;
+        for tx in transactions.into_iter().rev() {
+            let hash = tx.hash();
+            if self.index.contains(&hash) {
+                continue;
+            }
+            let size = tx.bytes().len();
+            self.status.set(&hash, TxStatus::Pending);
+            self.index.insert(hash.clone());
+            self.transactions.push_front((hash, tx, size));
+            self.num_bytes += size;
+            num += 1;
+        }
+        PERFORMANCE_COUNTER.record_requeued_transactions(num);
+        while self.num_bytes > self.config.max_bytes {
+            self.evict_oldest();
+        }
+    }
+
+    pub fn track_proposal(&mut self, block_id: u64, header: &BlockHeader, transactions: Vec<Transaction>) {
+        for tx in transactions.iter() {
+            self.status.set(&tx.hash(), TxStatus::Proposed(block_id));
+        }
+        if let Some(db) = &mut self.db {
+            db.insert_proposal(block_id, header, &transactions);
+        }
+        let proposal = InFlight {
+            header: header.clone(),
+            transactions: transactions,
+            proposed: Instant::now(),
+        };
+        // the block id is proposed anew, an expired proposal of it is no
+        // longer reconciled
+        self.expired.remove(&block_id);
+        if let Some(replaced) = self.in_flight.insert(block_id, proposal) {
+            self.requeue(replaced.transactions);
+        }
+    }
+
+    // the proposal was never sent, or is known not to make it
+    pub fn fail_proposal(&mut self, block_id: u64) {
+        if let Some(proposal) = self.in_flight.remove(&block_id) {
+            info!("{:?} requeue {} transactions of failed block {}", self.addr, proposal.transactions.len(), block_id);
+            self.requeue(proposal.transactions);
+        }
+        self.forget_proposal(block_id);
+        self.remove_block(block_id);
+    }
+
+    fn forget_proposal(&mut self, block_id: u64) {
+        if let Some(db) = &mut self.db {
+            db.remove_proposal(block_id);
+        }
+    }
+
+    // a block committed on the main chain, ours or one superseding ours
+    pub fn commit_block(&mut self, block_id: u64, header: &BlockHeader) {
+        let proposal = match self.in_flight.remove(&block_id) {
+            Some(p) => p,
+            None => {
+                self.commit_expired(block_id, header);
+                return;
+            }
+        };
+        if proposal.header == *header {
+            // a main chain reorg may still take the block back
+            info!("{:?} block {} committed with {} transactions", self.addr, block_id, proposal.transactions.len());
+            self.committed.insert(block_id, proposal);
+        } else {
+            info!("{:?} block {} superseded, requeue {} transactions", self.addr, block_id, proposal.transactions.len());
+            self.forget_proposal(block_id);
+            self.requeue(proposal.transactions);
+        }
+    }
+
+    // an expired proposal committed after all, its requeued transactions are
+    // taken out of the pool again
+    fn commit_expired(&mut self, block_id: u64, header: &BlockHeader) {
+        let (expired_header, hashes) = match self.expired.remove(&block_id) {
+            Some(e) => e,
+            None => return,
+        };
+        if expired_header == *header {
+            info!("{:?} expired block {} committed late, withdraw {} transactions", self.addr, block_id, hashes.len());
+            self.withdraw(&hashes);
+        } else {
+            self.remove_block(block_id);
+        }
+    }
+
+    // drop pending transactions by hash, wherever they are in the queue
+    fn withdraw(&mut self, hashes: &[CMTH256]) {
+        let hashes: HashSet<&CMTH256> = hashes.iter().filter(|h| self.index.contains(*h)).collect();
+        if hashes.is_empty() {
+            return;
+        }
+        let mut num_bytes = self.num_bytes;
+        self.transactions.retain(|(hash, _, size)| {
+            if hashes.contains(hash) {
+                num_bytes -= size;
+                false
+            } else {
+                true
+            }
+        });
+        self.num_bytes = num_bytes;
+        for hash in hashes {
+            self.index.remove(hash);
+            if let Some(db) = &mut self.db {
+                db.remove_transaction(hash);
+            }
+        }
+    }
+
+    // an own proposal made it when the trusted chain commits its header. A
+    // commit found by polling has no header hash, its block is resolved
+    // once decoded
+    pub fn observe_tip(&mut self, block_id: u64, committed_hash: Option<[u8; 32]>) {
+        let (proposal, committed_hash) = match (self.in_flight.get(&block_id), committed_hash) {
+            (Some(proposal), Some(committed_hash)) => (proposal, committed_hash),
+            _ => return,
+        };
+        if header_hash(&proposal.header).0 != committed_hash {
+            return;
+        }
+        for tx in proposal.transactions.iter() {
+            self.status.set(&tx.hash(), TxStatus::Committed(block_id));
+        }
+    }
+
+    // transactions of a block decoded from scale nodes, whoever proposed it,
+    // forwarded ones included
+    pub fn record_decoded(&mut self, block_id: u64, transactions: &[Transaction]) {
+        for tx in transactions {
+            self.status.set(&tx.hash(), TxStatus::Retrievable(block_id));
+        }
+    }
+
+    // own blocks a reorg took back are in flight again, to commit again,
+    // be superseded or expire like any proposal
+    pub fn revert_blocks(&mut self, block_ids: &[u64]) {
+        if !block_ids.is_empty() {
+            self.status.revert(block_ids);
+        }
+        for block_id in block_ids {
+            if let Some(mut proposal) = self.committed.remove(block_id) {
+                info!("{:?} block {} reverted, {} transactions in flight again", self.addr, block_id, proposal.transactions.len());
+                proposal.proposed = Instant::now();
+                self.in_flight.insert(*block_id, proposal);
+            }
+        }
+    }
+
+    // own blocks up to the confirmed block id are final, their transactions
+    // are forgotten
+    pub fn confirm_blocks(&mut self, confirmed_block_id: u64) {
+        let confirmed: Vec<u64> = self.committed.
+            keys().
+            filter(|id| **id <= confirmed_block_id).
+            cloned().
+            collect();
+        for block_id in confirmed {
+            let proposal = self.committed.remove(&block_id).expect("committed block");
+            self.forget_proposal(block_id);
+            if let Some(db) = &mut self.db {
+                for tx in proposal.transactions.iter() {
+                    db.remove_transaction(&tx.hash());
+                }
+            }
+        }
+    }
+
+    pub fn record_forwarded(&mut self, hash: &CMTH256, addr: SocketAddr) {
+        self.status.set(hash, TxStatus::Forwarded(addr));
+    }
+
+    pub fn get_status(&self, hash: &CMTH256) -> Option<TxStatus> {
+        self.status.get(hash)
+    }
+
+    // proposals not seen committed within proposal_timeout. The block id is
+    // not resolved yet, so its samples stay and a late commit is reconciled
+    // through the tombstone
+    pub fn expire_proposals(&mut self) {
+        let timeout = self.config.proposal_timeout;
+        let expired: Vec<u64> = self.in_flight.
+            iter().
+            filter(|(_, p)| p.proposed.elapsed() >= timeout).
+            map(|(block_id, _)| *block_id).
+            collect();
+        for block_id in expired {
+            warn!("{:?} block {} not committed in {:?}", self.addr, block_id, timeout);
+            let proposal = self.in_flight.remove(&block_id).expect("expired proposal in flight");
+            self.forget_proposal(block_id);
+            let hashes = proposal.transactions.iter().map(|tx| tx.hash()).collect();
+            self.expired.insert(block_id, (proposal.header, hashes));
+            self.requeue(proposal.transactions);
+        }
+
+        let newest = match self.expired.keys().max() {
+            Some(newest) => *newest,
+            None => return,
+        };
+        let forgotten: Vec<u64> = self.expired.
+            keys().
+            filter(|id| **id + EXPIRED_HISTORY < newest).
+            cloned().
+            collect();
+        for block_id in forgotten {
+            self.expired.remove(&block_id);
+            self.remove_block(block_id);
+        }
+    }
+
+    pub fn num_in_flight(&self) -> usize {
+        self.in_flight.len()
     }
 
     pub fn insert_symbols(
@@ -126,12 +540,15 @@ impl Mempool {
         block_header: &BlockHeader,
         symbols_by_scale_id: HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>)>
     ) {
+        if let Some(db) = &mut self.db {
+            db.insert_dispersal(block_id, block_header, &symbols_by_scale_id, self.config.keep_blocks);
+        }
         self.headers_by.insert(block_id, block_header.clone());
         self.symbols_by.insert(block_id, symbols_by_scale_id);
     }
 
     pub fn get_cmt_sample(&mut self, block_id: u64, scale_id: u64) 
-        -> (BlockHeader, Vec<Vec<Symbol>>, Vec<Vec<u64>>) {
+        -> Option<(BlockHeader, Vec<Vec<Symbol>>, Vec<Vec<u64>>)> {
          match self.symbols_by.get(&block_id) {
             Some(symbols_by) => {
                 match symbols_by.get(&scale_id) {
@@ -140,20 +557,20 @@ impl Mempool {
                             Some(h) => h.clone(),
                             None => {
                                 info!("I don't have cmt header for block id {}", block_id);
-                                unreachable!();
+                                return None;
                             },
                         };
-                        return (header, s.clone(), i.clone())
+                        return Some((header, s.clone(), i.clone()))
                     },
                     None => {
                         info!("I have cmt symbols for block id {}, but not have for scale node {}", block_id, scale_id);
-                        unreachable!();
+                        return None;
                     }
                 }
             },
             None => {
                 info!("I don't have cmt symbols for block id {}", block_id);
-                unreachable!();
+                return None;
             }
          }
     }
@@ -213,30 +630,22 @@ impl Mempool {
         //}
     //}
 
+    // first fit in arrival order, a transaction too large for the space left
+    // gives way to smaller ones behind it
     pub fn package_trans(&mut self, transactions: &mut Vec<Transaction>) {
-        let tx_bytes_size = self.transaction_size_in_bytes();
-        if tx_bytes_size > self.block_size {
-            let mut s = 0;
-            for i in 0..self.transactions.len() {
-                s += self.transactions[i].bytes().len();
-                if s > self.block_size {
-                    if self.transactions.len() == 0 {
-                        panic!("single transaction too large, block size is insufficient");
-                    }
-                    break;
-                } else {
-                    transactions.push(self.transactions[i].clone());
-                }
-            }
-            for _ in 0..transactions.len() {
-                self.transactions.pop_front();
-            }
-        } else {
-            for tx in &self.transactions {
-                transactions.push(tx.clone());
+        let mut space = self.block_size;
+        let mut kept = VecDeque::with_capacity(self.transactions.len());
+        for (hash, tx, size) in self.transactions.drain(..) {
+            if size <= space {
+                space -= size;
+                self.index.remove(&hash);
+                self.num_bytes -= size;
+                transactions.push(tx);
+            } else {
+                kept.push_back((hash, tx, size));
             }
-            self.transactions.clear();
         }
+        self.transactions = kept;
         //let mut trans_byte = transactions.iter().map(Transaction::bytes).collect::<Vec<Bytes>>();
         //let mut total_size = 0;
         //for tx in &trans_byte {
@@ -297,27 +706,31 @@ impl Mempool {
         info!("mempool remove {}", block_id);
         self.symbols_by.remove(&block_id);
         self.headers_by.remove(&block_id);
+        if let Some(db) = &mut self.db {
+            db.remove_dispersal(block_id);
+        }
     }
 
     
-   pub fn insert(&mut self, transaction: Transaction) {
-        self.transactions.push_back(transaction);
-        let tx_bytes_size = self.transaction_size_in_bytes();
-
-        // need to truncate 
-        if tx_bytes_size > 0 {//self.block_size {
+    pub fn insert(&mut self, transaction: Transaction) -> Result<(), AdmissionError> {
+        let was_full_block = self.num_bytes > self.block_size;
+        let result = self.admit(transaction);
+        if result.is_err() {
+            PERFORMANCE_COUNTER.record_rejected_transaction();
+        }
+        // tell the scheduler once a block worth of transactions is pending
+        if !was_full_block && self.num_bytes > self.block_size {
             self.schedule_handler.send(scheduler::Signal::Control);
         }
+        result
     }
 
-
-    pub fn insert_transactions(&mut self, transactions: Vec<Transaction>) {
-        self.transactions.extend(transactions);
-        let tx_bytes_size = self.transaction_size_in_bytes();
-        //info!("tx_bytes_size {} num {}", tx_bytes_size, self.transactions.len());
-        if tx_bytes_size > self.block_size {
-            self.schedule_handler.send(scheduler::Signal::Control);
-        }
+    // one result per transaction, in order
+    pub fn insert_transactions(&mut self, transactions: Vec<Transaction>) -> Vec<Result<(), AdmissionError>> {
+        transactions.
+            into_iter().
+            map(|tx| self.insert(tx)).
+            collect()
     }
 
     pub fn estimate_gas(&mut self, transaction: Transaction) {
@@ -6,6 +6,8 @@ use super::cmtda;
 
 pub mod mempool;
 pub mod scheduler;
+pub mod clock;
+pub mod status;
 
 
 
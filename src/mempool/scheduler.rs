@@ -1,12 +1,13 @@
 use std::sync::{Arc, Mutex};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use serde::{Serialize, Deserialize};
 use std::net::{SocketAddr};
 use super::mempool::{Mempool};
-use super::message::{Message, ServerSignal};
+use super::clock::{SlotClock};
+use super::message::{Message, ServerSignal, Proposal, header_hash};
 use super::blockchain::{BlockChain};
 use mio_extras::channel::Sender as MioSender;
-use crossbeam::channel::{Receiver, Sender, self};
+use crossbeam::channel::{Receiver, Sender, RecvTimeoutError, self};
 use std::{thread, time};
 use super::cmtda::{BlockHeader, Block, H256, HEADER_SIZE, Transaction, read_codes};
 use super::contract::utils;
@@ -16,10 +17,12 @@ use super::contract::interface::Message as ContractMessage;
 use super::contract::interface::Response as ContractResponse;
 use crypto::sha3::Sha3;
 use crypto::digest::Digest;
-use std::time::{SystemTime, UNIX_EPOCH, Duration};
-use web3::types::Address;
+use std::time::{SystemTime, UNIX_EPOCH, Duration, Instant};
+use web3::types::{Address, U256};
 use crate::experiment::snapshot::PERFORMANCE_COUNTER;
-use chain::constants::{TRANSACTION_SIZE, BLOCK_SIZE, BASE_SYMBOL_SIZE, RATE, UNDECODABLE_RATIO};
+use crate::experiment::adversary::Adversary;
+use crate::network::discovery::{ScaleSet, parse_registered_addr};
+use chain::constants::{BLOCK_SIZE, BASE_SYMBOL_SIZE, RATE, UNDECODABLE_RATIO};
 
 use chain::decoder::{Code, Decoder, TreeDecoder, CodingErr, IncorrectCodingProof};
 use chain::decoder::{Symbol};
@@ -27,11 +30,121 @@ use super::cmtda::Block as CMTBlock;
 use super::cmtda::H256 as CMTH256;
 use rand::Rng;
 
+// polling interval of the token ring scheduler, and of the slot scheduler
+// while its clock has no reading
+const TOKEN_POLL: Duration = Duration::from_millis(100);
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Token {
-    pub version: usize,
+    pub version: usize, // bumped whenever a lost token is regenerated
     pub ring_size: usize,
     pub node_list: Vec<SocketAddr>,
+    pub holder: usize,  // index of the holder in node_list
+    pub hop: u64,       // passes since the token was created
+    pub block_id: u64,  // next block proposed by the holder
+    pub slot: u64,      // clock slot of the hand-off
+    pub signature: Vec<u8>, // by the passer, or by the holder of a token it created
+}
+
+impl Token {
+    pub fn new(node_list: Vec<SocketAddr>) -> Token {
+        Token {
+            version: 0,
+            ring_size: node_list.len(),
+            node_list: node_list,
+            holder: 0,
+            hop: 0,
+            block_id: 1,
+            slot: 0,
+            signature: vec![],
+        }
+    }
+
+    // later version wins, then the later pass
+    pub fn is_newer_than(&self, other: &Token) -> bool {
+        (self.version, self.hop) > (other.version, other.hop)
+    }
+
+    // keccak of every field but the signature, bound to the side chain
+    pub fn digest(&self, sid: u64) -> [u8; 32] {
+        let fields = (sid, self.version, self.ring_size, &self.node_list, self.holder, self.hop, self.block_id, self.slot);
+        let message = bincode::serialize(&fields).unwrap();
+        let mut digest = [0u8; 32];
+        utils::hash_header(&message, &mut digest);
+        digest
+    }
+
+    pub fn sign(&mut self, sid: u64, private_key: &[u8]) -> bool {
+        match utils::sign_digest(&self.digest(sid), private_key) {
+            Some(signature) => {
+                self.signature = signature;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // the side node handing the token over, the holder itself for a token
+    // it created or regenerated
+    pub fn passer(&self) -> usize {
+        if self.hop == 0 {
+            self.holder
+        } else {
+            (self.holder + self.ring_size - 1) % self.ring_size
+        }
+    }
+
+    // the ring position allowed to create a token in its slot, the first side
+    // node for the initial token
+    pub fn minter(&self) -> usize {
+        if self.version == 0 {
+            0
+        } else {
+            (self.slot % self.ring_size as u64) as usize
+        }
+    }
+
+    // created while the ring still passes a known token, less than timeout
+    // slots before
+    pub fn is_premature(&self, known: &Token, timeout: f32) -> bool {
+        self.hop == 0 && known.hop > 0 && known.node_list == self.node_list &&
+            known.slot + timeout as u64 > self.slot
+    }
+
+    pub fn is_well_formed(&self) -> bool {
+        self.ring_size > 0 && self.node_list.len() == self.ring_size && self.holder < self.ring_size
+    }
+
+    // signed by the registered account of the passer, a created token only
+    // by the minter of its slot
+    pub fn verify(&self, sid: u64, accounts: &HashMap<SocketAddr, Address>) -> bool {
+        if !self.is_well_formed() || (self.hop == 0 && self.holder != self.minter()) {
+            return false;
+        }
+        match accounts.get(&self.node_list[self.passer()]) {
+            Some(account) => utils::recover_address(&self.digest(sid), &self.signature) == Some(*account),
+            None => false,
+        }
+    }
+}
+
+// how side nodes of a side chain take turns proposing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulingMode {
+    Slot,      // wall clock slots counted from --start_time
+    TokenRing, // a token passed around the side node ring
+}
+
+impl std::str::FromStr for SchedulingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SchedulingMode, String> {
+        match s {
+            "slot" => Ok(SchedulingMode::Slot),
+            "token" => Ok(SchedulingMode::TokenRing),
+            _ => Err(format!("unknown scheduling mode {}, expect slot or token", s)),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -48,16 +161,28 @@ pub struct Scheduler {
     pub contract_handler: Sender<Handle>,
     pub handle: Receiver<Signal>,
     pub chain: Arc<Mutex<BlockChain>>, 
-    //pub side_id: u64,
+    pub sid: u64, // side chain proposed to
     pub sidenodes: Vec<SocketAddr>,
+    pub accounts: HashMap<SocketAddr, Address>, // registered account of each side node
     pub address: Address,
+    pub private_key: Vec<u8>, // signs proposals
     pub slot_time: f32, 
-    pub start_sec: u64, 
-    pub start_millis: u64,
+    pub clock: Arc<dyn SlotClock>,
+    pub proposed_slot: Option<u64>, // a chain clock may stay in a slot for long
+    pub last_proposal: Option<(Proposal, Option<Proposal>, Instant)>, // sent in proposed_slot, its equivocating twin, and when last sent
     pub prepared_block: Option<BlockHeader>,
-    pub num_nodes: u64, //scale nodes
-    pub symbols_by: Option<HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>)>>,
+    pub prepared_trans: Vec<Transaction>, // of the prepared block, tracked by the mempool once proposed
+    pub scale_set: Arc<Mutex<ScaleSet>>, // scale nodes sampled for
+    pub redundancy: f32, // times each symbol is sampled on average
+    pub adversary: Adversary,
+    pub prepared_cmt: Option<CMTBlock>, // coded prepared block, sampled once its block id is known
     pub codes_for_encoding: Vec<Code>,
+    pub mode: SchedulingMode,
+    pub token_timeout: f32, // slots without a token pass before regenerating
+    pub last_token: Option<Token>, // latest token seen passing in the ring
+    pub last_seen: Instant,
+    pub pack_deadline: f32, // fraction of its slot a proposer waits for a full block
+    pub slot_entered: Option<(u64, Instant)>, // own slot and when it was first seen
 }
 
 impl Scheduler {
@@ -69,14 +194,20 @@ impl Scheduler {
         handle: Receiver<Signal>,
         chain: Arc<Mutex<BlockChain>>,
         contract_handler: Sender<Handle>,
-        //side_id: u64,
+        sid: u64,
         sidenodes: Vec<SocketAddr>,
+        accounts: HashMap<SocketAddr, Address>,
         address: Address,
+        private_key: Vec<u8>,
         slot_time: f32,
-        start_sec: u64,
-        start_millis: u64,
-        num_scale: u64,
-        codes_for_encoding: Vec<Code>
+        clock: Arc<dyn SlotClock>,
+        scale_set: Arc<Mutex<ScaleSet>>,
+        codes_for_encoding: Vec<Code>,
+        redundancy: f32,
+        adversary: Adversary,
+        mode: SchedulingMode,
+        token_timeout: f32,
+        pack_deadline: f32,
     ) -> Scheduler {
         Scheduler {
             addr,
@@ -86,16 +217,28 @@ impl Scheduler {
             contract_handler,
             handle,
             chain: chain,
-            //side_id,
+            sid,
             sidenodes,
+            accounts,
             address,
+            private_key,
             slot_time: slot_time,
-            start_sec: start_sec,
-            start_millis: start_millis,
+            clock: clock,
+            proposed_slot: None,
+            last_proposal: None,
             prepared_block: None,
-            num_nodes: num_scale,
-            symbols_by: None,
+            prepared_trans: vec![],
+            scale_set: scale_set,
+            redundancy: redundancy,
+            adversary: adversary,
+            prepared_cmt: None,
             codes_for_encoding: codes_for_encoding,
+            mode: mode,
+            token_timeout: token_timeout,
+            last_token: None,
+            last_seen: Instant::now(),
+            pack_deadline: pack_deadline,
+            slot_entered: None,
         }
     }
 
@@ -112,35 +255,6 @@ impl Scheduler {
 
     
 
-    //pub fn start(mut self) {
-        //let _ = std::thread::spawn(move || {
-            //loop {
-                //match self.handle.recv() {
-                    //Ok(v) => {
-                        //match v {
-                            //Signal::Control => {
-                                //// try transmit
-                                //match self.token.as_mut() {
-                                    //None => (),
-                                    //Some(token) => {
-                                        ////info!("with token, propose a block");
-                                        //self.propose_block();
-                                    //},
-                                //}
-                            //},
-                            //Signal::Data(token) => {
-                                ////info!("reiceive a token, propose a block");
-                                //self.token = Some(token);
-                                //self.propose_block();
-                            //}
-                        //}
-                    //},
-                    //Err(e) => info!("scheduler error"),
-                //}
-            //}
-        //});
-    //}
-
     //pub fn get_time_diff() {
         //let curr_time: u64 = match time::SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
             //Ok(n) => n.as_secs(),
@@ -160,54 +274,61 @@ impl Scheduler {
         }
     }
 
-    pub fn start(mut self) {
-        info!("scheduler started");
+    pub fn start(self) {
+        info!("scheduler of side chain {} started in {:?} mode", self.sid, self.mode);
+        match self.mode {
+            SchedulingMode::Slot => self.start_slot(),
+            SchedulingMode::TokenRing => self.start_token_ring(),
+        }
+    }
+
+    fn start_slot(mut self) {
         let _ = std::thread::spawn(move || {
             loop {
+                if !self.clock.is_ready() {
+                    thread::sleep(TOKEN_POLL);
+                    continue;
+                }
                 // setup
                 let round = self.sidenodes.len() as u64;
-                let round_millis = (self.slot_time * 1000.0) as u64 * round ;
                 let side_id = self.get_side_id();     
-                // pipelining
-                match &self.prepared_block {
-                    None => {
-                        //info!("start preparing a block");
-                        match self.prepare_block() {
-                            None => thread::sleep(time::Duration::from_millis(100)),
-                            _ => (),
-                        }
-                    }, 
-                    Some(h) => { 
-                        let (curr_slot, elapsed) = get_curr_slot(self.start_sec, self.start_millis, self.slot_time);
-
-                        let curr_id = curr_slot % round;
-                        // my slot
-                        if curr_id == side_id {
-                            PERFORMANCE_COUNTER.record_token_update(true);
-                            if self.propose_block() {
-                                let (curr_slot, elapsed) = get_curr_slot(self.start_sec, self.start_millis, self.slot_time);
-                                // go over the deadline
-                                if curr_slot%round != side_id {
-                                    PERFORMANCE_COUNTER.record_token_update(false);
-                                    continue;
-                                } else {
-                                    // to next slot
-                                    let target_time = ((side_id+1) * (self.slot_time*1000.0) as u64)  - elapsed%round_millis;
-                                    thread::sleep(time::Duration::from_millis(target_time));
-                                    PERFORMANCE_COUNTER.record_token_update(false);
-                                }
-                            } 
-                        } else if curr_id < side_id {
-                            PERFORMANCE_COUNTER.record_token_update(false);
-                            let target = side_id*(self.slot_time* 1000.0) as u64 - elapsed%round_millis;
-                            thread::sleep(time::Duration::from_millis(target));
+                let curr_slot = self.clock.curr_slot();
+                // a chain clock only moves on once the block commits
+                if curr_slot % round == side_id && self.proposed_slot == Some(curr_slot) {
+                    self.repropose();
+                }
+                // my slot
+                let my_slot = curr_slot % round == side_id && self.proposed_slot != Some(curr_slot);
+                // pipelining, a full block is packed ahead of the slot
+                if self.prepared_block.is_none() {
+                    let partial = my_slot && self.past_pack_deadline(curr_slot);
+                    if self.prepare_block(partial).is_none() {
+                        // woken early once the mempool fills a block
+                        let wait = if !my_slot {
+                            self.clock.until_slot(self.my_next_slot())
+                        } else if partial {
+                            // nothing pending at all, wait out the slot
+                            self.clock.until_slot(curr_slot + 1)
                         } else {
-                            PERFORMANCE_COUNTER.record_token_update(false);
-                            let time_left = round*(self.slot_time*1000.0) as u64 - elapsed%round_millis + side_id*(self.slot_time*1000.0) as u64;
-                            thread::sleep(time::Duration::from_millis(time_left));
-                        }
+                            self.until_pack_deadline(curr_slot)
+                        };
+                        self.wait_for_block(wait);
+                        continue;
                     }
                 }
+                if my_slot {
+                    PERFORMANCE_COUNTER.record_token_update(true);
+                    // a hack, to make sure curr_slot > 0, otherwise block rejected
+                    if self.propose_block(curr_slot + 1) {
+                        self.proposed_slot = Some(curr_slot);
+                        // to next slot, no wait if over the deadline
+                        thread::sleep(self.clock.until_slot(curr_slot + 1));
+                        PERFORMANCE_COUNTER.record_token_update(false);
+                    } 
+                } else {
+                    PERFORMANCE_COUNTER.record_token_update(false);
+                    thread::sleep(self.clock.until_slot(self.my_next_slot()));
+                }
             }
         });
     }
@@ -244,27 +365,157 @@ impl Scheduler {
         //thread::sleep(sleep_sec);
     //}
 
-    //fn pass_token(&mut self, token: Token) {
-        //info!("{:?} passing token.", self.addr);
-        //if token.ring_size >= 2 {
-            //let mut index = 0;
-            //for sock in &token.node_list {
-                //if *sock == self.addr {
-                    //let next_index = (index + 1) % token.ring_size;
-                    //let next_sock = token.node_list[next_index];
-                    //let message = Message::PassToken(token);
-                    //let signal = ServerSignal::ServerUnicast((next_sock, message));
-                    //self.server_control_sender.send(signal);
-                    //break;
-                //}
-                //index = (index + 1) % token.ring_size;
-            //}
-        //} else {
-            //let sleep_time = time::Duration::from_millis(1000);
-            //thread::sleep(sleep_time);
-            //self.propose_block();
-        //}
-    //}
+    fn start_token_ring(mut self) {
+        let _ = std::thread::spawn(move || {
+            self.last_seen = Instant::now();
+            loop {
+                // pipelining
+                if self.prepared_block.is_none() {
+                    self.prepare_block(false);
+                }
+                if let Some(token) = self.token.take() {
+                    self.hold_token(token);
+                    continue;
+                }
+                match self.handle.recv_timeout(TOKEN_POLL) {
+                    Ok(Signal::Data(token)) => self.observe_token(token),
+                    Ok(Signal::Control) => (),
+                    Err(RecvTimeoutError::Timeout) => self.check_token_loss(),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        warn!("{:?} scheduler channel of side chain {} closed", self.addr, self.sid);
+                        return;
+                    },
+                }
+            }
+        });
+    }
+
+    // propose at most one block, keep the token for a slot then pass it on
+    fn hold_token(&mut self, mut token: Token) {
+        let held = Instant::now();
+        // a new token is announced, so that scale nodes accept its proposal
+        if token.hop == 0 && token.signature.is_empty() {
+            // a regenerated token keeps the slot it was minted for
+            if token.version == 0 {
+                token.slot = self.clock.curr_slot();
+            }
+            if !token.sign(self.sid, &self.private_key) {
+                warn!("{:?} unable to sign token of side chain {}", self.addr, self.sid);
+            }
+            self.last_token = Some(token.clone());
+            let signal = ServerSignal::ServerBroadcast(Message::PassToken(self.sid, token.clone()));
+            self.server_control_sender.send(signal);
+        }
+        PERFORMANCE_COUNTER.record_token_update(true);
+        // wait for a full block up to the packing deadline, then pack what there is
+        let deadline = Duration::from_secs_f32(self.slot_time * self.pack_deadline);
+        while self.prepared_block.is_none() && held.elapsed() < deadline {
+            if self.prepare_block(false).is_none() {
+                self.wait_for_block(deadline.checked_sub(held.elapsed()).unwrap_or_default());
+            }
+        }
+        if self.prepared_block.is_none() {
+            self.prepare_block(true);
+        }
+        if self.prepared_block.is_some() && self.propose_block(token.block_id) {
+            token.block_id += 1;
+        }
+        let slot = Duration::from_secs_f32(self.slot_time);
+        let elapsed = held.elapsed();
+        if elapsed < slot {
+            thread::sleep(slot - elapsed);
+        }
+        PERFORMANCE_COUNTER.record_token_update(false);
+        self.pass_token(token);
+    }
+
+    // broadcast, so that every side node sees the token moving
+    fn pass_token(&mut self, mut token: Token) {
+        token.holder = (token.holder + 1) % token.ring_size;
+        token.hop += 1;
+        token.slot = self.clock.curr_slot();
+        if !token.sign(self.sid, &self.private_key) {
+            warn!("{:?} unable to sign token of side chain {}", self.addr, self.sid);
+        }
+        self.last_token = Some(token.clone());
+        self.last_seen = Instant::now();
+        let next_sock = token.node_list[token.holder];
+        info!("{:?} passing token of side chain {} to {:?}", self.addr, self.sid, next_sock);
+        // broadcast even to ourselves, scale nodes check proposals against it
+        let message = Message::PassToken(self.sid, token.clone());
+        let signal = ServerSignal::ServerBroadcast(message);
+        self.server_control_sender.send(signal);
+        if next_sock == self.addr {
+            self.token = Some(token);
+        }
+    }
+
+    fn observe_token(&mut self, token: Token) {
+        if token.node_list != self.sidenodes || token.holder >= token.ring_size {
+            warn!("{:?} drop token of another ring {:?}", self.addr, token.node_list);
+            return;
+        }
+        if !token.verify(self.sid, &self.accounts) {
+            warn!("{:?} drop token of side chain {} not signed by {:?}", self.addr, self.sid, token.node_list[token.passer()]);
+            return;
+        }
+        if let Some(last) = &self.last_token {
+            if !token.is_newer_than(last) {
+                return;
+            }
+            if token.is_premature(last, self.token_timeout) {
+                warn!("{:?} drop token of side chain {} created by {:?}, ring still passing at slot {}", 
+                    self.addr, self.sid, token.node_list[token.holder], last.slot);
+                return;
+            }
+        }
+        self.last_token = Some(token.clone());
+        self.last_seen = Instant::now();
+        if token.node_list[token.holder] == self.addr {
+            self.token = Some(token);
+        }
+    }
+
+    // successors of a silent holder regenerate the token in ring order, each
+    // only in the slots where it is the minter, so that the first live one
+    // takes over and the others accept its token
+    fn check_token_loss(&mut self) {
+        let ring_size = self.sidenodes.len();
+        let side_id = self.get_side_id() as usize;
+        let (version, holder, block_id) = match &self.last_token {
+            Some(token) => (token.version, token.holder, token.block_id),
+            None => (0, ring_size - 1, 0),
+        };
+        let rank = (side_id + ring_size - holder - 1) % ring_size;
+        let timeout = Duration::from_secs_f32(self.slot_time * (self.token_timeout + rank as f32));
+        if self.last_seen.elapsed() < timeout {
+            return;
+        }
+        // the silent holder may have proposed its block before failing
+        let token = Token {
+            version: version + 1,
+            ring_size: ring_size,
+            node_list: self.sidenodes.clone(),
+            holder: side_id,
+            hop: 0,
+            block_id: block_id + 1,
+            slot: self.clock.curr_slot(),
+            signature: vec![],
+        };
+        if token.minter() != side_id {
+            return;
+        }
+        if let Some(last) = &self.last_token {
+            if token.is_premature(last, self.token_timeout) {
+                return;
+            }
+        }
+        warn!("{:?} token of side chain {} lost at {:?}, regenerate", self.addr, self.sid, self.sidenodes[holder]);
+        PERFORMANCE_COUNTER.record_token_regeneration();
+        self.last_token = Some(token.clone());
+        self.last_seen = Instant::now();
+        self.token = Some(token);
+    }
 
     pub fn create_cmt_block(&mut self, trans: &Vec<Transaction>) -> Option<BlockHeader> {
         let mut rng = rand::thread_rng();
@@ -276,6 +527,7 @@ impl Scheduler {
             bits: 5.into(),
             nonce: rng.gen(),
             coded_merkle_roots_hashes: vec![CMTH256::default(); 8],
+            payload_len: 0,
         };
         let (block, trans_len) = CMTBlock::new(
             header.clone(), 
@@ -283,26 +535,10 @@ impl Scheduler {
             BLOCK_SIZE as usize, 
             HEADER_SIZE, 
             &self.codes_for_encoding, 
-            vec![true; self.codes_for_encoding.len()]
+            self.adversary.coding_layers(self.codes_for_encoding.len())
         );
 
         let cmt_header = block.block_header.clone();
-        let num_symbol = BLOCK_SIZE/(BASE_SYMBOL_SIZE as u64) *((1.0/RATE) as u64);
-        let mut symbols_by: HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>)> = HashMap::new();
-
-        // debug
-        //let mut symbols = 
-       
-        for scale_id in (1..self.num_nodes+1) {
-            //info!("scale_id {}", scale_id);
-            let samples_idx = get_sample_index(
-                scale_id, 
-                num_symbol, 
-                self.num_nodes); 
-            let (mut symbols, mut idx) = block.sample_vec(samples_idx);
-            // add sample, sample idx to mempool
-            symbols_by.insert(scale_id, (symbols, idx));
-        }
 
         //match decoder.run_tree_decoder(symbols.clone(), idx.clone(), cmt_block.block_header.clone()) {
             //Ok(transactions) => {
@@ -312,33 +548,67 @@ impl Scheduler {
             //_ => info!("tree decoder error"),
         //};
 
-        self.symbols_by = Some(symbols_by);
+        self.prepared_cmt = Some(block);
         self.prepared_block = Some(cmt_header);
         Some(header)
     }
 
-    pub fn prepare_block(&mut self) -> Option<BlockHeader> {
-        let tx_thresh = BLOCK_SIZE / TRANSACTION_SIZE - 1;
+    // samples of every scale node, seeded by the header and the scale node
+    // key. Scale nodes whose key is not known yet get none, a guessed key
+    // would only give them samples they do not expect
+    fn sample_cmt_block(&self, block: &CMTBlock, header: &BlockHeader, block_id: u64) -> HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>)> {
+        let num_symbol = BLOCK_SIZE/(BASE_SYMBOL_SIZE as u64) *((1.0/RATE) as u64);
+        let mut symbols_by: HashMap<u64, (Vec<Vec<Symbol>>, Vec<Vec<u64>>)> = HashMap::new();
+        let scale_set = self.scale_set.lock().unwrap().clone();
+        let header_hash = header_hash(header);
+        for scale_id in (1..scale_set.num_scale+1) {
+            //info!("scale_id {}", scale_id);
+            let pub_key = match scale_set.pub_key(scale_id) {
+                Some(pub_key) => pub_key,
+                None => {
+                    warn!("{:?} no samples of block {} for scale id {}, key not known yet", self.addr, block_id, scale_id);
+                    continue;
+                }
+            };
+            let seed = sample_seed(self.sid, block_id, &header_hash.0, &pub_key);
+            let samples_idx = get_sample_index(
+                &seed,
+                scale_id, 
+                num_symbol, 
+                scale_set.num_scale,
+                self.redundancy); 
+            let (symbols, idx) = block.sample_vec(samples_idx);
+            // add sample, sample idx to mempool
+            symbols_by.insert(scale_id, (symbols, idx));
+        }
+        symbols_by
+    }
+
+    // packs a block once the mempool fills one, or with whatever is pending
+    // if partial
+    pub fn prepare_block(&mut self, partial: bool) -> Option<BlockHeader> {
         // generate a coded block
         let mut mempool = self.mempool.lock().unwrap();
-        let num_tx = mempool.get_num_transaction();
-        if num_tx <= tx_thresh  { // transation size
-            info!("{:?} Skip: {} less than {:?}", self.addr, num_tx, tx_thresh); 
+        mempool.expire_proposals();
+        let pending = mempool.transaction_size_in_bytes();
+        if pending == 0 || (!partial && pending < mempool.get_block_size()) {
             return None;
         }
         let trans = mempool.prepare_transaction_block();
         drop(mempool);
+        if partial {
+            info!("{:?} pack {} transactions into a partial block", self.addr, trans.len());
+        }
         let new_block_id = self.my_next_slot();
         PERFORMANCE_COUNTER.record_propose_block_update(new_block_id);
         let header = self.create_cmt_block(&trans);
+        self.prepared_trans = trans;
         PERFORMANCE_COUNTER.record_propose_block_stop();
         header
     }
 
-    pub fn propose_block(&mut self) -> bool {
+    pub fn propose_block(&mut self, new_block_id: u64) -> bool {
         // construct message and broadcast 
-        let (curr_slot, elapsed)= get_curr_slot(self.start_sec, self.start_millis, self.slot_time);
-        let new_block_id =  curr_slot + 1; // a hack, to make sure curr_slot > 0, otherwise block rejected
         //info!("************start propose block with {}", new_block_id);
         PERFORMANCE_COUNTER.record_block_update(new_block_id);
         PERFORMANCE_COUNTER.record_propose_block_id(new_block_id as usize);
@@ -347,44 +617,130 @@ impl Scheduler {
             Some(header) => header.clone(),
             None => panic!("propose block without block ready"),
         };
-        let mut mempool = self.mempool.lock().unwrap();
-        let symbols = match self.symbols_by.take() {
-            Some(s) => s,
-            None => panic!("unable to take symbols in scheduler"),
+        let symbols = match self.prepared_cmt.take() {
+            Some(block) => self.sample_cmt_block(&block, &header, new_block_id),
+            None => panic!("unable to take cmt block in scheduler"),
         };
+        let mut mempool = self.mempool.lock().unwrap();
         mempool.insert_symbols(new_block_id, &header, symbols);
+        mempool.track_proposal(new_block_id, &header, std::mem::take(&mut self.prepared_trans));
         drop(mempool);
 
         self.prepared_block = None;
-        self.symbols_by = None;
-
-        let header_bytes = serialize(&header);
-        let header_message: Vec<u8> = header_bytes.clone().into();
-        let hash_str = utils::hash_header_hex(&header_message);
-        let message =  Message::ProposeBlock(
-            self.addr, 
-            new_block_id as u64, 
-            header_message); 
-        let signal = ServerSignal::ServerBroadcast(message);
+
+        let mut proposal = Proposal {
+            sid: self.sid,
+            block_id: new_block_id as u64,
+            proposer: self.addr,
+            proposer_account: self.address,
+            header: header,
+            signature: vec![],
+        };
+        if !proposal.sign(&self.private_key) {
+            warn!("{:?} unable to sign proposal {}", self.addr, new_block_id);
+            self.mempool.lock().unwrap().fail_proposal(new_block_id);
+            return false;
+        }
+        // an equivocating proposer signs a second header for the same slot
+        let conflicting = if self.adversary.equivocate {
+            let mut conflicting = proposal.clone();
+            conflicting.header.nonce = conflicting.header.nonce.wrapping_add(1);
+            conflicting.sign(&self.private_key);
+            Some(conflicting)
+        } else {
+            None
+        };
 
         // last check before sending out the block
         let side_id = self.get_side_id();
-        let (curr_slot, _) = get_curr_slot(self.start_sec, self.start_millis, self.slot_time); 
+        let curr_slot = self.clock.curr_slot(); 
         let curr_id = curr_slot % self.sidenodes.len() as u64;
-        if curr_id != side_id {
+        if self.mode == SchedulingMode::Slot && curr_id != side_id {
             info!("{:?} preempt take too long to construct block", self.addr);
+            self.mempool.lock().unwrap().fail_proposal(new_block_id);
             return false;
         }
         // send the block
-        self.server_control_sender.send(signal);
+        self.send_proposal(&proposal, conflicting.as_ref());
+        self.last_proposal = Some((proposal, conflicting, Instant::now()));
         //PERFORMANCE_COUNTER.record_propose_block_stop();
         //let (curr_slot, elapsed) = get_curr_slot(self.start_sec, self.start_millis, self.slot_time);
         //info!("sent Propose_block {:?}", elapsed);
         true 
     }
 
+    // the proposal sent in a slot the clock is still in, sent again every
+    // slot time so that scale nodes that missed it or timed out can vote
+    fn repropose(&mut self) {
+        let slot_time = Duration::from_secs_f32(self.slot_time);
+        let (proposal, conflicting) = match &self.last_proposal {
+            Some((proposal, conflicting, sent)) if sent.elapsed() >= slot_time => (proposal.clone(), conflicting.clone()),
+            _ => return,
+        };
+        info!("{:?} block {} not committed yet, propose it again", self.addr, proposal.block_id);
+        self.send_proposal(&proposal, conflicting.as_ref());
+        self.last_proposal = Some((proposal, conflicting, Instant::now()));
+    }
+
+    // with a conflicting header the scale nodes are split in two halves that
+    // each only see one of the headers, so that both may collect votes
+    fn send_proposal(&self, proposal: &Proposal, conflicting: Option<&Proposal>) {
+        let conflicting = match conflicting {
+            Some(conflicting) => conflicting,
+            None => {
+                let signal = ServerSignal::ServerBroadcast(Message::ProposeBlock(proposal.clone()));
+                self.server_control_sender.send(signal);
+                return;
+            }
+        };
+        info!("{:?} equivocate on block {}", self.addr, proposal.block_id);
+        let scale_addrs: Vec<SocketAddr> = self.scale_set.lock().unwrap().
+            nodes.
+            values().
+            filter_map(|node| parse_registered_addr(&node.ip_addr)).
+            collect();
+        let half = (scale_addrs.len() + 1) / 2;
+        for (i, addr) in scale_addrs.into_iter().enumerate() {
+            let sent = if i < half { proposal } else { conflicting };
+            let signal = ServerSignal::ServerUnicast((addr, Message::ProposeBlock(sent.clone())));
+            self.server_control_sender.send(signal);
+        }
+    }
+
+    // whether pack_deadline of the own slot has passed
+    fn past_pack_deadline(&mut self, slot: u64) -> bool {
+        self.until_pack_deadline(slot) == Duration::from_millis(0)
+    }
+
+    // time left until pack_deadline of the own slot, counted from when the
+    // slot was first seen so that chain clocks work alike
+    fn until_pack_deadline(&mut self, slot: u64) -> Duration {
+        let entered = match self.slot_entered {
+            Some((s, entered)) if s == slot => entered,
+            _ => {
+                let now = Instant::now();
+                self.slot_entered = Some((slot, now));
+                now
+            },
+        };
+        Duration::from_secs_f32(self.slot_time * self.pack_deadline).
+            checked_sub(entered.elapsed()).
+            unwrap_or_default()
+    }
+
+    // sleeps up to timeout, returning early when the mempool signals a full
+    // block. Tokens arriving meanwhile are still observed
+    fn wait_for_block(&mut self, timeout: Duration) {
+        match self.handle.recv_timeout(timeout) {
+            Ok(Signal::Data(token)) => self.observe_token(token),
+            Ok(Signal::Control) => (),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(timeout),
+        }
+    }
+
     pub fn my_next_slot(&self) -> u64 {
-        let (curr_slot, elapsed) = get_curr_slot(self.start_sec, self.start_millis, self.slot_time);
+        let curr_slot = self.clock.curr_slot();
         let round = self.sidenodes.len() as u64;
         let curr_round = curr_slot / round;
         let side_id = self.get_side_id();
@@ -405,18 +761,82 @@ pub fn get_curr_slot(start_sec: u64, start_millis: u64, slot_time: f32) -> (u64,
 }
 
 
-// scale id starts at 1
-pub fn get_sample_index(scale_id: u64, num_trans: u64, num_node: u64) -> Vec<u32> {
-    let num_sample = ((num_trans as f32) / (num_node as f32)).ceil() as u64;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotError {
+    NoSlot,               // block id 0 belongs to no slot, or the clock has none yet
+    WrongProposer(u64),   // side id owning the slot
+    Early(u64),           // millis before the slot opens
+    Late(u64),            // millis after the slot closes
+}
+
+// a block id is proposed in slot block_id-1 by side node slot % num_side,
+// on time as told by the slot clock
+pub fn check_slot(
+    clock: &dyn SlotClock,
+    side_id: u64,
+    num_side: u64,
+    block_id: u64,
+    max_skew_millis: u64,
+) -> Result<(), SlotError> {
+    if block_id == 0 || num_side == 0 {
+        return Err(SlotError::NoSlot);
+    }
+    let slot = block_id - 1;
+    let owner = slot % num_side;
+    if owner != side_id {
+        return Err(SlotError::WrongProposer(owner));
+    }
+    clock.check_time(slot, max_skew_millis)
+}
+
+// keccak(sid || block_id || header hash || bls public key of the scale node).
+// Samples are only fixed once the header commits to the coded symbols, so the
+// proposer cannot code a block around the symbols nobody checks
+pub fn sample_seed(sid: u64, block_id: u64, header_hash: &[u8; 32], pub_key: &(U256, U256, U256, U256)) -> [u8; 32] {
+    let mut message = Vec::with_capacity(176);
+    message.extend_from_slice(&sid.to_be_bytes());
+    message.extend_from_slice(&block_id.to_be_bytes());
+    message.extend_from_slice(header_hash);
+    for word in &[pub_key.0, pub_key.1, pub_key.2, pub_key.3] {
+        let mut bytes = [0u8; 32];
+        word.to_big_endian(&mut bytes);
+        message.extend_from_slice(&bytes);
+    }
+    let mut seed = [0u8; 32];
+    utils::hash_header(&message, &mut seed);
+    seed
+}
+
+// base symbols sampled by a scale node (id starts at 1), drawn from
+// keccak(seed || scale_id || counter) so that proposers and scale nodes derive
+// the same set. The seed comes from sample_seed, or a vrf output once available.
+// Each node takes redundancy/num_node of the symbols independently of the
+// others, so the committee can change without reshuffling everyone.
+pub fn get_sample_index(seed: &[u8; 32], scale_id: u64, num_symbol: u64, num_node: u64, redundancy: f32) -> Vec<u32> {
+    if num_symbol == 0 || num_node == 0 {
+        return vec![];
+    }
+    let num_sample = (((num_symbol as f32) * redundancy / (num_node as f32)).ceil() as u64).min(num_symbol);
+    let mut sampled = HashSet::new();
     let mut sample_idx = vec![];
-    let start = (scale_id-1)*num_sample;
-    let stop = if scale_id*num_sample > num_trans {
-        num_trans
-    } else {
-        scale_id*num_sample
-    };
-    for i in start..stop {
-        sample_idx.push(i as u32);
+    let mut counter: u64 = 0;
+    while (sample_idx.len() as u64) < num_sample {
+        let mut message = Vec::with_capacity(48);
+        message.extend_from_slice(seed);
+        message.extend_from_slice(&scale_id.to_be_bytes());
+        message.extend_from_slice(&counter.to_be_bytes());
+        let mut digest = [0u8; 32];
+        utils::hash_header(&message, &mut digest);
+        for chunk in digest.chunks(8) {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(chunk);
+            let i = u64::from_be_bytes(bytes) % num_symbol;
+            if (sample_idx.len() as u64) < num_sample && sampled.insert(i) {
+                sample_idx.push(i as u32);
+            }
+        }
+        counter += 1;
     }
+    sample_idx.sort();
     sample_idx
 }
@@ -0,0 +1,88 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use super::cmtda::H256 as CMTH256;
+
+// where a transaction is on its way to the trusted chain, as far as this
+// node knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxStatus {
+    Forwarded(SocketAddr), // sent to the side node of the next slot, until a decoded block has it
+    Pending,               // in the mempool
+    Proposed(u64),         // in an own proposal of the block id, or one a reorg reverted
+    Committed(u64),        // the trusted chain reached the block id
+    Retrievable(u64),      // committed, and the block is decoded from scale nodes
+}
+
+impl TxStatus {
+    // the mempool no longer holds the transaction, the status is only kept
+    // for queries
+    pub fn is_settled(&self) -> bool {
+        match self {
+            TxStatus::Forwarded(_) | TxStatus::Committed(_) | TxStatus::Retrievable(_) => true,
+            _ => false,
+        }
+    }
+}
+
+// status by tx hash. Settled ones are forgotten oldest first beyond capacity
+// or max_age
+pub struct StatusBoard {
+    status_by: HashMap<CMTH256, (TxStatus, Instant)>,
+    settled: VecDeque<(CMTH256, Instant)>,
+    capacity: usize,
+    max_age: Duration,
+}
+
+impl StatusBoard {
+    pub fn new(capacity: usize, max_age: Duration) -> StatusBoard {
+        StatusBoard {
+            status_by: HashMap::new(),
+            settled: VecDeque::new(),
+            capacity: capacity,
+            max_age: max_age,
+        }
+    }
+
+    pub fn set(&mut self, hash: &CMTH256, status: TxStatus) {
+        let now = Instant::now();
+        self.status_by.insert(hash.clone(), (status, now));
+        if status.is_settled() {
+            self.settled.push_back((hash.clone(), now));
+        }
+        while let Some((old, since)) = self.settled.front().cloned() {
+            if self.settled.len() <= self.capacity && now.duration_since(since) <= self.max_age {
+                break;
+            }
+            self.settled.pop_front();
+            // unless its status changed since
+            if self.status_by.get(&old).map_or(false, |(_, set)| *set == since) {
+                self.status_by.remove(&old);
+            }
+        }
+    }
+
+    pub fn get(&self, hash: &CMTH256) -> Option<TxStatus> {
+        self.status_by.get(hash).map(|(status, _)| *status)
+    }
+
+    pub fn remove(&mut self, hash: &CMTH256) {
+        self.status_by.remove(hash);
+    }
+
+    // transactions of blocks a main chain reorg took back are only proposed
+    // until their block ids commit again
+    pub fn revert(&mut self, block_ids: &[u64]) {
+        let now = Instant::now();
+        for (status, since) in self.status_by.values_mut() {
+            match *status {
+                TxStatus::Committed(id) | TxStatus::Retrievable(id) if block_ids.contains(&id) => {
+                    // out of the settled queue
+                    *status = TxStatus::Proposed(id);
+                    *since = now;
+                },
+                _ => (),
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam::channel::{Sender, Receiver, RecvTimeoutError};
+use web3::types::{Address, U256};
+use log::{info, warn};
+
+use super::server;
+use super::message::ConnectResult;
+use super::contract::interface::{Handle, ScaleNodeInfo, ContractEvent, Retry, request};
+use super::contract::interface::Message as ContractMessage;
+use super::contract::interface::Response as ContractResponse;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// registered ip is either "ip:port" or a bare ip, which is not dialable
+pub fn parse_registered_addr(ip_addr: &str) -> Option<SocketAddr> {
+    ip_addr.trim().parse::<SocketAddr>().ok()
+}
+
+// scale id of the account, 0 if the account is not a registered scale node
+pub fn scale_id_of(registry: &[ScaleNodeInfo], address: &Address) -> u64 {
+    match registry.iter().find(|node| node.address == *address) {
+        Some(node) => node.index as u64,
+        None => 0,
+    }
+}
+
+// scale set as last read from the registry, shared with the schedulers
+// sampling for it and the performer counting its votes
+#[derive(Debug, Clone, Default)]
+pub struct ScaleSet {
+    pub scale_id: u64, // own, 0 if not a scale node
+    pub num_scale: u64,
+    pub nodes: BTreeMap<u64, ScaleNodeInfo>, // registered, by scale id
+}
+
+impl ScaleSet {
+    pub fn new(scale_id: u64, num_scale: u64, registry: Vec<ScaleNodeInfo>) -> ScaleSet {
+        ScaleSet {
+            scale_id: scale_id,
+            num_scale: num_scale,
+            nodes: registry.into_iter().map(|node| (node.index as u64, node)).collect(),
+        }
+    }
+
+    // bls public key votes of the scale id are checked against
+    pub fn pub_key(&self, scale_id: u64) -> Option<(U256, U256, U256, U256)> {
+        self.nodes.get(&scale_id).map(|node| node.pub_key)
+    }
+}
+
+// keeps the node connected to every scale node in the contract registry,
+// and the scale set up to date with it
+pub struct Discovery {
+    contract_handler: Sender<Handle>,
+    server_handle: server::Handle,
+    local_addr: SocketAddr,
+    account: Address,
+    interval: Duration,
+    registry_events: Receiver<ContractEvent>, // registry changes seen by the managers
+    scale_set: Arc<Mutex<ScaleSet>>,
+    fixed: (Option<u64>, Option<u64>), // scale id and number of scale nodes given by hand
+    known: HashMap<Address, SocketAddr>,
+    connected: HashSet<SocketAddr>,
+    refreshed: bool,
+}
+
+impl Discovery {
+    pub fn new(
+        contract_handler: Sender<Handle>,
+        server_handle: server::Handle,
+        local_addr: SocketAddr,
+        account: Address,
+        interval: Duration,
+        registry_events: Receiver<ContractEvent>,
+        scale_set: Arc<Mutex<ScaleSet>>,
+        fixed: (Option<u64>, Option<u64>),
+        static_peers: &[SocketAddr],
+    ) -> Discovery {
+        Discovery {
+            contract_handler,
+            server_handle,
+            local_addr,
+            account,
+            interval,
+            registry_events,
+            scale_set,
+            fixed,
+            known: HashMap::new(),
+            connected: static_peers.iter().cloned().collect(),
+            refreshed: false,
+        }
+    }
+
+    pub fn start(mut self) {
+        let _ = thread::Builder::new()
+            .name("discovery".to_string())
+            .spawn(move || {
+                // refresh every interval, and right away on a registry change
+                loop {
+                    self.refresh();
+                    match self.registry_events.recv_timeout(self.interval) {
+                        Ok(ContractEvent::SideNodeAdded { sid, ip_addr, .. }) => {
+                            match parse_registered_addr(&ip_addr) {
+                                Some(addr) => self.dial(addr),
+                                None => warn!("side node of side chain {} registered without port: {}", sid, ip_addr),
+                            }
+                        },
+                        Ok(event) => info!("registry changed: {:?}", event),
+                        Err(RecvTimeoutError::Timeout) => (),
+                        // no manager runs, refresh by the interval only
+                        Err(RecvTimeoutError::Disconnected) => thread::sleep(self.interval),
+                    }
+                }
+            });
+    }
+
+    // a failed read waits for the next refresh
+    fn get_scale_nodes(&self) -> Option<Vec<ScaleNodeInfo>> {
+        match request(&self.contract_handler, ContractMessage::GetScaleNodes, Retry::default().timeout) {
+            Ok(ContractResponse::ScaleNodesList(nodes)) => Some(nodes),
+            Ok(_) => {
+                warn!("answer to GetScaleNodes: invalid response type");
+                None
+            },
+            Err(e) => {
+                warn!("unable to get scale nodes: {}", e);
+                None
+            },
+        }
+    }
+
+    pub fn refresh(&mut self) {
+        let registry = match self.get_scale_nodes() {
+            Some(nodes) => nodes,
+            None => return,
+        };
+        self.update_scale_set(&registry);
+
+        let mut current = HashMap::new();
+        for node in registry.iter() {
+            match parse_registered_addr(&node.ip_addr) {
+                Some(addr) => {
+                    current.insert(node.address, addr);
+                },
+                None => warn!("scale node {} registered without port: {}", node.index, node.ip_addr),
+            }
+        }
+
+        // dropped connections are dialed again. Static peers are dialed at
+        // startup, so only the first refresh takes them as connected
+        match self.server_handle.outgoing_peers() {
+            Some(peers) if self.refreshed => self.connected = peers.into_iter().collect(),
+            Some(peers) => self.connected.extend(peers),
+            None => warn!("p2p server did not list its peers"),
+        }
+        self.refreshed = true;
+
+        for (address, addr) in self.known.iter() {
+            if current.get(address) != Some(addr) {
+                info!("scale node {:?} at {} left the registry, disconnect", address, addr);
+                self.server_handle.disconnect(*addr);
+                self.connected.remove(addr);
+            }
+        }
+
+        for (_, addr) in current.iter() {
+            self.dial(*addr);
+        }
+        self.known = current;
+    }
+
+    fn dial(&mut self, addr: SocketAddr) {
+        if addr == self.local_addr || self.connected.contains(&addr) {
+            return;
+        }
+        match self.server_handle.connect(addr) {
+            Ok(rx) => {
+                match rx.recv_timeout(CONNECT_TIMEOUT) {
+                    Ok(ConnectResult::Success) => {
+                        info!("{:?} discovered and connected to {:?}", self.local_addr, addr);
+                        self.connected.insert(addr);
+                    },
+                    _ => info!("{:?} unable to connect {:?}, retry next refresh", self.local_addr, addr),
+                }
+            },
+            Err(e) => warn!("Error connecting to peer {}: {}", addr, e),
+        }
+    }
+
+    // a new registry size or own registration takes effect on the next
+    // proposal and vote, roles taken at start stay
+    fn update_scale_set(&self, registry: &[ScaleNodeInfo]) {
+        let scale_id = self.fixed.0.unwrap_or(scale_id_of(registry, &self.account));
+        let num_scale = self.fixed.1.unwrap_or(registry.len() as u64);
+        let mut scale_set = self.scale_set.lock().unwrap();
+        if scale_set.scale_id != scale_id || scale_set.num_scale != num_scale {
+            info!("scale set changed to scale id {} of {} scale nodes", scale_id, num_scale);
+            scale_set.scale_id = scale_id;
+            scale_set.num_scale = num_scale;
+        }
+        scale_set.nodes = registry.
+            iter().
+            map(|node| (node.index as u64, node.clone())).
+            collect();
+    }
+}
@@ -6,13 +6,23 @@ use chain::transaction::Transaction;
 use super::scheduler::Token;
 use std::net::{SocketAddr};
 use chain::{BlockHeader}; 
-use super::cmtda::{Block, H256, BLOCK_SIZE, HEADER_SIZE, read_codes};
+use super::cmtda::{Block, BLOCK_SIZE, HEADER_SIZE, read_codes};
 use ser::{deserialize, serialize};
 use primitives::bytes::{Bytes};
 use chain::decoder::{Symbol};
 use chain::big_array::{BigArray};
 use super::primitive::block::ContractState;
 use web3::types::Address;
+use super::MSG_BUF_SIZE;
+use super::queue::{OutgoingQueue, Priority, QueueError};
+use std::sync::Arc;
+use super::primitive::hash::H256;
+use super::contract::utils;
+use web3::types::U256;
+
+// bumped whenever the layout of Message changes, peers speaking another
+// version are rejected at decoding
+pub const PROTOCOL_VERSION: u16 = 4;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Samples {
@@ -44,21 +54,309 @@ impl Samples {
     }
 }
 
+// serde adapter for the cmt BlockHeader, which only has the bitcoin style
+// serialization
+pub mod wire_header {
+    use chain::BlockHeader;
+    use serde::{Serializer, Deserializer, Deserialize};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(header: &BlockHeader, s: S) -> Result<S::Ok, S::Error> {
+        let bytes: Vec<u8> = ser::serialize(header).into();
+        s.serialize_bytes(&bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<BlockHeader, D::Error> {
+        let bytes: Vec<u8> = Deserialize::deserialize(d)?;
+        ser::deserialize(&bytes as &[u8]).map_err(|e| D::Error::custom(format!("block header {:?}", e)))
+    }
+}
+
+// keccak of the serialized header, the same hash the contract computes
+pub fn header_hash(header: &BlockHeader) -> H256 {
+    let bytes: Vec<u8> = serialize(header).into();
+    let mut hash = [0u8; 32];
+    utils::hash_header(&bytes, &mut hash);
+    H256(hash)
+}
+
+// a side node proposes a coded block for a slot
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Proposal {
+    pub sid: u64,
+    pub block_id: u64,
+    pub proposer: SocketAddr,   // p2p address of the side node
+    pub proposer_account: Address,
+    #[serde(with = "wire_header")]
+    pub header: BlockHeader,
+    pub signature: Vec<u8>,     // by proposer_account over digest()
+}
+
+impl Proposal {
+    pub fn header_hash(&self) -> H256 {
+        header_hash(&self.header)
+    }
+
+    // keccak(sid || block_id || header hash), the slot the header is bound to
+    pub fn digest(&self) -> [u8; 32] {
+        let mut message = Vec::with_capacity(48);
+        message.extend_from_slice(&self.sid.to_be_bytes());
+        message.extend_from_slice(&self.block_id.to_be_bytes());
+        message.extend_from_slice(&self.header_hash().0);
+        let mut digest = [0u8; 32];
+        utils::hash_header(&message, &mut digest);
+        digest
+    }
+
+    pub fn sign(&mut self, private_key: &[u8]) -> bool {
+        match utils::sign_digest(&self.digest(), private_key) {
+            Some(signature) => {
+                self.signature = signature;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        utils::recover_address(&self.digest(), &self.signature) == Some(self.proposer_account)
+    }
+
+    // the hex header is what gets signed and submitted to the contract
+    pub fn header_hex(&self) -> String {
+        let bytes: Vec<u8> = serialize(&self.header).into();
+        hex::encode(&bytes)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlsSignature {
+    pub x: U256,
+    pub y: U256,
+}
+
+impl BlsSignature {
+    // the bls binaries speak decimal strings
+    pub fn from_dec_str(x: &str, y: &str) -> Option<BlsSignature> {
+        match (U256::from_dec_str(x), U256::from_dec_str(y)) {
+            (Ok(x), Ok(y)) => Some(BlsSignature { x, y }),
+            _ => None,
+        }
+    }
+
+    pub fn to_dec_str(&self) -> (String, String) {
+        (self.x.to_string(), self.y.to_string())
+    }
+}
+
+// a scale node attests it has stored its samples of the header
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Vote {
+    pub sid: u64,
+    pub block_id: u64,
+    pub header_hash: H256,
+    pub signer: u64, // scale id
+    pub signature: BlsSignature,
+}
+
+// a scale node did not receive enough samples before the vote deadline,
+// received == 0 means the proposer withheld its samples entirely
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AvailabilityReport {
+    pub sid: u64,
+    pub block_id: u64,
+    pub header_hash: H256,
+    pub signer: u64, // scale id
+    pub received: u64, // base symbols
+    pub expected: u64,
+    pub signature: BlsSignature, // by signer over message()
+}
+
+impl AvailabilityReport {
+    // every field but the signature, tagged apart from headers signed in votes
+    pub fn message(&self) -> Vec<u8> {
+        let mut message = b"availability".to_vec();
+        message.extend_from_slice(&self.sid.to_be_bytes());
+        message.extend_from_slice(&self.block_id.to_be_bytes());
+        message.extend_from_slice(&self.header_hash.0);
+        message.extend_from_slice(&self.signer.to_be_bytes());
+        message.extend_from_slice(&self.received.to_be_bytes());
+        message.extend_from_slice(&self.expected.to_be_bytes());
+        message
+    }
+
+    // keccak of message(), what the bls signature is checked against
+    pub fn digest(&self) -> [u8; 32] {
+        let mut digest = [0u8; 32];
+        utils::hash_header(&self.message(), &mut digest);
+        digest
+    }
+}
+
+// two conflicting statements for the same slot, each checkable on its own
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Evidence {
+    // one side node signed two headers for the same block id
+    Proposal(Proposal, Proposal),
+    // one scale node voted for two headers of the same block id, the bls
+    // signatures are checked against the registered key of the signer
+    Vote(Vote, Vote),
+}
+
+impl Evidence {
+    pub fn slot(&self) -> (u64, u64) {
+        match self {
+            Evidence::Proposal(a, _) => (a.sid, a.block_id),
+            Evidence::Vote(a, _) => (a.sid, a.block_id),
+        }
+    }
+
+    // the statements conflict and, for proposals, are signed by the same key.
+    // Whether the keys are the registered ones is up to the receiver
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Evidence::Proposal(a, b) => {
+                a.sid == b.sid && 
+                a.block_id == b.block_id && 
+                a.proposer_account == b.proposer_account &&
+                a.header_hash() != b.header_hash() &&
+                a.verify() && 
+                b.verify()
+            },
+            Evidence::Vote(a, b) => {
+                a.sid == b.sid && 
+                a.block_id == b.block_id && 
+                a.signer == b.signer &&
+                a.header_hash != b.header_hash
+            },
+        }
+    }
+}
+
 // prototype only, message can be made secured with crypto
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
     Ping(String),
     Pong(String),
     SyncBlock(EthBlkTransaction),
-    SendTransaction(Vec<u8>), 
-    PassToken(Token),
-    //ip(pubkey) BlockHeader block_id //sender is client
-    ProposeBlock(SocketAddr, u64, Vec<u8>), 
-    ScaleReqChunks(SocketAddr, u64, u64), //(id, scale_id), // sender is scalenode
-    ScaleReqChunksReply(SocketAddr, u64, Samples),
-    MySign(String, u64, u64, String, String, u64),
-    ScaleGetAllChunks(ContractState), // blockheader
-    ScaleGetAllChunksReply((Option<Samples>, u64)),
+    SendTransaction(u64, Vec<u8>), // (sid, transaction)
+    PassToken(u64, Token),
+    ProposeBlock(Proposal), //sender is client
+    ScaleReqChunks(u64, SocketAddr, u64, u64), //(sid, proposer, id, scale_id), // sender is scalenode
+    ScaleReqChunksReply(u64, SocketAddr, u64, Samples),
+    Vote(Vote),
+    ScaleGetAllChunks(u64, ContractState), // (sid, state)
+    ScaleGetAllChunksReply((u64, Option<Samples>, u64)), // (sid, samples, id)
+    Evidence(Evidence),
+    Availability(AvailabilityReport),
+}
+
+impl Message {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::Ping(_) => "Ping",
+            Message::Pong(_) => "Pong",
+            Message::SyncBlock(_) => "SyncBlock",
+            Message::SendTransaction(_, _) => "SendTransaction",
+            Message::PassToken(_, _) => "PassToken",
+            Message::ProposeBlock(_) => "ProposeBlock",
+            Message::ScaleReqChunks(_, _, _, _) => "ScaleReqChunks",
+            Message::ScaleReqChunksReply(_, _, _, _) => "ScaleReqChunksReply",
+            Message::Vote(_) => "Vote",
+            Message::ScaleGetAllChunks(_, _) => "ScaleGetAllChunks",
+            Message::ScaleGetAllChunksReply(_) => "ScaleGetAllChunksReply",
+            Message::Evidence(_) => "Evidence",
+            Message::Availability(_) => "Availability",
+        }
+    }
+}
+
+// upper bound in bytes of a serialized message, grouped by message type
+#[derive(Debug, Clone, Copy)]
+pub struct MessageLimits {
+    pub max_control: usize,     // ping, token, chunk requests and signatures
+    pub max_transaction: usize,
+    pub max_proposal: usize,    // block headers and synced blocks
+    pub max_samples: usize,     // coded symbols sent by side and scale nodes
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        MessageLimits {
+            max_control: 64 * 1024,
+            max_transaction: 64 * 1024,
+            max_proposal: MSG_BUF_SIZE,
+            // all coded symbols of a block, with room for the upper layers
+            max_samples: 8 * BLOCK_SIZE as usize,
+        }
+    }
+}
+
+impl MessageLimits {
+    pub fn max_frame(&self) -> usize {
+        *[self.max_control, self.max_transaction, 2 * self.max_proposal, self.max_samples]
+            .iter()
+            .max()
+            .unwrap()
+    }
+
+    pub fn limit_for(&self, msg: &Message) -> usize {
+        match msg {
+            Message::Ping(_) | 
+            Message::Pong(_) | 
+            Message::PassToken(_, _) |
+            Message::ScaleReqChunks(_, _, _, _) | 
+            Message::Vote(_) | 
+            Message::Availability(_) |
+            Message::ScaleGetAllChunks(_, _) => self.max_control,
+            Message::SendTransaction(_, _) => self.max_transaction,
+            Message::ProposeBlock(_) |
+            Message::SyncBlock(_) => self.max_proposal,
+            Message::Evidence(_) => 2 * self.max_proposal,
+            Message::ScaleReqChunksReply(_, _, _, _) |
+            Message::ScaleGetAllChunksReply(_) => self.max_samples,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Oversized(&'static str, usize, usize), // message type, size, limit
+    Version(u16),
+    Malformed(String),
+}
+
+// frame payload: protocol version (u16 little endian) followed by the
+// bincode message
+pub fn encode_message(msg: &Message) -> Vec<u8> {
+    let mut payload = PROTOCOL_VERSION.to_le_bytes().to_vec();
+    payload.extend(bincode::serialize(msg).unwrap());
+    payload
+}
+
+// bincode is bounded by the frame limit so that a forged length field inside
+// the payload cannot trigger a huge allocation
+pub fn decode_message(payload: &[u8], limits: &MessageLimits) -> Result<Message, DecodeError> {
+    let max_frame = limits.max_frame();
+    if payload.len() > max_frame {
+        return Err(DecodeError::Oversized("Frame", payload.len(), max_frame));
+    }
+    if payload.len() < 2 {
+        return Err(DecodeError::Malformed("missing protocol version".to_string()));
+    }
+    let version = u16::from_le_bytes([payload[0], payload[1]]);
+    if version != PROTOCOL_VERSION {
+        return Err(DecodeError::Version(version));
+    }
+    let msg: Message = match bincode::config().limit(max_frame as u64).deserialize(&payload[2..]) {
+        Ok(m) => m,
+        Err(e) => return Err(DecodeError::Malformed(e.to_string())),
+    };
+    let limit = limits.limit_for(&msg);
+    if payload.len() > limit {
+        return Err(DecodeError::Oversized(msg.name(), payload.len(), limit));
+    }
+    Ok(msg)
 }
 
 
@@ -76,20 +374,31 @@ pub enum ServerSignal {
     ServerStart,
     ServerBroadcast(Message),
     ServerUnicast((SocketAddr, Message)),
+    ServerPenalize((SocketAddr, u32)), // misbehaving peer, penalty score
+    ServerDisconnectPeer(SocketAddr), // drop connections dialed to the peer
+    ServerPeers(mpsc::Sender<Vec<SocketAddr>>), // answered with the peers dialed and connected
 }
 
 #[derive(Clone)]
 pub struct PeerHandle {
-    pub write_queue: channel::Sender<Vec<u8>>,   
+    pub write_queue: Arc<OutgoingQueue>,
+    pub doorbell: channel::Sender<()>, // wakes up the writer of this peer
     pub addr: SocketAddr,
 }
 
 impl PeerHandle {
-    pub fn write(&self, msg: Message) {
-        let buffer = bincode::serialize(&msg).unwrap();
-        if self.write_queue.send(buffer).is_err() {
+    pub fn write(&self, msg: Message) -> Result<(), QueueError> {
+        let priority = Priority::of(&msg);
+        let buffer = encode_message(&msg);
+        if let Err(e) = self.write_queue.push(buffer, msg.name(), priority) {
+            warn!("Drop {} to peer {}, outgoing queue full", msg.name(), self.addr);
+            return Err(e);
+        }
+        if self.doorbell.send(()).is_err() {
             warn!("Failed to send write request for peer {}, channel detached", self.addr);
+            return Err(QueueError::Closed);
         }
+        Ok(())
     }
 }
 
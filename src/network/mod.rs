@@ -4,6 +4,8 @@ pub mod server;
 pub mod peer;
 pub mod message;
 pub mod performer;
+pub mod queue;
+pub mod discovery;
 
 use super::primitive;
 use super::db::{blockDb, utxoDb};
@@ -15,5 +17,6 @@ use super::mempool::scheduler;
 use super::cmtda;
 
 
-// should be greater than coded_merkle_tree/chain constants.rs BLOCK_SIZE
+// default limit of a proposal message, samples are bounded separately
+// by message::MessageLimits
 pub const MSG_BUF_SIZE: usize = 1000_000;//65535;
@@ -7,9 +7,17 @@ use std::collections::{VecDeque};
 use super::message::{ConnectResult, ConnectHandle, PeerHandle};
 use std::io::{Write, Read};
 use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
 
 use log::{warn, info};
 use super::MSG_BUF_SIZE;
+use super::queue::{OutgoingQueue, QueueConfig, TokenBucket};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::experiment::snapshot::PERFORMANCE_COUNTER;
+
+// smallest piece of payload worth waking up for when rate limited
+const MIN_WRITE_CHUNK: usize = 16 * 1024;
 
 enum DecodeState {
     Length,
@@ -22,15 +30,29 @@ pub enum ReadResult {
     EOF,
 }
 
-pub struct ReadContext {
-    reader: std::io::BufReader<mio::net::TcpStream>,
+pub struct ReadContext<R: Read = mio::net::TcpStream> {
+    reader: std::io::BufReader<R>,
     buffer: Vec<u8>,
     msg_length: usize,
     read_length: usize,
-    state: DecodeState
+    state: DecodeState,
+    max_frame_size: usize,
 }
 
-impl ReadContext {
+impl<R: Read> ReadContext<R> {
+    pub fn new(reader: R, max_frame_size: usize) -> ReadContext<R> {
+        ReadContext {
+            reader: std::io::BufReader::new(reader),
+            buffer: vec![0; std::mem::size_of::<u32>()],
+            msg_length: std::mem::size_of::<u32>(),
+            read_length: 0,
+            state: DecodeState::Length,
+            max_frame_size: max_frame_size,
+        }
+    }
+
+    // a frame violating the limits is reported as InvalidData, the caller
+    // is expected to drop the connection
     pub fn read(&mut self) -> std::io::Result<(ReadResult)> {
         let bytes_read = self
             .reader
@@ -50,6 +72,12 @@ impl ReadContext {
                         DecodeState::Length => {
                             let message_length =
                                 u32::from_be_bytes(self.buffer[0..4].try_into().unwrap());
+                            if message_length == 0 || message_length as usize > self.max_frame_size {
+                                return Err(Error::new(
+                                    ErrorKind::InvalidData,
+                                    format!("frame length {} exceeds limit {}", message_length, self.max_frame_size),
+                                ));
+                            }
                             self.state = DecodeState::Payload;
                             self.read_length = 0;
                             self.msg_length = message_length as usize;
@@ -61,6 +89,11 @@ impl ReadContext {
                         }
                         DecodeState::Payload => {
                             let new_payload: Vec<u8> = self.buffer[0..self.msg_length].to_vec();
+                            // do not keep a large buffer around after a big frame
+                            if self.buffer.len() > MSG_BUF_SIZE {
+                                self.buffer.truncate(std::mem::size_of::<u32>());
+                                self.buffer.shrink_to_fit();
+                            }
                             self.state = DecodeState::Length;
                             self.read_length = 0;
                             self.msg_length = std::mem::size_of::<u32>();
@@ -81,6 +114,7 @@ pub enum WriteResult {
     Complete,
     EOF,
     ChanClosed,
+    Throttled(Duration), // rate limited, retry after the duration
 }
 
 enum WriteState {
@@ -89,10 +123,14 @@ enum WriteState {
 }
 
 pub struct WriteContext {
+    addr: SocketAddr,
     writer: std::io::BufWriter<mio::net::TcpStream>,
-    pub queue: channel::Receiver<Vec<u8>>,
+    pub doorbell: channel::Receiver<()>,
+    queue: Arc<OutgoingQueue>,
+    bucket: Option<TokenBucket>,
     len_buffer: [u8; std::mem::size_of::<u32>()],
     msg_buffer: Vec<u8>,
+    msg_name: Option<&'static str>, // message being written, for traffic accounting
     msg_length: usize,
     written_length: usize,
     state: WriteState,
@@ -121,16 +159,26 @@ impl WriteContext {
                 WriteState::Payload => {
                     if self.written_length == self.msg_length {
                         self.writer.flush()?;
-                        let msg = match self.queue.try_recv() {
-                            Ok(msg) => msg,
-                            Err(e) => match e {
-                                mpsc::TryRecvError::Empty => return Ok(WriteResult::Complete),
-                                mpsc::TryRecvError::Disconnected => {
+                        if let Some(name) = self.msg_name.take() {
+                            PERFORMANCE_COUNTER.record_sent(&self.addr, name, self.msg_length + std::mem::size_of::<u32>());
+                        }
+                        // the doorbell only wakes us up, messages are taken
+                        // from the priority queue
+                        loop {
+                            match self.doorbell.try_recv() {
+                                Ok(()) => continue,
+                                Err(mpsc::TryRecvError::Empty) => break,
+                                Err(mpsc::TryRecvError::Disconnected) => {
                                     return Ok(WriteResult::ChanClosed);
                                 }
                             }
+                        }
+                        let (msg, name) = match self.queue.pop() {
+                            Some(msg) => msg,
+                            None => return Ok(WriteResult::Complete),
                         };
                         self.msg_buffer = msg;
+                        self.msg_name = Some(name);
                         self.msg_length = self.msg_buffer.len();
                         self.len_buffer[..4]
                             .copy_from_slice(&(self.msg_length as u32).to_be_bytes());
@@ -138,12 +186,25 @@ impl WriteContext {
                         self.state = WriteState::Length;
                         continue;
                     } else {
+                        let mut end = self.msg_length;
+                        if let Some(bucket) = self.bucket.as_mut() {
+                            let available = bucket.available();
+                            let wanted = MIN_WRITE_CHUNK.min(end - self.written_length).min(bucket.burst());
+                            if available < wanted {
+                                self.writer.flush()?;
+                                return Ok(WriteResult::Throttled(bucket.wait_time(wanted)));
+                            }
+                            end = end.min(self.written_length + available);
+                        }
                         let written = self
                             .writer
-                            .write(&self.msg_buffer[self.written_length..self.msg_length])?;
+                            .write(&self.msg_buffer[self.written_length..end])?;
                         if written == 0 {
                             return Ok(WriteResult::EOF);
                         }
+                        if let Some(bucket) = self.bucket.as_mut() {
+                            bucket.consume(written);
+                        }
                         self.written_length += written;
                         continue;
                     }
@@ -166,33 +227,38 @@ impl PeerContext {
     pub fn new(
         stream: mio::net::TcpStream,
         direction: PeerDirection,
+        max_frame_size: usize,
+        queue_config: &QueueConfig,
     ) -> std::io::Result<(PeerContext, PeerHandle)> {
         let reader_stream = stream.try_clone()?;
         let writer_stream = stream.try_clone()?;
         let addr = stream.peer_addr()?;
-        let bufreader = std::io::BufReader::new(reader_stream);
-        let read_ctx = ReadContext {
-            reader: bufreader,
-            buffer: vec![0; std::mem::size_of::<u32>()],
-            msg_length: std::mem::size_of::<u32>(),
-            read_length: 0,
-            state: DecodeState::Length,
-        };
+        let read_ctx = ReadContext::new(reader_stream, max_frame_size);
 
         let bufwriter = std::io::BufWriter::new(writer_stream);
-        let (write_sender, write_receiver) = channel::channel();
+        let (doorbell_sender, doorbell_receiver) = channel::channel();
+        let queue = Arc::new(OutgoingQueue::new(queue_config.capacity));
+        let bucket = match queue_config.rate {
+            Some(rate) => Some(TokenBucket::new(rate, queue_config.burst)),
+            None => None,
+        };
         let write_ctx = WriteContext {
+            addr: addr,
             writer: bufwriter,
-            queue: write_receiver,
+            doorbell: doorbell_receiver,
+            queue: queue.clone(),
+            bucket: bucket,
             len_buffer: [0; std::mem::size_of::<u32>()],
             msg_buffer: Vec::new(),
+            msg_name: None,
             msg_length: 0,
             written_length: 0,
             state: WriteState::Payload,
         };
 
         let handle = PeerHandle {
-            write_queue: write_sender,
+            write_queue: queue,
+            doorbell: doorbell_sender,
             addr,
         };
 
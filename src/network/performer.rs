@@ -4,15 +4,16 @@ use std::thread;
 use std::sync::{Arc, Mutex};
 
 use crate::db::blockDb::{BlockDb};
-use crate::blockchain::blockchain::{BlockChain};
-use crate::mempool::mempool::{Mempool};
-use crate::mempool::scheduler::{self, get_curr_slot};
+use crate::blockchain::sidechain::{SideChain};
+use crate::mempool::scheduler::{self, get_curr_slot, SchedulingMode, Token};
+use crate::mempool::clock::{SlotClock};
 
-use super::message::{Message, TaskRequest, PeerHandle, Samples, ServerSignal};
+use super::message::{Message, TaskRequest, PeerHandle, Samples, ServerSignal, Vote, BlsSignature, Proposal, Evidence};
+use super::message::{AvailabilityReport};
 use super::contract::contract::{Contract};
 use super::contract::interface::Message as ContractMessage;
 use super::contract::interface::Response as ContractResponse;
-use super::contract::interface::{Handle, Answer};
+use super::contract::interface::{Handle, Answer, request_with_retry, Retry};
 use super::contract::interface::Error as ContractError;
 use super::primitive::block::ContractState;
 
@@ -35,80 +36,97 @@ use mio_extras::channel::Sender as MioSender;
 use super::cmtda::{BlockHeader};
 use hex;
 use chain::constants::{BLOCK_SIZE, BASE_SYMBOL_SIZE, RATE, UNDECODABLE_RATIO};
-use std::time::{SystemTime, UNIX_EPOCH, Duration};
+use std::time::{SystemTime, UNIX_EPOCH, Duration, Instant};
 use web3::types::{Address};
 use crate::experiment::snapshot::PERFORMANCE_COUNTER;
+use crate::experiment::adversary::Adversary;
+use super::discovery::ScaleSet;
+use super::server::{MALFORMED_MESSAGE_PENALTY};
+use std::collections::HashSet;
+
+// slots of proposals and votes kept for equivocation detection
+const SLOT_HISTORY: u64 = 256;
+// a vote whose tx could not be sent is tried again after the backoff,
+// doubled each time
+const VOTE_ATTEMPTS: usize = 3;
+const VOTE_BACKOFF: Duration = Duration::from_secs(1);
 
 pub struct Performer {
     task_source: Receiver<TaskRequest>,
-    chain: Arc<Mutex<BlockChain>>, 
+    chains: HashMap<u64, SideChain>, // side chains served, by sid
     block_db: Arc<Mutex<BlockDb>>,
-    mempool: Arc<Mutex<Mempool>>,
-    scheduler_handler: Sender<scheduler::Signal>,
     contract_handler: Sender<Handle>,
     addr: SocketAddr,
-    proposal_by: HashMap<(SocketAddr, u64), Sender<Samples> >, //TODO cleanup memory
-    key_file: String,
-    scale_id: u64,
-    agg_sig: Arc<Mutex<HashMap<String, (String, String, usize)>>>,
+    proposal_by: Arc<Mutex<HashMap<(u64, SocketAddr, u64), Sender<Samples>>>>, // removed once voted or timed out
+    key: utils::BLSKeyStr, // decrypted, handed to the bls binary on stdin
+    scale_set: Arc<Mutex<ScaleSet>>, // own scale id and the number of scale nodes
+    agg_sig: Arc<Mutex<HashMap<H256, (String, String, usize)>>>, // by header hash
+    headers: Arc<Mutex<HashMap<H256, (u64, u64, String)>>>, // sid, block id, hex header
     threshold: u64,
     server_control_sender: MioSender<ServerSignal>,
-    manager_source: Sender<(u64, Option<Samples>)>,
-    num_nodes: u64,
     bin_dir: String,
-    num_side: u64,
     address: Address,
     slot_time: f32,
-    sidenodes: Vec<SocketAddr>,
-    start_sec: u64,
-    start_millis: u64,
+    max_skew_millis: u64, // tolerated clock skew of proposals
+    scheduling: SchedulingMode,
+    vote_deadline: f32, // slots to wait for samples before reporting
+    token_timeout: f32, // slots without a token pass before the ring regenerates it
+    redundancy: f32, // times each symbol is sampled on average
+    adversary: Adversary,
+    slot_proposals: HashMap<(u64, u64), Proposal>, // first proposal of (sid, block id)
+    slot_votes: HashMap<(u64, u64, u64), Vote>, // first vote of (sid, block id, signer)
+    equivocated: HashSet<(u64, u64)>, // slots never to be signed
+    early_votes: HashMap<H256, Vec<Vote>>, // verified votes ahead of their proposal, by header hash
+    tokens: HashMap<u64, Token>, // latest verified token of each side chain
 }
 
 pub fn new(
     task_source: Receiver<TaskRequest>, 
-    blockchain: Arc<Mutex<BlockChain>>,
+    chains: HashMap<u64, SideChain>,
     block_db: Arc<Mutex<BlockDb>>,
-    mempool: Arc<Mutex<Mempool>>,
-    scheduler_handler: Sender<scheduler::Signal>,
     contract_handler: Sender<Handle>,
     addr: SocketAddr,
-    key_file: String,
-    scale_id: u64,
+    key: utils::BLSKeyStr,
+    scale_set: Arc<Mutex<ScaleSet>>,
     threshold: u64,
     server_control_sender: MioSender<ServerSignal>,
-    manager_source: Sender<(u64, Option<Samples>)>,
-    num_nodes: u64,
     bin_dir: &str,
-    num_side: u64,
     address: Address,
     slot_time: f32,
-    sidenodes: Vec<SocketAddr>,
-    start_sec: u64,
-    start_millis: u64,
+    max_skew_millis: u64,
+    scheduling: SchedulingMode,
+    vote_deadline: f32,
+    token_timeout: f32,
+    redundancy: f32,
+    adversary: Adversary,
 ) -> Performer {
     Performer {
         task_source,
-        chain: blockchain,
+        chains: chains,
         block_db: block_db,
-        mempool: mempool,
         contract_handler: contract_handler,
-        scheduler_handler: scheduler_handler,
         addr: addr,
-        proposal_by: HashMap::new(),
-        key_file,
-        scale_id,
+        proposal_by: Arc::new(Mutex::new(HashMap::new())),
+        key,
+        scale_set,
         agg_sig: Arc::new(Mutex::new(HashMap::new())),
+        headers: Arc::new(Mutex::new(HashMap::new())),
         threshold,
         server_control_sender: server_control_sender,
-        manager_source: manager_source,
-        num_nodes: num_nodes,
         bin_dir: bin_dir.to_string(),
-        num_side: num_side,
         address: address,
         slot_time: slot_time,
-        sidenodes: sidenodes,
-        start_sec: start_sec,
-        start_millis: start_millis,
+        max_skew_millis: max_skew_millis,
+        scheduling: scheduling,
+        vote_deadline: vote_deadline,
+        token_timeout: token_timeout,
+        redundancy: redundancy,
+        adversary: adversary,
+        slot_proposals: HashMap::new(),
+        slot_votes: HashMap::new(),
+        equivocated: HashSet::new(),
+        early_votes: HashMap::new(),
+        tokens: HashMap::new(),
     } 
 }
 
@@ -140,75 +158,116 @@ impl Performer {
         return local_hash;
     }
 
-    pub fn decide_node(&self, proposer_addr: &SocketAddr) -> bool {
-        let (curr_slot, elapsed) = get_curr_slot(self.start_sec, self.start_millis, self.slot_time);
-        let curr_id = curr_slot % self.sidenodes.len() as u64;
-        let sender_id = match self.sidenodes.
+    fn side_chain(&self, sid: u64) -> Option<&SideChain> {
+        let side_chain = self.chains.get(&sid);
+        if side_chain.is_none() {
+            warn!("{:?} does not serve side chain {}", self.addr, sid);
+        }
+        side_chain
+    }
+
+    // the proposer has to own the slot of block_id in its side chain, sign
+    // with the account it registered, and the proposal has to arrive within
+    // the slot. With a token ring the slot is the one the token was handed
+    // over in, and the proposer has to hold it
+    pub fn decide_node(&self, sid: u64, proposer_addr: &SocketAddr, account: &Address, block_id: u64) -> bool {
+        let side_chain = match self.side_chain(sid) {
+            Some(side_chain) => side_chain,
+            None => {
+                PERFORMANCE_COUNTER.record_rejected_proposal();
+                return false;
+            }
+        };
+        let (sidenodes, clock) = (&side_chain.sidenodes, &side_chain.clock);
+        let sender_id = match sidenodes.
             iter().
             position(|x| x== proposer_addr) 
         {
             Some(i) => i as u64,
-            None => return false,
+            None => {
+                warn!("unknown side node {:?} proposes block {} of side chain {}", proposer_addr, block_id, sid);
+                PERFORMANCE_COUNTER.record_rejected_proposal();
+                return false;
+            }
         };
+        // a final block is never replaced, whatever the slot says
+        let confirmed = side_chain.chain.lock().unwrap().get_final_state();
+        if let Some(confirmed) = confirmed {
+            if block_id <= confirmed.block_id {
+                warn!("reject block {} of side chain {}, block {} is final", block_id, sid, confirmed.block_id);
+                PERFORMANCE_COUNTER.record_rejected_proposal();
+                return false;
+            }
+        }
+        if !side_chain.is_account_of(proposer_addr, account) {
+            warn!("side node {:?} proposes block {} of side chain {} with account {:?} it did not register", 
+                proposer_addr, block_id, sid, account);
+            PERFORMANCE_COUNTER.record_rejected_proposal();
+            return false;
+        }
+        if self.scheduling == SchedulingMode::TokenRing {
+            return self.check_token(sid, proposer_addr, block_id, clock.as_ref());
+        }
 
-        //info!("sender id {:?}; curr id {}", sender_id, curr_id);
-        return true;
-        //if sender_id  == curr_id {
-            //true
-        //} else {
-            //warn!("wrong node {:?} {} propose, curr_id {}", proposer_addr, sender_id, curr_id);
-            //false
-        //}
+        match scheduler::check_slot(
+            clock.as_ref(), 
+            sender_id, 
+            sidenodes.len() as u64, 
+            block_id, 
+            self.max_skew_millis) 
+        {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("reject block {} of side chain {} from side node {} {:?}: {:?}", block_id, sid, sender_id, proposer_addr, e);
+                PERFORMANCE_COUNTER.record_rejected_proposal();
+                false
+            }
+        }
     }
 
-    fn get_eth_transactions(&self, start: u64, end: u64) -> Vec<EthBlkTransaction> {
-        let (answer_tx, answer_rx) = channel::bounded(1);
-        let handle = Handle {
-            message: ContractMessage::GetAll(([0 as u8;32], start as usize, end as usize)),
-            answer_channel: Some(answer_tx),
+    // the proposal arrives while the proposer holds the latest token, in the
+    // slot of the hand-off or the next one
+    fn check_token(&self, sid: u64, proposer_addr: &SocketAddr, block_id: u64, clock: &dyn SlotClock) -> bool {
+        let token = match self.tokens.get(&sid) {
+            Some(token) => token,
+            None => {
+                warn!("reject block {} of side chain {}, no token seen", block_id, sid);
+                PERFORMANCE_COUNTER.record_rejected_proposal();
+                return false;
+            }
         };
-        self.contract_handler.send(handle);
-
-        match answer_rx.recv() {
-            Ok(answer) => {
-                match answer {
-                    Answer::Success(resp) => {
-                        match resp {
-                            ContractResponse::GetAll(requested_list) => requested_list,
-                            _ => panic!("performer contract get wrong answer"), 
-                        }
-                    },
-                    _ => panic!("fail"),
-                }
-            },
-            Err(e) => panic!("performer contract channel broke"), 
+        if token.node_list[token.holder] != *proposer_addr || token.block_id != block_id {
+            warn!("reject block {} of side chain {} from {:?}, token holder {:?} is at block {}", 
+                block_id, sid, proposer_addr, token.node_list[token.holder], token.block_id);
+            PERFORMANCE_COUNTER.record_rejected_proposal();
+            return false;
+        }
+        let on_time = clock.check_time(token.slot, self.max_skew_millis).
+            or_else(|_| clock.check_time(token.slot + 1, self.max_skew_millis));
+        match on_time {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("reject block {} of side chain {} from token holder {:?}: {:?}", block_id, sid, proposer_addr, e);
+                PERFORMANCE_COUNTER.record_rejected_proposal();
+                false
+            }
         }
     }
 
+    fn get_eth_transactions(&self, start: u64, end: u64) -> Result<Vec<EthBlkTransaction>, ContractError> {
+        let message = ContractMessage::GetAll(([0 as u8;32], start as usize, end as usize));
+        match request_with_retry(&self.contract_handler, message, &Retry::default())? {
+            ContractResponse::GetAll(requested_list) => Ok(requested_list),
+            _ => Err(ContractError::Decode("performer contract get wrong answer".to_string())),
+        }
+    }
 
-    fn get_eth_curr_state(&self) -> ContractState {
-        let (answer_tx, answer_rx) = channel::bounded(1);
-        let handle = Handle {
-            message: ContractMessage::GetCurrState(0),
-            answer_channel: Some(answer_tx),
-        };
-        self.contract_handler.send(handle);
-
-        match answer_rx.recv() {
-            Ok(answer) => {
-                match answer {
-                    Answer::Success(resp) => {
-                        match resp {
-                            ContractResponse::GetCurrState(state) => state,
-                            _ => panic!("get_all_eth_contract_state wrong answer"), 
-                        }
-                    },
-                    _ => panic!("get_all_eth_contract_state fail"),
-                }
-            },
-            Err(e) => {
-                panic!("performer to contract handler channel broke");
-            }, 
+
+    fn get_eth_curr_state(&self, sid: u64) -> Result<ContractState, ContractError> {
+        let message = ContractMessage::GetCurrState(sid as usize);
+        match request_with_retry(&self.contract_handler, message, &Retry::default())? {
+            ContractResponse::GetCurrState(state) => Ok(state),
+            _ => Err(ContractError::Decode("get_all_eth_contract_state wrong answer".to_string())),
         }
 
     }
@@ -285,10 +344,200 @@ impl Performer {
         //drop(chain);
     //}
 
+    // the message decoded but its content is invalid
+    fn penalize(&self, peer_handle: &PeerHandle, reason: &str) {
+        warn!("{:?} malformed message from {}: {}", self.addr, peer_handle.addr, reason);
+        let signal = ServerSignal::ServerPenalize((peer_handle.addr, MALFORMED_MESSAGE_PENALTY));
+        self.server_control_sender.send(signal);
+    }
+
+    // return true only for the first header of a slot, a conflicting header
+    // turns into evidence. The same header sent again, while a chain clock
+    // stays in its slot, is handled again once the previous job ended
+    fn record_proposal(&mut self, proposal: &Proposal) -> bool {
+        let slot = (proposal.sid, proposal.block_id);
+        self.slot_proposals.retain(|(_, id), _| id + SLOT_HISTORY >= slot.1);
+        if self.equivocated.contains(&slot) {
+            return false;
+        }
+        let first = match self.slot_proposals.get(&slot) {
+            Some(first) => first.clone(),
+            None => {
+                self.slot_proposals.insert(slot, proposal.clone());
+                return true;
+            }
+        };
+        if first.header_hash() != proposal.header_hash() {
+            self.equivocated.insert(slot);
+            self.report_evidence(Evidence::Proposal(first, proposal.clone()));
+            return false;
+        }
+        !self.proposal_by.lock().unwrap().contains_key(&(proposal.sid, proposal.proposer, proposal.block_id))
+    }
+
+    fn record_vote(&mut self, vote: &Vote) -> bool {
+        let key = (vote.sid, vote.block_id, vote.signer);
+        self.slot_votes.retain(|(_, id, _), _| id + SLOT_HISTORY >= key.1);
+        let first = match self.slot_votes.get(&key) {
+            Some(first) => first.clone(),
+            None => {
+                self.slot_votes.insert(key, vote.clone());
+                return true;
+            }
+        };
+        if first.header_hash != vote.header_hash {
+            self.report_evidence(Evidence::Vote(first, vote.clone()));
+        }
+        false
+    }
+
+    // evidence only counts if its statements are signed by the keys the
+    // registry holds for the side node or scale node
+    fn verify_evidence(&self, evidence: &Evidence) -> bool {
+        if !evidence.is_valid() {
+            return false;
+        }
+        match evidence {
+            Evidence::Proposal(a, b) => {
+                match self.chains.get(&a.sid) {
+                    Some(side_chain) => {
+                        side_chain.is_account_of(&a.proposer, &a.proposer_account) &&
+                        side_chain.is_account_of(&b.proposer, &b.proposer_account)
+                    },
+                    None => false,
+                }
+            },
+            Evidence::Vote(a, b) => self.verify_vote(a) && self.verify_vote(b),
+        }
+    }
+
+    // evidence of signers registered after the last refresh is dropped
+    // without a penalty
+    fn knows_signers(&self, evidence: &Evidence) -> bool {
+        match evidence {
+            Evidence::Proposal(a, b) => match self.chains.get(&a.sid) {
+                Some(side_chain) => {
+                    side_chain.accounts.contains_key(&a.proposer) &&
+                    side_chain.accounts.contains_key(&b.proposer)
+                },
+                None => false,
+            },
+            Evidence::Vote(a, b) => self.is_known_scale_id(a.signer) && self.is_known_scale_id(b.signer),
+        }
+    }
+
+    // persist and broadcast, every scale node keeps its own copy
+    fn report_evidence(&self, evidence: Evidence) {
+        let (sid, block_id) = evidence.slot();
+        warn!("{:?} equivocation in side chain {} block {}: {:?}", self.addr, sid, block_id, evidence);
+        let mut db = self.block_db.lock().unwrap();
+        let is_new = db.insert_evidence(&evidence);
+        drop(db);
+        if is_new {
+            PERFORMANCE_COUNTER.record_equivocation();
+            let signal = ServerSignal::ServerBroadcast(Message::Evidence(evidence));
+            self.server_control_sender.send(signal);
+        }
+    }
+
+    fn scale_id(&self) -> u64 {
+        self.scale_set.lock().unwrap().scale_id
+    }
+
+    fn num_nodes(&self) -> u64 {
+        self.scale_set.lock().unwrap().num_scale
+    }
+
+    // a vote signs the header, whose keccak is the header hash
+    fn verify_vote(&self, vote: &Vote) -> bool {
+        let pub_key = match self.scale_set.lock().unwrap().pub_key(vote.signer) {
+            Some(pub_key) => pub_key,
+            None => {
+                warn!("{:?} no registered key of scale id {}", self.addr, vote.signer);
+                return false;
+            }
+        };
+        let (sigx, sigy) = vote.signature.to_dec_str();
+        utils::_verify_bls(&vote.header_hash.0, &sigx, &sigy, &pub_key, &self.bin_dir)
+    }
+
+    fn verify_report(&self, report: &AvailabilityReport) -> bool {
+        let pub_key = match self.scale_set.lock().unwrap().pub_key(report.signer) {
+            Some(pub_key) => pub_key,
+            None => {
+                warn!("{:?} no registered key of scale id {}", self.addr, report.signer);
+                return false;
+            }
+        };
+        let (sigx, sigy) = report.signature.to_dec_str();
+        utils::_verify_bls(&report.digest(), &sigx, &sigy, &pub_key, &self.bin_dir)
+    }
+
+    // samples are due vote_deadline slots after the slot of the proposal
+    // opened, so a late proposal does not buy its samples more time. Without
+    // a known opening, from now on
+    fn vote_deadline_of(&self, sid: u64, block_id: u64) -> Instant {
+        let window = Duration::from_secs_f32(self.slot_time * self.vote_deadline);
+        let slot = match self.scheduling {
+            SchedulingMode::Slot => block_id.checked_sub(1),
+            SchedulingMode::TokenRing => self.tokens.
+                get(&sid).
+                filter(|token| token.block_id == block_id).
+                map(|token| token.slot),
+        };
+        let since = match (self.side_chain(sid), slot) {
+            (Some(side_chain), Some(slot)) => side_chain.clock.since_slot(slot),
+            _ => None,
+        };
+        Instant::now() + window.checked_sub(since.unwrap_or_default()).unwrap_or_default()
+    }
+
+    // votes may overtake the proposal they are for, they are aggregated
+    // once it arrives
+    fn add_vote(&mut self, vote: Vote) {
+        if !self.headers.lock().unwrap().contains_key(&vote.header_hash) {
+            let block_id = vote.block_id;
+            self.early_votes.retain(|_, votes| votes[0].block_id + SLOT_HISTORY >= block_id);
+            self.early_votes.entry(vote.header_hash).or_insert(vec![]).push(vote);
+            return;
+        }
+        let threshold = (UNDECODABLE_RATIO*(self.num_nodes() as f32)).ceil() as usize ;
+        if let Some(agg) = aggregate_vote(&self.agg_sig, &vote, &self.bin_dir, threshold) {
+            info!("2. Sufficient Signture block {}", vote.block_id);
+            let header = self.headers.lock().unwrap().remove(&vote.header_hash);
+            if let Some((sid, block_id, header_hex)) = header {
+                submit_aggregate(&self.contract_handler, header_hex, sid, block_id, agg);
+            }
+        }
+    }
+
+    // scale ids the signer bitset can hold at all
+    fn is_valid_scale_id(&self, scale_id: u64) -> bool {
+        scale_id > 0 && scale_id < 64
+    }
+
+    // registered as of the last discovery refresh, a newer scale node is
+    // not penalized for it
+    fn is_known_scale_id(&self, scale_id: u64) -> bool {
+        self.scale_set.lock().unwrap().nodes.contains_key(&scale_id)
+    }
+
     fn perform(&mut self) {
         loop {
-            let task = self.task_source.recv().unwrap();
-            let peer_handle = task.peer.unwrap();
+            let task = match self.task_source.recv() {
+                Ok(task) => task,
+                Err(e) => {
+                    warn!("performer task channel closed {:?}", e);
+                    return;
+                }
+            };
+            let peer_handle = match task.peer {
+                Some(peer) => peer,
+                None => {
+                    warn!("drop {} task without peer", task.msg.name());
+                    continue;
+                }
+            };
             match task.msg {
                 Message::Ping(info_msg) => {
                     info!("{}", info_msg);
@@ -304,194 +553,307 @@ impl Performer {
                     info!("receive sync block");
                     //self.update_block(main_node_block);
                 },
-                Message::SendTransaction(transaction_ser) => {
-                    let transaction: Transaction = deserialize(&transaction_ser as &[u8]).unwrap();
-                    let mut mempool = self.mempool.lock().expect("perform locl mempool");
-                    mempool.insert(transaction);
+                Message::SendTransaction(sid, transaction_ser) => {
+                    let transaction: Transaction = match deserialize(&transaction_ser as &[u8]) {
+                        Ok(t) => t,
+                        Err(e) => {
+                            self.penalize(&peer_handle, &format!("transaction {:?}", e));
+                            continue;
+                        }
+                    };
+                    let mempool = match self.side_chain(sid) {
+                        Some(side_chain) => side_chain.mempool.clone(),
+                        None => continue,
+                    };
+                    let mut mempool = mempool.lock().expect("perform locl mempool");
+                    if let Err(e) = mempool.insert(transaction) {
+                        info!("side chain {} mempool rejects transaction from {}: {:?}", sid, peer_handle.addr, e);
+                    }
                     drop(mempool);
                 },
-                Message::PassToken(token) => {
-                    info!("{:?} receive token", self.addr);
-                    self.scheduler_handler.send(scheduler::Signal::Data(token));
+                Message::PassToken(sid, token) => {
+                    //info!("{:?} receive token of side chain {}", self.addr, sid);
+                    let side_chain = match self.side_chain(sid) {
+                        Some(side_chain) => side_chain,
+                        None => continue,
+                    };
+                    if !token.is_well_formed() {
+                        self.penalize(&peer_handle, &format!("token of side chain {}", sid));
+                        continue;
+                    }
+                    // our view of the ring and its accounts may lag the contract
+                    let passer = token.node_list[token.passer()];
+                    if token.node_list != side_chain.sidenodes || !side_chain.accounts.contains_key(&passer) {
+                        info!("{:?} drop token of side chain {} from {:?}, not registered yet", self.addr, sid, passer);
+                        continue;
+                    }
+                    if !token.verify(sid, &side_chain.accounts) {
+                        self.penalize(&peer_handle, &format!("token signature of side chain {}", sid));
+                        continue;
+                    }
+                    // the creator may have missed passes of the live token
+                    if let Some(last) = self.tokens.get(&sid) {
+                        if token.is_premature(last, self.token_timeout) {
+                            info!("{:?} drop token of side chain {} created by {:?}, ring still passing at slot {}", 
+                                self.addr, sid, token.node_list[token.holder], last.slot);
+                            continue;
+                        }
+                    }
+                    // only side nodes of the ring run a scheduler
+                    if side_chain.is_proposer(&self.addr) {
+                        side_chain.scheduler_handler.send(scheduler::Signal::Data(token.clone()));
+                    }
+                    if self.tokens.get(&sid).map_or(true, |last| token.is_newer_than(last)) {
+                        self.tokens.insert(sid, token);
+                    }
                 },
-                Message::ProposeBlock(proposer_addr, block_id, header) => {
-                    if self.scale_id > 0 {
+                Message::ProposeBlock(proposal) => {
+                    let (scale_id, num_nodes) = (self.scale_id(), self.num_nodes());
+                    if scale_id > 0 {
+                        let proposer_addr = proposal.proposer;
+                        let block_id = proposal.block_id;
+                        let sid = proposal.sid;
                         //let (curr_slot, elapsed) = get_curr_slot(self.start_sec, self.start_millis, self.slot_time);
                         //info!("recv Propose_block {:?}", elapsed);
                         info!("recv Propose block {}", block_id);
-                        let hash_str = utils::hash_header_hex(&header);
-                        //info!("{:?} receive ProposeBlock: header hash: {:?}", self.addr, hash_str);
                         let local_addr = self.addr.clone();
                         
-                        if !self.decide_node(&proposer_addr) {
+                        if !self.decide_node(sid, &proposer_addr, &proposal.proposer_account, block_id) {
+                            continue;
+                        }
+                        if !proposal.verify() {
+                            self.penalize(&peer_handle, "proposal signature");
+                            continue;
+                        }
+                        // samples are seeded by our registered key, which the
+                        // proposer does not know either before discovery sees it
+                        let pub_key = match self.scale_set.lock().unwrap().pub_key(scale_id) {
+                            Some(pub_key) => pub_key,
+                            None => {
+                                warn!("{:?} skip block {}, own scale id {} not registered yet", local_addr, block_id, scale_id);
+                                continue;
+                            }
+                        };
+                        // sign at most one header per slot
+                        if !self.record_proposal(&proposal) {
                             continue;
                         }
                         info!("recv Propose block {}", block_id);
 
-                        //let (curr_slot, elapsed) = get_curr_slot(self.start_sec, self.start_millis, self.slot_time); 
-                        //let true_block_id = curr_slot+1;
-                        //if true_block_id != block_id {
-                            //warn!("wrong block id {} != {} from {:?}", block_id, true_block_id, proposer_addr);
-                            //continue;
-                        //}
                         PERFORMANCE_COUNTER.record_sign_block_update(block_id);
                         let (tx, rx) = channel::unbounded();
-                        self.proposal_by.insert((proposer_addr, block_id), tx);
-
-                        let header_cmt: BlockHeader = deserialize(
-                            &header.clone() as &[u8]
-                            ).unwrap();
+                        self.proposal_by.lock().unwrap().insert((sid, proposer_addr, block_id), tx);
 
-                        let num_symbol = BLOCK_SIZE/(BASE_SYMBOL_SIZE as u64) *((1.0/RATE) as u64);
-                        let samples_idx = get_sample_index(
-                            self.scale_id, 
-                            num_symbol, 
-                            self.num_nodes); 
+                        // votes only carry the hash, keep the header for submission
+                        let header_hash = proposal.header_hash();
+                        let header_hex = proposal.header_hex();
+                        let mut headers = self.headers.lock().unwrap();
+                        headers.insert(header_hash, (sid, block_id, header_hex.clone()));
+                        drop(headers);
+                        for vote in self.early_votes.remove(&header_hash).unwrap_or_default() {
+                            self.add_vote(vote);
+                        }
 
                         let response_msg = Message::ScaleReqChunks(
+                            sid,
                             proposer_addr, // scalenode addr 
                             block_id,
-                            self.scale_id);
+                            scale_id);
                         peer_handle.write(response_msg);
 
-                        let keyfile = self.key_file.clone();
-                        let scaleid = self.scale_id.clone();
+                        let key = self.key.clone();
+                        let scaleid = scale_id;
                         let local_aggsig = self.agg_sig.clone();
+                        let local_headers = self.headers.clone();
                         let broadcaster = self.server_control_sender.clone();
                         let db = self.block_db.clone();
 
-                        let proposer_addr_local = proposer_addr; 
-                        let block_id_local = block_id;
-                        let contract_handler = self.contract_handler.clone();
-                        let num_nodes = self.num_nodes;
                         let bin_dir = self.bin_dir.clone();
-                        let threshold = (UNDECODABLE_RATIO*(self.num_nodes as f32)).ceil() as usize ;
+                        let threshold = (UNDECODABLE_RATIO*(num_nodes as f32)).ceil() as usize ;
                         let local_contract_handler = self.contract_handler.clone();
+                        let local_proposal_by = self.proposal_by.clone();
+                        let sign_without_data = self.adversary.sign_without_data;
+                        // only the symbols assigned to us for the slot count
+                        let num_symbol = BLOCK_SIZE/(BASE_SYMBOL_SIZE as u64) *((1.0/RATE) as u64);
+                        let assigned: HashSet<u64> = scheduler::get_sample_index(
+                            &scheduler::sample_seed(sid, block_id, &header_hash.0, &pub_key), 
+                            scale_id, 
+                            num_symbol, 
+                            num_nodes, 
+                            self.redundancy).
+                            into_iter().
+                            map(|i| i as u64).
+                            collect();
+                        let deadline = self.vote_deadline_of(sid, block_id);
 
                         //info!(" {:?} start aggregating ", self.addr);
                         // timed loop
                         thread::spawn(move || {
                             let mut num_chunk = 0;
-                            let chunk_thresh = (((BLOCK_SIZE/BASE_SYMBOL_SIZE as u64) as f32 )/RATE*UNDECODABLE_RATIO) as u64 / num_nodes; 
-
-                            let mut chunk_complete = false;
+                            let mut counted = HashSet::new();
+                            // every assigned symbol has to arrive
+                            let chunk_thresh = assigned.len() as u64;
 
                             loop {
-                                match rx.recv() {
-                                    Ok(chunk_reply) => {
-                                        //info!(" {:?} get sample ", local_addr);
-                                        let mut local_db = db.lock().unwrap();
-                                        // compute id
-                                        local_db.insert_cmt_sample(block_id, &chunk_reply);
-                                        let num =  get_num_base_symbols(&chunk_reply.idx);
-                                        num_chunk += num;
-                                    },
-                                    Err(e) => info!("proposer error"),
+                                // a lazy signer votes on headers it never stored
+                                if !sign_without_data {
+                                    let timeout = deadline.saturating_duration_since(Instant::now());
+                                    match rx.recv_timeout(timeout) {
+                                        Ok(chunk_reply) => {
+                                            //info!(" {:?} get sample ", local_addr);
+                                            let mut local_db = db.lock().unwrap();
+                                            // compute id
+                                            local_db.insert_cmt_sample(sid, block_id, &chunk_reply);
+                                            let num = count_assigned_symbols(&chunk_reply.idx, &assigned, &mut counted);
+                                            if num < get_num_base_symbols(&chunk_reply.idx) {
+                                                warn!("{:?} block {} has samples outside of our assignment", local_addr, block_id);
+                                            }
+                                            num_chunk += num;
+                                        },
+                                        Err(_) => {
+                                            // withheld or slow samples, report what arrived
+                                            let mut report = AvailabilityReport {
+                                                sid: sid,
+                                                block_id: block_id,
+                                                header_hash: header_hash,
+                                                signer: scaleid,
+                                                received: num_chunk,
+                                                expected: chunk_thresh,
+                                                signature: BlsSignature::default(),
+                                            };
+                                            let (sigx, sigy) = utils::_sign_bls(hex::encode(report.message()), &key, &bin_dir);
+                                            match BlsSignature::from_dec_str(&sigx, &sigy) {
+                                                Some(s) => report.signature = s,
+                                                None => {
+                                                    error!("{:?} unable to sign availability of block {}", local_addr, block_id);
+                                                    local_headers.lock().unwrap().remove(&header_hash);
+                                                    break;
+                                                }
+                                            }
+                                            warn!("{:?} deadline of block {} passed with {}/{} samples", local_addr, block_id, num_chunk, chunk_thresh);
+                                            PERFORMANCE_COUNTER.record_availability_timeout(num_chunk == 0);
+                                            let signal = ServerSignal::ServerBroadcast(Message::Availability(report));
+                                            broadcaster.send(signal);
+                                            local_headers.lock().unwrap().remove(&header_hash);
+                                            break;
+                                        },
+                                    }
                                 }
-                                if num_chunk > chunk_thresh {
+                                if num_chunk >= chunk_thresh || sign_without_data {
                                     // vote
-                                    let header_str: String = hex::encode(&header);
-                                    
-                                    let (sigx, sigy) = utils::_sign_bls(header_str.clone(), keyfile, &bin_dir);
-                                    let sid = 0;
-                                    let response_msg = Message::MySign(
-                                        header_str.clone(), 
-                                        sid, 
-                                        block_id, 
-                                        sigx.clone(), 
-                                        sigy.clone(), 
-                                        scaleid);
-                                    let signal = ServerSignal::ServerBroadcast(response_msg);
+                                    let (sigx, sigy) = utils::_sign_bls(header_hex.clone(), &key, &bin_dir);
+                                    let signature = match BlsSignature::from_dec_str(&sigx, &sigy) {
+                                        Some(s) => s,
+                                        None => {
+                                            error!("{:?} unable to sign block {}", local_addr, block_id);
+                                            break;
+                                        }
+                                    };
+                                    let vote = Vote {
+                                        sid: sid,
+                                        block_id: block_id,
+                                        header_hash: header_hash,
+                                        signer: scaleid,
+                                        signature: signature,
+                                    };
+                                    let signal = ServerSignal::ServerBroadcast(Message::Vote(vote.clone()));
                                     broadcaster.send(signal);                                   
 
-                                    let mut aggsig = local_aggsig.lock().unwrap();
-                                    if aggsig.get(&header_str).is_none() {
-                                        aggsig.insert(header_str.clone(),  (sigx.clone(), sigy.clone(), (1 << scaleid)));
-                                        drop(aggsig);
-                                    } else {
-                                        let ( x, y, bitset) = aggsig.get(&header_str).unwrap();
-                                        let (sigx, sigy) = utils::_aggregate_sig(x.to_string(), y.to_string(), sigx, sigy, &bin_dir);
-                                        let bitset = bitset + (1 << scaleid);
-                                        aggsig.insert(
-                                            header_str.clone(),  
-                                            (sigx.clone(), sigy.clone(), bitset.clone()));
-                                        drop(aggsig);
-                                        if utils::_count_sig(bitset.clone()) >= threshold {
-                                            //info!("{:?} first loop aggreg enough sig", local_addr);
-                                            info!("1. Sufficient Signture block {}", block_id);
-                                            PERFORMANCE_COUNTER.record_sign_block_stop(block_id as usize);
-                                            PERFORMANCE_COUNTER.record_submit_block_update(block_id);
-                                            
-                                            let (answer_tx, answer_rx) = channel::bounded(1);
-                                            let handle = Handle {
-                                                message: ContractMessage::SubmitVote(header_str.clone(), U256::from(sid), U256::from(block_id), U256::from_dec_str(sigx.as_ref()).unwrap(), U256::from_dec_str(sigy.as_ref()).unwrap(), U256::from(bitset.clone())),
-                                                answer_channel: Some(answer_tx),
-                                            };
-                                            local_contract_handler.send(handle);
-                                            //let mut aggsig = self.agg_sig.lock().unwrap();
-                                            //aggsig.remove(&header);
-                                            //drop(aggsig);
-                                        }
+                                    if let Some(agg) = aggregate_vote(&local_aggsig, &vote, &bin_dir, threshold) {
+                                        //info!("{:?} first loop aggreg enough sig", local_addr);
+                                        info!("1. Sufficient Signture block {}", block_id);
+                                        local_headers.lock().unwrap().remove(&header_hash);
+                                        submit_aggregate(&local_contract_handler, header_hex, sid, block_id, agg);
                                     }
                                     break;
                                 }
                             }
-                            // after time out
-                            // vote and communicate signature depending on number of recv chunks
+                            local_proposal_by.lock().unwrap().remove(&(sid, proposer_addr, block_id));
                         });
                     }
                 },
-                Message::MySign(header , sid, bid, sigx, sigy, scale_id) => {
-                    if self.scale_id <= 0 {
+                Message::Vote(vote) => {
+                    if self.scale_id() <= 0 {
                         continue;
                     }
-                    // new
-                    let decode_header = hex::decode(&header).unwrap();
-                    let header_hash_str = utils::hash_header_hex(&decode_header);
-                    let mut sigx = sigx;
-                    let mut sigy = sigy;
-                    // send to spawned thread like ScaleReqChunksReply
-                    let mut aggsig = self.agg_sig.lock().unwrap();
-                    let threshold = (UNDECODABLE_RATIO*(self.num_nodes as f32)).ceil() as usize ;
-
-                    if aggsig.get(&header).is_none() {
-                        aggsig.insert(header.clone(),  (sigx, sigy, (1 << scale_id)));
-                    } else {
-                        let ( x, y, mut bitset) = aggsig.get(&header).unwrap().clone();
-                        if (1 << scale_id) & bitset.clone() == 0 {
-                            let (sigx_t, sigy_t) = utils::_aggregate_sig(x.to_string(), y.to_string(), sigx.clone(), sigy.clone(), &self.bin_dir);
-                            sigx = sigx_t;
-                            sigy = sigy_t;
-                            bitset = bitset + (1 << scale_id);
-                            aggsig.insert(header.clone(), (sigx.clone(), sigy.clone(), bitset.clone()));
-                        }
-                        drop(aggsig);
-                        if utils::_count_sig(bitset.clone()) >= threshold {
-                            info!("2. Sufficient Signture block {}", bid);
-                            //info!("{:?} aggreg enough sig", self.addr);
-                            PERFORMANCE_COUNTER.record_sign_block_stop(bid as usize);
-                            PERFORMANCE_COUNTER.record_submit_block_update(bid);
-                            let (answer_tx, answer_rx) = channel::bounded(1);
-                            let handle = Handle {
-                                message: ContractMessage::SubmitVote(header.clone(), U256::from(sid), U256::from(bid), U256::from_dec_str(sigx.as_ref()).unwrap(), U256::from_dec_str(sigy.as_ref()).unwrap(), U256::from(bitset.clone())),
-                                answer_channel: Some(answer_tx),
-                            };
-                            self.contract_handler.send(handle);
-                            let mut aggsig = self.agg_sig.lock().unwrap();
-                            aggsig.remove(&header);
-                            drop(aggsig);
-                        }
+                    if !self.is_valid_scale_id(vote.signer) {
+                        self.penalize(&peer_handle, &format!("scale id {}", vote.signer));
+                        continue;
+                    }
+                    if !self.is_known_scale_id(vote.signer) {
+                        info!("{:?} drop vote of scale id {}, not registered yet", self.addr, vote.signer);
+                        continue;
+                    }
+                    if !self.verify_vote(&vote) {
+                        self.penalize(&peer_handle, &format!("vote signature of scale id {}", vote.signer));
+                        continue;
+                    }
+                    if !self.record_vote(&vote) {
+                        continue;
                     }
+                    self.add_vote(vote);
                 },
-                Message::ScaleReqChunks(proposer_addr, block_id, sender_scale_id) => {
+                Message::Availability(report) => {
+                    if !self.is_valid_scale_id(report.signer) {
+                        self.penalize(&peer_handle, &format!("scale id {}", report.signer));
+                        continue;
+                    }
+                    if !self.is_known_scale_id(report.signer) {
+                        info!("{:?} drop availability of scale id {}, not registered yet", self.addr, report.signer);
+                        continue;
+                    }
+                    if !self.verify_report(&report) {
+                        self.penalize(&peer_handle, &format!("availability signature of scale id {}", report.signer));
+                        continue;
+                    }
+                    info!("scale node {} got {}/{} samples of block {} before deadline", 
+                        report.signer, report.received, report.expected, report.block_id);
+                    PERFORMANCE_COUNTER.record_availability_report(report.received == 0);
+                },
+                Message::Evidence(evidence) => {
+                    if !self.knows_signers(&evidence) {
+                        info!("{:?} drop evidence of {:?}, signers not registered yet", self.addr, evidence.slot());
+                        continue;
+                    }
+                    if !self.verify_evidence(&evidence) {
+                        self.penalize(&peer_handle, "invalid equivocation evidence");
+                        continue;
+                    }
+                    let (sid, block_id) = evidence.slot();
+                    if let Evidence::Proposal(_, _) = &evidence {
+                        self.equivocated.insert((sid, block_id));
+                    }
+                    let mut db = self.block_db.lock().unwrap();
+                    let is_new = db.insert_evidence(&evidence);
+                    drop(db);
+                    if is_new {
+                        warn!("{:?} received equivocation evidence for side chain {} block {}", self.addr, sid, block_id);
+                        PERFORMANCE_COUNTER.record_equivocation();
+                    }
+                },
+                Message::ScaleReqChunks(sid, proposer_addr, block_id, sender_scale_id) => {
                     // this client needs to prepare chunks in response to 
+                    if self.adversary.withhold_from.contains(&sender_scale_id) {
+                        info!("{:?} withhold samples of block {} from scale id {}", self.addr, block_id, sender_scale_id);
+                        continue;
+                    }
+                    let mempool = match self.side_chain(sid) {
+                        Some(side_chain) => side_chain.mempool.clone(),
+                        None => continue,
+                    };
                     let start = SystemTime::now();
-                    let mut mempool = self.mempool.lock().expect("lock mempool");
-                    let (header, symbols, idx) = mempool.get_cmt_sample(
+                    let mut mempool = mempool.lock().expect("lock mempool");
+                    let sample = mempool.get_cmt_sample(
                         block_id,
                         sender_scale_id);
-                    
                     drop(mempool);
+                    let (header, symbols, idx) = match sample {
+                        Some(sample) => sample,
+                        None => {
+                            warn!("{:?} no samples of block {} of side chain {} for scale id {}", self.addr, block_id, sid, sender_scale_id);
+                            continue;
+                        }
+                    };
                     //info!("fetched samples for scale id {} for block id {}", sender_scale_id, block_id);
                     let header_bytes = serialize(&header);
                     let hash_str = utils::hash_header_hex(&header_bytes);
@@ -501,60 +863,165 @@ impl Performer {
                         idx: idx,
                     };
                     let response_msg = Message::ScaleReqChunksReply(
+                        sid,
                         self.addr, // only side nodes sends it == propser_addr
                         block_id,
                         symbols);
                     peer_handle.write(response_msg);
                     //info!("ScaleReq Response time {:?}", start.elapsed());
                 },
-                Message::ScaleReqChunksReply(proposer_addr, block_id, symbols) => {
-                    if self.scale_id > 0 {
-                        match &self.proposal_by.get(&(proposer_addr, block_id)) {
+                Message::ScaleReqChunksReply(sid, proposer_addr, block_id, symbols) => {
+                    if self.scale_id() > 0 {
+                        if symbols.symbols.len() != symbols.idx.len() {
+                            self.penalize(&peer_handle, "samples and indices do not match");
+                            continue;
+                        }
+                        match self.proposal_by.lock().unwrap().get(&(sid, proposer_addr, block_id)) {
                             Some(sender) => {
                                 sender.send(symbols);
                             },
-                            None => error!("No proposer but receive chunk reply"),
+                            None => warn!("No pending proposal {} but receive chunk reply", block_id),
                         }
                     } 
                 },
-                Message::ScaleGetAllChunks(state) => {
-                    if self.scale_id > 0 {
+                Message::ScaleGetAllChunks(sid, state) => {
+                    if self.scale_id() > 0 {
+                        if self.adversary.refuse_retrieval {
+                            info!("{:?} refuse to return samples of block {}", self.addr, state.block_id);
+                            continue;
+                        }
                         let local_db = self.block_db.lock().unwrap();
-                        let chunk = local_db.get_chunk(state.block_id as u64);
+                        let mut chunk = local_db.get_chunk(sid, state.block_id as u64);
                         drop(local_db);
+                        if self.adversary.corrupt_retrieval {
+                            if let Some(chunk) = chunk.as_mut() {
+                                self.adversary.corrupt(chunk);
+                            }
+                        }
                         let response_msg = match chunk {
-                            Some(chunk) => Message::ScaleGetAllChunksReply((Some(chunk), state.block_id)),
-                            None => Message::ScaleGetAllChunksReply((None, state.block_id)),
+                            Some(chunk) => Message::ScaleGetAllChunksReply((sid, Some(chunk), state.block_id)),
+                            None => Message::ScaleGetAllChunksReply((sid, None, state.block_id)),
                         };
                         peer_handle.write(response_msg);
                     }
                 },
-                Message::ScaleGetAllChunksReply((chunk, block_id)) => {
+                Message::ScaleGetAllChunksReply((sid, chunk, block_id)) => {
                     //info!("{:?} recv ScaleGetAllChunksReply", self.addr);
-                    self.manager_source.send((block_id, chunk));
+                    if let Some(side_chain) = self.side_chain(sid) {
+                        side_chain.manager_source.send((block_id, chunk));
+                    }
                 },
             }
         } 
     }
 }
 
-// scale id starts at 1
-pub fn get_sample_index(scale_id: u64, num_trans: u64, num_node: u64) -> Vec<u32> {
-    let num_sample = ((num_trans as f32) / (num_node as f32)).ceil() as u64;
-    let mut sample_idx = vec![];
-    let start = (scale_id-1)*num_sample;
-    let stop = if scale_id*num_sample > num_trans {
-        num_trans
+// add a vote to the aggregate signature of its header, return the aggregate
+// once enough scale nodes have signed
+pub fn aggregate_vote(
+    agg_sig: &Mutex<HashMap<H256, (String, String, usize)>>,
+    vote: &Vote,
+    bin_dir: &str,
+    threshold: usize,
+) -> Option<(String, String, usize)> {
+    let (sigx, sigy) = vote.signature.to_dec_str();
+    let signer_bit = 1usize << vote.signer;
+    let mut aggsig = agg_sig.lock().unwrap();
+    let aggregate = match aggsig.get(&vote.header_hash) {
+        None => (sigx, sigy, signer_bit),
+        Some((x, y, bitset)) => {
+            if signer_bit & *bitset != 0 {
+                return None;
+            }
+            let (x, y) = utils::_aggregate_sig(x.to_string(), y.to_string(), sigx, sigy, bin_dir);
+            (x, y, *bitset + signer_bit)
+        }
+    };
+    if utils::_count_sig(aggregate.2) >= threshold {
+        aggsig.remove(&vote.header_hash);
+        Some(aggregate)
     } else {
-        scale_id*num_sample
+        aggsig.insert(vote.header_hash, aggregate);
+        None
+    }
+}
+
+pub fn submit_aggregate(
+    contract_handler: &Sender<Handle>,
+    header_hex: String,
+    sid: u64,
+    block_id: u64,
+    aggregate: (String, String, usize),
+) {
+    let (sigx, sigy, bitset) = aggregate;
+    let signature = match BlsSignature::from_dec_str(&sigx, &sigy) {
+        Some(s) => s,
+        None => {
+            error!("invalid aggregate signature for block {}", block_id);
+            return;
+        }
     };
-    for i in start..stop {
-        sample_idx.push(i as u32);
+    PERFORMANCE_COUNTER.record_sign_block_stop(block_id as usize);
+    PERFORMANCE_COUNTER.record_submit_block_update(block_id);
+    let message = ContractMessage::SubmitVote(
+        header_hex, 
+        U256::from(sid), 
+        U256::from(block_id), 
+        signature.x, 
+        signature.y, 
+        U256::from(bitset));
+    let contract_handler = contract_handler.clone();
+    // the vote is final only once mined, wait for it off the performer
+    let _ = thread::Builder::new()
+        .name(format!("vote-{}-{}", sid, block_id))
+        .spawn(move || {
+            let mut backoff = VOTE_BACKOFF;
+            for attempt in 1..=VOTE_ATTEMPTS {
+                let (answer_tx, answer_rx) = channel::bounded(1);
+                let handle = Handle {
+                    message: message.clone(),
+                    answer_channel: Some(answer_tx),
+                };
+                if contract_handler.send(handle).is_err() {
+                    warn!("contract handler stopped, vote of block {} dropped", block_id);
+                    return;
+                }
+                let e = match answer_rx.recv() {
+                    Ok(Answer::Success(_)) => return,
+                    Ok(Answer::Fail(e)) => e,
+                    Err(_) => ContractError::Stopped,
+                };
+                // only a vote that never reached the main chain is safe to send again
+                match e {
+                    ContractError::ConnectionFail(_) if attempt < VOTE_ATTEMPTS => {
+                        warn!("vote of block {} not sent: {}, try again in {:?}", block_id, e, backoff);
+                        thread::sleep(backoff);
+                        backoff *= 2;
+                    },
+                    e => {
+                        warn!("vote of block {} failed: {}", block_id, e);
+                        return;
+                    },
+                }
+            }
+        });
+}
+
+// base symbols of a reply that belong to the assignment and were not
+// counted before
+pub fn count_assigned_symbols(idx: &Vec<Vec<u64>>, assigned: &HashSet<u64>, counted: &mut HashSet<u64>) -> u64 {
+    match idx.first() {
+        Some(base) => base.
+            iter().
+            filter(|i| assigned.contains(i) && counted.insert(**i)).
+            count() as u64,
+        None => 0,
     }
-    sample_idx
 }
 
 pub fn get_num_base_symbols(idx: &Vec<Vec<u64>>) -> u64 {
-    assert!(idx.len() > 0);
-    idx[0].len() as u64
+    match idx.first() {
+        Some(base) => base.len() as u64,
+        None => 0,
+    }
 }
@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use super::message::Message;
+use crate::experiment::snapshot::PERFORMANCE_COUNTER;
+
+pub const NUM_PRIORITY: usize = 3;
+
+// messages of a higher class are always written first, a message already
+// being written is never interrupted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    High = 0,   // votes, proposals and requests
+    Normal = 1, // transactions and synced blocks
+    Bulk = 2,   // coded samples
+}
+
+impl Priority {
+    pub fn of(msg: &Message) -> Priority {
+        match msg {
+            Message::Ping(_) |
+            Message::Pong(_) |
+            Message::PassToken(_, _) |
+            Message::ProposeBlock(_) |
+            Message::ScaleReqChunks(_, _, _, _) |
+            Message::Vote(_) |
+            Message::Evidence(_) |
+            Message::Availability(_) |
+            Message::ScaleGetAllChunks(_, _) => Priority::High,
+            Message::SendTransaction(_, _) |
+            Message::SyncBlock(_) => Priority::Normal,
+            Message::ScaleReqChunksReply(_, _, _, _) |
+            Message::ScaleGetAllChunksReply(_) => Priority::Bulk,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QueueConfig {
+    pub capacity: [usize; NUM_PRIORITY], // bytes per priority class
+    pub rate: Option<u64>,               // bytes per second per peer, None for unlimited
+    pub burst: u64,                      // bytes
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            capacity: [8 << 20, 16 << 20, 256 << 20],
+            rate: None,
+            burst: 1 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    Full(Priority),
+    Closed,
+}
+
+struct Queues {
+    classes: [VecDeque<(Vec<u8>, &'static str)>; NUM_PRIORITY], // encoded message and its name
+    bytes: [usize; NUM_PRIORITY],
+}
+
+// bounded outgoing queue of one peer, shared by the PeerHandle and the
+// WriteContext
+pub struct OutgoingQueue {
+    inner: Mutex<Queues>,
+    capacity: [usize; NUM_PRIORITY],
+}
+
+impl OutgoingQueue {
+    pub fn new(capacity: [usize; NUM_PRIORITY]) -> OutgoingQueue {
+        OutgoingQueue {
+            inner: Mutex::new(Queues {
+                classes: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+                bytes: [0; NUM_PRIORITY],
+            }),
+            capacity: capacity,
+        }
+    }
+
+    // reject instead of growing without bound, a single message larger than
+    // the capacity is still accepted into an empty class
+    pub fn push(&self, buffer: Vec<u8>, name: &'static str, priority: Priority) -> Result<(), QueueError> {
+        let class = priority as usize;
+        let len = buffer.len();
+        let mut queues = self.inner.lock().unwrap();
+        if queues.bytes[class] > 0 && queues.bytes[class] + len > self.capacity[class] {
+            PERFORMANCE_COUNTER.record_queue_drop();
+            return Err(QueueError::Full(priority));
+        }
+        queues.bytes[class] += len;
+        queues.classes[class].push_back((buffer, name));
+        PERFORMANCE_COUNTER.record_queue_push(len);
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<(Vec<u8>, &'static str)> {
+        let mut queues = self.inner.lock().unwrap();
+        for class in 0..NUM_PRIORITY {
+            if let Some((buffer, name)) = queues.classes[class].pop_front() {
+                queues.bytes[class] -= buffer.len();
+                PERFORMANCE_COUNTER.record_queue_pop(buffer.len());
+                return Some((buffer, name));
+            }
+        }
+        None
+    }
+
+    // (number of messages, bytes) of each priority class
+    pub fn depth(&self) -> [(usize, usize); NUM_PRIORITY] {
+        let queues = self.inner.lock().unwrap();
+        let mut depth = [(0, 0); NUM_PRIORITY];
+        for class in 0..NUM_PRIORITY {
+            depth[class] = (queues.classes[class].len(), queues.bytes[class]);
+        }
+        depth
+    }
+}
+
+impl Drop for OutgoingQueue {
+    fn drop(&mut self) {
+        let queues = self.inner.lock().unwrap();
+        for class in 0..NUM_PRIORITY {
+            for (buffer, _) in queues.classes[class].iter() {
+                PERFORMANCE_COUNTER.record_queue_pop(buffer.len());
+            }
+        }
+    }
+}
+
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64, burst: u64) -> TokenBucket {
+        TokenBucket {
+            rate: rate as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last = now;
+    }
+
+    pub fn burst(&self) -> usize {
+        self.burst as usize
+    }
+
+    pub fn available(&mut self) -> usize {
+        self.refill();
+        self.tokens.max(0.0) as usize
+    }
+
+    pub fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+
+    // time until `bytes` tokens are available
+    pub fn wait_time(&mut self, bytes: usize) -> Duration {
+        self.refill();
+        let needed = bytes as f64 - self.tokens;
+        if needed <= 0.0 {
+            Duration::from_millis(0)
+        } else {
+            Duration::from_secs_f64(needed / self.rate)
+        }
+    }
+}
@@ -8,6 +8,8 @@ use std::io::{self, Read, Write};
 use super::peer::{PeerContext, PeerDirection};
 use super::MSG_BUF_SIZE;
 use super::message::{Message, ServerSignal, ConnectResult, ConnectHandle, TaskRequest};
+use super::message::{MessageLimits, DecodeError, decode_message};
+use super::queue::{QueueConfig};
 use super::peer::{self, ReadResult, WriteResult};
 use mio_extras::channel::{self, Receiver};
 use std::sync::mpsc;
@@ -15,6 +17,7 @@ use crossbeam::channel as cbchannel;
 use mio::{self, net};
 
 use log::{info, warn};
+use crate::experiment::snapshot::PERFORMANCE_COUNTER;
 
 // refer to https://sergey-melnychuk.github.io/2019/08/01/rust-mio-tcp-server/ 
 // for context
@@ -24,6 +27,18 @@ const NETWORK_TOKEN: usize = 0;
 const LOCAL_TOKEN: usize = 1;
 
 const EVENT_CAP: usize = 1024;
+// a busy server still answers control signals within
+const CONTROL_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+// misbehavior score by peer address, a peer reaching BAN_SCORE is
+// disconnected and its address refused for BAN_TIME. Peers share an ip in
+// local experiments and behind a nat, so scores are never kept by ip alone
+pub const BAN_SCORE: u32 = 100;
+pub const MALFORMED_FRAME_PENALTY: u32 = 100;
+pub const MALFORMED_MESSAGE_PENALTY: u32 = 20;
+const BAN_TIME: time::Duration = time::Duration::from_secs(600);
+// a score loses a point every SCORE_DECAY, occasional mistakes never add up
+const SCORE_DECAY: time::Duration = time::Duration::from_secs(6);
 
 pub struct Context {
     poll: mio::Poll,
@@ -34,6 +49,11 @@ pub struct Context {
     api_receiver: channel::Receiver<ServerSignal>,
     local_addr: SocketAddr,
     is_scale_node: bool,
+    limits: MessageLimits,
+    scores: HashMap<SocketAddr, (u32, time::Instant)>, // score and when it was last decayed
+    banned: HashMap<SocketAddr, time::Instant>,
+    queue_config: QueueConfig,
+    throttled: HashMap<Token, time::Instant>, // rate limited peers and when to resume
 }
 
 pub struct Handle {
@@ -58,6 +78,17 @@ impl Handle{
         self.control_tx.send(
             ServerSignal::ServerBroadcast(msg));       
     }
+
+    pub fn disconnect(&mut self, addr: SocketAddr) {
+        self.control_tx.send(ServerSignal::ServerDisconnectPeer(addr));
+    }
+
+    // None if the server did not answer
+    pub fn outgoing_peers(&mut self) -> Option<Vec<SocketAddr>> {
+        let (sender, receiver) = mpsc::channel();
+        self.control_tx.send(ServerSignal::ServerPeers(sender)).ok()?;
+        receiver.recv_timeout(CONTROL_TIMEOUT).ok()
+    }
 }
 
 impl Context {
@@ -65,6 +96,8 @@ impl Context {
         task_sender: cbchannel::Sender<TaskRequest>, 
         addr: SocketAddr, 
         is_scale_node: bool,
+        limits: MessageLimits,
+        queue_config: QueueConfig,
     ) -> (Context, Handle) {
         let (control_tx, control_rx) = channel::channel();
         let handle = Handle { 
@@ -79,6 +112,11 @@ impl Context {
             api_receiver: control_rx,
             local_addr: addr,
             is_scale_node: is_scale_node,
+            limits: limits,
+            scores: HashMap::new(),
+            banned: HashMap::new(),
+            queue_config: queue_config,
+            throttled: HashMap::new(),
         };
         (context, handle)
     }
@@ -107,11 +145,16 @@ impl Context {
         ).unwrap();
 
         // create a peer context
-        let (peer_context, handle) = PeerContext::new(socket, direction).unwrap();
+        let (peer_context, handle) = PeerContext::new(
+            socket, 
+            direction, 
+            self.limits.max_frame(), 
+            &self.queue_config
+        ).unwrap();
         let local_token = Token(self.token_counter);
         self.token_counter += 1;
         self.poll.register(
-            &peer_context.writer.queue,
+            &peer_context.writer.doorbell,
             local_token,
             Ready::readable(),
             PollOpt::edge() | mio::PollOpt::oneshot(),
@@ -120,6 +163,52 @@ impl Context {
         Ok(network_token)
     }
 
+    // drop the peer and stop polling both of its tokens
+    pub fn remove_peer(&mut self, token: Token) {
+        self.throttled.remove(&token);
+        if let Some(peer) = self.peers.remove(&token) {
+            let _ = self.poll.deregister(&peer.stream);
+            let _ = self.poll.deregister(&peer.writer.doorbell);
+        }
+    }
+
+    pub fn is_banned(&mut self, addr: &SocketAddr) -> bool {
+        match self.banned.get(addr) {
+            Some(since) if since.elapsed() < BAN_TIME => true,
+            Some(_) => {
+                self.banned.remove(addr);
+                false
+            },
+            None => false,
+        }
+    }
+
+    pub fn penalize(&mut self, addr: SocketAddr, penalty: u32) {
+        let now = time::Instant::now();
+        let (score, decayed) = self.scores.entry(addr).or_insert((0, now));
+        let points = (now.duration_since(*decayed).as_secs() / SCORE_DECAY.as_secs()) as u32;
+        if points > 0 {
+            *score = score.saturating_sub(points);
+            *decayed = now;
+        }
+        *score += penalty;
+        warn!("Peer {} misbehaves, score {}", addr, *score);
+        if *score < BAN_SCORE {
+            return;
+        }
+        warn!("Ban peer {} for {:?}", addr, BAN_TIME);
+        self.scores.remove(&addr);
+        self.banned.insert(addr, now);
+        let tokens: Vec<Token> = self.peers.
+            iter().
+            filter(|(_, peer)| peer.addr == addr).
+            map(|(token, _)| *token).
+            collect();
+        for token in tokens {
+            self.remove_peer(token);
+        }
+    }
+
     // create tcp stream for each peer
     pub fn connect(&mut self, connect_handle: ConnectHandle) -> io::Result<()> {
         let addr: SocketAddr = connect_handle.dest_addr;
@@ -164,18 +253,43 @@ impl Context {
                     }
                 }
             },
+            ServerSignal::ServerPenalize((socket, penalty)) => {
+                self.penalize(socket, penalty);
+            },
             ServerSignal::ServerStart => {
             },
             ServerSignal::ServerStop => {
             },
             ServerSignal::ServerDisconnect => {
             },
+            ServerSignal::ServerDisconnectPeer(socket) => {
+                let tokens: Vec<Token> = self.peers.
+                    iter().
+                    filter(|(_, peer)| peer.addr == socket && is_outgoing(peer)).
+                    map(|(token, _)| *token).
+                    collect();
+                for token in tokens {
+                    info!("disconnect peer {}", socket);
+                    self.remove_peer(token);
+                }
+            },
+            ServerSignal::ServerPeers(sender) => {
+                let addrs = self.peers.
+                    values().
+                    filter(|peer| is_outgoing(peer)).
+                    map(|peer| peer.addr).
+                    collect();
+                sender.send(addrs);
+            },
         }
         Ok(())
     }
 
     pub fn process_writable(&mut self, token: mio::Token) -> std::io::Result<()> {
-        let peer = self.peers.get_mut(&token).expect("writable cannot get peer"); 
+        let peer = match self.peers.get_mut(&token) {
+            Some(peer) => peer,
+            None => return Ok(()), // removed earlier in this poll round
+        };
         match peer.writer.write() {
             Ok(WriteResult::Complete) => {
                 let writer_token = mio::Token(token.0 + 1);
@@ -189,7 +303,7 @@ impl Context {
                 )?;
                 // we're interested in write queue again.
                 self.poll.reregister(
-                    &peer.writer.queue,
+                    &peer.writer.doorbell,
                     writer_token,
                     mio::Ready::readable(),
                     mio::PollOpt::edge() | mio::PollOpt::oneshot(),
@@ -197,7 +311,7 @@ impl Context {
             },
             Ok(WriteResult::EOF) => {
                 info!("Peer {} dropped connection", peer.addr);
-                self.peers.remove(&token);
+                self.remove_peer(token);
             },
             Ok(WriteResult::ChanClosed) => {
                 warn!("Peer {} outgoing queue closed", peer.addr);
@@ -208,7 +322,17 @@ impl Context {
                     mio::Ready::readable(),
                     mio::PollOpt::edge(),
                 )?;
-                self.poll.deregister(&peer.writer.queue)?;
+                self.poll.deregister(&peer.writer.doorbell)?;
+            },
+            Ok(WriteResult::Throttled(wait)) => {
+                // stop writable events until the rate limit allows more bytes
+                self.poll.reregister(
+                    &peer.stream,
+                    token,
+                    mio::Ready::readable(),
+                    mio::PollOpt::edge(),
+                )?;
+                self.throttled.insert(token, time::Instant::now() + wait);
             },
             Err(e) => {
                 if e.kind() == std::io::ErrorKind::WouldBlock {
@@ -216,7 +340,7 @@ impl Context {
                 // socket is not ready anymore, stop reading
                 } else {
                     warn!("Error writing peer {}, disconnecting: {}", peer.addr, e);
-                    self.peers.remove(&token);
+                    self.remove_peer(token);
                 }
             }
         }
@@ -224,12 +348,15 @@ impl Context {
     }
 
     pub fn process_readable(&mut self, token: mio::Token) {
-        let mut peer = self.peers.get_mut(&token).expect("get peer fail"); 
         loop {
+            let peer = match self.peers.get_mut(&token) {
+                Some(peer) => peer,
+                None => return, // removed earlier in this poll round
+            };
             match peer.reader.read() {
                 Ok(ReadResult::EOF) => {
                     info!("Peer {} dropped connection", peer.addr);
-                    self.peers.remove(&token);
+                    self.remove_peer(token);
                     //let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
                     //self.peer_list.swap_remove(index);
                     break;
@@ -240,7 +367,17 @@ impl Context {
                 },
                 Ok(ReadResult::Message(m)) => {
                     // send task request to performer
-                    let msg = bincode::deserialize(&m).unwrap();
+                    let msg = match decode_message(&m, &self.limits) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            let addr = peer.addr;
+                            warn!("Undecodable message from peer {}, disconnecting: {:?}", addr, e);
+                            self.remove_peer(token);
+                            self.penalize(addr, MALFORMED_FRAME_PENALTY);
+                            break;
+                        }
+                    };
+                    PERFORMANCE_COUNTER.record_recv(&peer.addr, msg.name(), m.len() + std::mem::size_of::<u32>());
                     let performer_task = TaskRequest{
                         peer: Some(peer.peer_handle.clone()), 
                         msg: msg,
@@ -252,9 +389,15 @@ impl Context {
                         trace!("Peer {:?} finished reading", token);
                         // socket is not ready anymore, stop reading
                         break;
+                    } else if e.kind() == std::io::ErrorKind::InvalidData {
+                        let addr = peer.addr;
+                        warn!("Invalid frame from peer {}, disconnecting: {}", addr, e);
+                        self.remove_peer(token);
+                        self.penalize(addr, MALFORMED_FRAME_PENALTY);
+                        break;
                     } else {
                         warn!("Error reading peer {}, disconnecting: {}", peer.addr, e);
-                        self.peers.remove(&token);
+                        self.remove_peer(token);
                         //let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
                         //self.peer_list.swap_remove(index);
                         break;
@@ -285,7 +428,23 @@ impl Context {
         let mut events = Events::with_capacity(EVENT_CAP);
 
         loop {
-            self.poll.poll(&mut events, None).expect("unable to poll events"); 
+            // wake up in time for the earliest rate limited peer
+            let now = time::Instant::now();
+            let timeout = self.throttled.
+                values().
+                min().
+                map(|resume| if *resume > now { *resume - now } else { time::Duration::from_millis(0) });
+            self.poll.poll(&mut events, timeout).expect("unable to poll events"); 
+            let now = time::Instant::now();
+            let resumed: Vec<Token> = self.throttled.
+                iter().
+                filter(|(_, resume)| **resume <= now).
+                map(|(token, _)| *token).
+                collect();
+            for token in resumed {
+                self.throttled.remove(&token);
+                self.process_writable(token);
+            }
             for event in &events {
                 let token = event.token();
                 match token {
@@ -293,6 +452,10 @@ impl Context {
                         loop {
                             match listener.accept() { 
                                 Ok((socket, socket_addr)) => {
+                                    if self.is_banned(&socket_addr) {
+                                        info!("Refuse banned peer {}", socket_addr);
+                                        continue;
+                                    }
                                     match self.register_peer(socket, PeerDirection::Incoming) {
                                         Ok(_) => (),
                                         Err(e) => {
@@ -346,7 +509,10 @@ impl Context {
                             },
                             LOCAL_TOKEN => {
                                 let peer_token = Token(token_id - 1);
-                                let peer = self.peers.get(&peer_token).expect("cannot get peer with local token"); 
+                                let peer = match self.peers.get(&peer_token) {
+                                    Some(peer) => peer,
+                                    None => continue,
+                                };
                                 self.poll.reregister(
                                     &peer.stream,
                                     peer_token,
@@ -363,4 +529,9 @@ impl Context {
     }
 }
 
-
+fn is_outgoing(peer: &PeerContext) -> bool {
+    match peer.direction {
+        PeerDirection::Outgoing => true,
+        PeerDirection::Incoming => false,
+    }
+}
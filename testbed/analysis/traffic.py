@@ -0,0 +1,78 @@
+#!/usr/bin/env python
+
+import matplotlib.pyplot as plt
+import json
+import sys
+import os
+
+# message types by protocol phase
+dispersal = ['ProposeBlock', 'ScaleReqChunks', 'ScaleReqChunksReply', 'Vote']
+retrieval = ['ScaleGetAllChunks', 'ScaleGetAllChunksReply']
+
+if len(sys.argv) <3:
+    print("need traffic dir, output dir")
+    sys.exit(0)
+
+traffic_dir = sys.argv[1]
+directory = sys.argv[2]
+
+nodes_f = "../nodes.txt"
+nodes = {}
+with open(nodes_f) as f:
+    for line in f:
+        line = line.replace('\n', '')
+        tokens = line.split(',')
+        name = tokens[0]
+        scale_id = int(tokens[5])
+        nodes[name] = scale_id
+
+# snapshot of every node taken at the end of the experiment
+snapshots = {}
+for k,v in nodes.items():
+    fname = traffic_dir + "/" + k + ".json"
+    if not os.path.exists(fname):
+        print("missing snapshot", fname)
+        continue
+    with open(fname) as f:
+        snapshots[k] = json.load(f)
+
+# sum sent bytes, so that each message is counted once
+by_type = {}
+for k, snap in snapshots.items():
+    for msg_type, t in snap['traffic_by_type'].items():
+        if msg_type not in by_type:
+            by_type[msg_type] = [0, 0]
+        by_type[msg_type][0] += t['sent_msgs']
+        by_type[msg_type][1] += t['sent_bytes']
+
+print("type", "msgs", "bytes")
+for msg_type, (msgs, size) in sorted(by_type.items()):
+    print(msg_type, msgs, size)
+
+# blocks confirmed on the trusted chain
+num_block = 0
+for k, snap in snapshots.items():
+    if nodes[k] > 0:
+        num_block = max(num_block, snap['chain_depth'])
+
+dispersal_bytes = sum([by_type[t][1] for t in dispersal if t in by_type])
+retrieval_bytes = sum([by_type[t][1] for t in retrieval if t in by_type])
+print("blocks", num_block)
+if num_block > 0:
+    print("dispersal bytes per block", dispersal_bytes/float(num_block))
+    print("retrieval bytes per block", retrieval_bytes/float(num_block))
+
+for k, snap in sorted(snapshots.items()):
+    print(k, "sent", snap['sent_bytes'], "recv", snap['recv_bytes'])
+
+if not os.path.exists(directory):
+    os.makedirs(directory)
+
+names = sorted(by_type.keys())
+fig = plt.figure()
+plt.bar(range(len(names)), [by_type[n][1]/1000000.0 for n in names])
+plt.xticks(range(len(names)), names, rotation=45, ha='right', fontsize=8)
+plt.ylabel('MB')
+plt.title('bytes sent by message type')
+plt.tight_layout()
+plt.savefig(directory + "/traffic")
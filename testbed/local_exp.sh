@@ -33,6 +33,30 @@ function start_node() {
 	account_num=$( expr $1 % $6 + 1 )
 	account="--account accounts/account"$account_num
 
+	# emulate WAN links, e.g. RATE_LIMIT=12500000 for 100Mbps per peer
+	rate_opt=""
+	if [ -n "$RATE_LIMIT" ]; then
+		rate_opt="--rate_limit $RATE_LIMIT"
+	fi
+
+	# misbehaving nodes, e.g. ADVERSARY_3=withhold_from=1:2,equivocate
+	adv_opt=""
+	adv_var="ADVERSARY_$1"
+	if [ -n "${!adv_var}" ]; then
+		adv_opt="--adversary ${!adv_var}"
+	fi
+
+	# SCHEDULING=token passes a token around the side nodes instead of
+	# relying on synchronized clocks
+	sched_opt=""
+	if [ -n "$SCHEDULING" ]; then
+		sched_opt="--scheduling $SCHEDULING"
+	fi
+	# SLOT_CLOCK=contract or main_chain counts slots in trusted chain heights
+	if [ -n "$SLOT_CLOCK" ]; then
+		sched_opt="$sched_opt --slot_clock $SLOT_CLOCK"
+	fi
+
 	known_peers=""	
 	for(( a=1 ; a<=$2 ; a++ )); do
 		if [ $a -ne $1 ] ; then
@@ -43,7 +67,7 @@ function start_node() {
 	done
 	
 	echo "RUST_LOG=info $bin -i $peer_addr ${known_peers} $3 --api_addr $api_addr $account --key $keyfile --scale_id $4 -n $5 -l ../src/LDPC_codes -j "./scripts/abi.json" -b "../go-bls" --contract_address ${contract_address} --rpc_url ${rpc_url} --start_time ${start_time}"
-	RUST_LOG=info $bin -i $peer_addr ${known_peers} $3 --api_addr "$api_addr" $account -t $7 --key "$keyfile" --scale_id $4 -n $5 -l "../src/LDPC_codes" -j "./scripts/abi.json" -b "../go-bls" -f ${contract_address} -u ${rpc_url} --start_time ${start_time}&
+	RUST_LOG=info $bin -i $peer_addr ${known_peers} $3 --api_addr "$api_addr" $account -t $7 --key "$keyfile" --scale_id $4 -n $5 -l "../src/LDPC_codes" -j "./scripts/abi.json" -b "../go-bls" -f ${contract_address} -u ${rpc_url} --start_time ${start_time} ${rate_opt} ${adv_opt} ${sched_opt}&
 	pid="$!"
 	pids="$pids $pid"
 }
@@ -169,7 +193,7 @@ function add_scale_nodes
 		new_account="accounts/account$i"
 		echo $new_address
 		keyfile="keyfile/node$i"
-		ip="127.0.0.1"
+		ip="127.0.0.1:$(expr 40000 + $i)"
 		$bin addScaleNode --contract_addr ${contract_address} --node_url ${rpc_url} --account ${contract_master} --keyfile ${keyfile} --ip_addr ${ip} --new_account ${new_account}
 	done
 	echo "Curr scale nodes"
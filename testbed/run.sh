@@ -127,6 +127,12 @@ function start_ping_single
 	curl "http://$3:$4/server/ping"
 }
 
+# save the traffic counters of a node before it stops
+function get_traffic_single
+{
+	curl -s "http://$3:$4/telematics/snapshot" > "logData/traffic/$1.json"
+}
+
 function query_side_api
 {
 	local nodes=$(cat nodes.txt)
@@ -404,13 +410,15 @@ function config_contract
 		local name
 		local id
 		local ip
+		local p2p_port
 		local scale_id
-		IFS=',' read -r name id ip _ _ scale_id <<< "$node"
+		IFS=',' read -r name id ip p2p_port _ scale_id <<< "$node"
 		if [ ${scale_id} -ne 0 ]; then
 			echo "$name ${scale_id}"
 			account="accounts/account${scale_id}"
 			key="keyfile/node${scale_id}"
-			${bin} addScaleNode --account ${account} --keyfile ${key} --ip_addr ${ip}
+			# registered with port so that nodes can discover each other
+			${bin} addScaleNode --account ${account} --keyfile ${key} --ip_addr ${ip}:${p2p_port}
 		fi
 	done
 
@@ -441,7 +449,7 @@ function add_scale_nodes
 		new_account="accounts/account$i"
 		echo $new_address
 		keyfile="keyfile/node$i"
-		ip="127.0.0.1"
+		ip="127.0.0.1:$(expr 40000 + $i)"
 		$bin addScaleNode --contract_addr ${contract_address} --node_url ${rpc_url} --account ${contract_master} --keyfile ${keyfile} --ip_addr ${ip} --new_account ${new_account}
 	done
 	echo "Curr scale nodes"
@@ -473,6 +481,9 @@ function analyze
 	mv logData $outdir
 	cd ./analysis 
 	python plot.py ../${outdir}/logData ../$outdir
+	if [ -d ../${outdir}/logData/traffic ]; then
+		python traffic.py ../${outdir}/logData/traffic ../$outdir
+	fi
 	cd ../
 }
 
@@ -492,6 +503,7 @@ case $1 in
 				start-ping
 				start-trans
 				read-log name
+				get-traffic
 				config_contract
 
 		Workflow:
@@ -502,6 +514,7 @@ case $1 in
 			reset-chain
 			run-exp
 			show
+			get-traffic
 			stop-exp
 			stop-instances	
 			analyze output_name
@@ -532,6 +545,9 @@ case $1 in
 		query_api start_ping ;;
 	read-log)
 		read_log $2 ;;
+	get-traffic)
+		mkdir -p logData/traffic
+		query_api get_traffic ;;
 	show)
 		show ;;
 	plot)